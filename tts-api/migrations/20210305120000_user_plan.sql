@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN plan INT NOT NULL DEFAULT 0;
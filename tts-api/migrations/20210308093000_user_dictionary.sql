@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE user_dictionary
+(
+    users_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    surface TEXT NOT NULL,
+    reading TEXT NOT NULL,
+    CONSTRAINT user_dictionary_pk PRIMARY KEY (users_id, surface)
+);
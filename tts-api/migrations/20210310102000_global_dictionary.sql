@@ -0,0 +1,19 @@
+-- Add migration script here
+CREATE TABLE global_dictionary_versions
+(
+    version SERIAL NOT NULL,
+    entry_count INT NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT global_dictionary_versions_pk PRIMARY KEY (version)
+);
+
+CREATE TABLE global_dictionary_entries
+(
+    version INT NOT NULL REFERENCES global_dictionary_versions(version) ON DELETE CASCADE,
+    surface TEXT NOT NULL,
+    reading TEXT NOT NULL,
+    accent_type INT NOT NULL,
+    part_of_speech TEXT NOT NULL,
+    CONSTRAINT global_dictionary_entries_pk PRIMARY KEY (version, surface)
+);
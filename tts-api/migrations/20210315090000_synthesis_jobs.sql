@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE synthesis_jobs
+(
+    id BIGSERIAL NOT NULL,
+    users_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status INT NOT NULL DEFAULT 0,
+    request TEXT NOT NULL,
+    segments_done INT NOT NULL DEFAULT 0,
+    segments_total INT NOT NULL DEFAULT 0,
+    attempts INT NOT NULL DEFAULT 0,
+    audio BYTEA,
+    content_type TEXT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    started_at TIMESTAMPTZ,
+    heartbeat_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    CONSTRAINT synthesis_jobs_pk PRIMARY KEY (id)
+);
+
+CREATE INDEX synthesis_jobs_pending ON synthesis_jobs (id) WHERE status IN (0, 1);
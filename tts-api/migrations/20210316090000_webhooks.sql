@@ -0,0 +1,41 @@
+-- Add migration script here
+ALTER TABLE synthesis_jobs ADD COLUMN callback_url TEXT;
+
+CREATE TABLE webhook_secrets
+(
+    users_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT webhook_secrets_pk PRIMARY KEY (users_id)
+);
+
+CREATE TABLE webhook_deliveries
+(
+    id BIGSERIAL NOT NULL,
+    jobs_id BIGINT NOT NULL REFERENCES synthesis_jobs(id) ON DELETE CASCADE,
+    users_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status INT NOT NULL DEFAULT 0,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    response_status INT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ,
+    CONSTRAINT webhook_deliveries_pk PRIMARY KEY (id)
+);
+
+CREATE INDEX webhook_deliveries_pending ON webhook_deliveries (next_attempt_at) WHERE status = 0;
+CREATE INDEX webhook_deliveries_jobs_id ON webhook_deliveries (jobs_id);
+
+CREATE TABLE webhook_attempts
+(
+    deliveries_id BIGINT NOT NULL REFERENCES webhook_deliveries(id) ON DELETE CASCADE,
+    attempt INT NOT NULL,
+    response_status INT,
+    error TEXT,
+    duration_ms INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT webhook_attempts_pk PRIMARY KEY (deliveries_id, attempt)
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE stored_audio
+(
+    id BIGSERIAL NOT NULL,
+    users_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    storage_key TEXT NOT NULL UNIQUE,
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    duration DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ,
+    CONSTRAINT stored_audio_pk PRIMARY KEY (id)
+);
+
+CREATE INDEX stored_audio_users_id ON stored_audio (users_id);
+CREATE INDEX stored_audio_expires_at ON stored_audio (expires_at);
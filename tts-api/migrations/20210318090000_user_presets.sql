@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE user_presets
+(
+    users_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    settings TEXT NOT NULL,
+    is_default BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT user_presets_pk PRIMARY KEY (users_id, name)
+);
+
+CREATE UNIQUE INDEX user_presets_default ON user_presets (users_id) WHERE is_default;
@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE admin_audit_log
+(
+    id BIGSERIAL NOT NULL,
+    admin_id BIGINT NOT NULL,
+    action TEXT NOT NULL,
+    target_id BIGINT,
+    details TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT admin_audit_log_pk PRIMARY KEY (id)
+);
+
+CREATE INDEX admin_audit_log_admin_id ON admin_audit_log (admin_id);
+CREATE INDEX admin_audit_log_target_id ON admin_audit_log (target_id);
@@ -0,0 +1,74 @@
+pub mod routes;
+
+pub use self::routes::init;
+
+use crate::{
+    auth::token::Token,
+    error::AppError,
+    models::{
+        audit::AuditEntry,
+        users::{User, UserChanges},
+    },
+};
+use serde_json::json;
+use sqlx::PgPool;
+
+/// Applies the set `changes` to a user and audits them. Fails with
+/// `RowNotFound` for unknown users.
+pub async fn update_user(
+    pool: &PgPool,
+    admin_id: i64,
+    user_id: i64,
+    changes: &UserChanges,
+    reason: Option<&str>,
+) -> Result<User, AppError> {
+    let mut tx = pool.begin().await?;
+    let before = User::lock(&mut tx, user_id).await?;
+    let after = User::update(&mut tx, user_id, changes).await?;
+    let details = json!({
+        "changes": changes,
+        "before": before,
+        "reason": reason,
+    });
+    AuditEntry::record(&mut tx, admin_id, "update_user", Some(user_id), &details).await?;
+    tx.commit().await?;
+    Ok(after)
+}
+
+/// Resets the characters a user has used and audits it. Fails with
+/// `RowNotFound` for unknown users.
+pub async fn reset_usage(
+    pool: &PgPool,
+    admin_id: i64,
+    user_id: i64,
+    reason: Option<&str>,
+) -> Result<User, AppError> {
+    let mut tx = pool.begin().await?;
+    let before = User::lock(&mut tx, user_id).await?;
+    let after = User::reset_count(&mut tx, user_id).await?;
+    let details = json!({
+        "character_count": before.character_count,
+        "reason": reason,
+    });
+    AuditEntry::record(&mut tx, admin_id, "reset_usage", Some(user_id), &details).await?;
+    tx.commit().await?;
+    Ok(after)
+}
+
+/// Revokes a user's token and audits it. Returns whether the user had a
+/// token; nothing is recorded when they did not.
+pub async fn revoke_token(
+    pool: &PgPool,
+    admin_id: i64,
+    user_id: i64,
+    reason: Option<&str>,
+) -> Result<bool, AppError> {
+    let mut tx = pool.begin().await?;
+    if !Token::revoke(&mut tx, user_id).await? {
+        return Ok(false);
+    }
+    let details = json!({ "reason": reason });
+    AuditEntry::record(&mut tx, admin_id, "revoke_token", Some(user_id), &details).await?;
+    tx.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,234 @@
+use crate::{
+    admin,
+    auth::{authenticate_admin, Credentials},
+    config::{Config, PlanLimits},
+    error::AppError,
+    models::{
+        audio::StoredAudio,
+        audit::AuditEntry,
+        users::{User, UserChanges, UserFilter},
+    },
+};
+use actix_web::{get, patch, post, web, HttpResponse};
+use sqlx::PgPool;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Deserialize, Debug)]
+struct Page {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl Page {
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AuditQuery {
+    admin: Option<i64>,
+    target: Option<i64>,
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct UpdateUserBody {
+    #[serde(flatten)]
+    changes: UserChanges,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ReasonBody {
+    reason: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct UserUsage<'a> {
+    user: User,
+    limits: &'a PlanLimits,
+    remaining_characters: i64,
+    storage_bytes: i64,
+}
+
+/// Maps a missing user to 404, passing other errors through.
+fn not_found(e: AppError) -> Result<HttpResponse, AppError> {
+    match e {
+        AppError::DatabaseError(sqlx::Error::RowNotFound) => {
+            Ok(HttpResponse::NotFound().body("User not found"))
+        }
+        e => Err(e),
+    }
+}
+
+#[get("/admin/users")]
+async fn list_users(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+    filter: web::Query<UserFilter>,
+    page: web::Query<Page>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    if let Err(e) = authenticate_admin(pool, query.id, &query.token).await {
+        return Ok(e);
+    }
+
+    if let Some(prefix) = &filter.id_prefix {
+        if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(HttpResponse::BadRequest().body("id_prefix must be a string of digits."));
+        }
+    }
+
+    let users = User::search(pool, &filter, page.limit(), page.offset()).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[get("/admin/users/{user_id}")]
+async fn get_user(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    user_id: web::Path<i64>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    if let Err(e) = authenticate_admin(pool, query.id, &query.token).await {
+        return Ok(e);
+    }
+
+    let user = match User::get(pool, user_id.into_inner()).await {
+        Ok(user) => user,
+        Err(e) => return not_found(e),
+    };
+    let storage_bytes = StoredAudio::usage(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(UserUsage {
+        limits: config.plans.limits(user.plan()),
+        remaining_characters: (user.character_limit - user.character_count).max(0),
+        storage_bytes,
+        user,
+    }))
+}
+
+/// Changes a user's character limit, plan, role or account status.
+/// Suspended and banned users are refused by authentication until they are
+/// made active again.
+#[patch("/admin/users/{user_id}")]
+async fn update_user(
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i64>,
+    query: web::Query<Credentials>,
+    body: web::Json<UpdateUserBody>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let admin = match authenticate_admin(pool, query.id, &query.token).await {
+        Ok(admin) => admin,
+        Err(e) => return Ok(e),
+    };
+    let user_id = user_id.into_inner();
+    let body = body.into_inner();
+
+    if let Some(limit) = body.changes.character_limit {
+        if limit < 0 {
+            return Ok(HttpResponse::BadRequest().body("character_limit must not be negative."));
+        }
+    }
+    if user_id == admin.id && (body.changes.role.is_some() || body.changes.status.is_some()) {
+        return Ok(HttpResponse::BadRequest()
+            .body("Administrators cannot change their own role or account status."));
+    }
+
+    let reason = body.reason.as_deref();
+    let after = match admin::update_user(pool, admin.id, user_id, &body.changes, reason).await {
+        Ok(user) => user,
+        Err(e) => return not_found(e),
+    };
+
+    info!("Admin {} updated user {}", admin.id, user_id);
+    Ok(HttpResponse::Ok().json(after))
+}
+
+/// Resets the characters a user has used against their limit.
+#[post("/admin/users/{user_id}/reset")]
+async fn reset_usage(
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i64>,
+    query: web::Query<Credentials>,
+    body: Option<web::Json<ReasonBody>>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let admin = match authenticate_admin(pool, query.id, &query.token).await {
+        Ok(admin) => admin,
+        Err(e) => return Ok(e),
+    };
+    let user_id = user_id.into_inner();
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+
+    let after = match admin::reset_usage(pool, admin.id, user_id, body.reason.as_deref()).await {
+        Ok(user) => user,
+        Err(e) => return not_found(e),
+    };
+
+    info!("Admin {} reset usage of user {}", admin.id, user_id);
+    Ok(HttpResponse::Ok().json(after))
+}
+
+/// Invalidates a user's token. The user gets a new one by logging in again.
+#[post("/admin/users/{user_id}/revoke")]
+async fn revoke_token(
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i64>,
+    query: web::Query<Credentials>,
+    body: Option<web::Json<ReasonBody>>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let admin = match authenticate_admin(pool, query.id, &query.token).await {
+        Ok(admin) => admin,
+        Err(e) => return Ok(e),
+    };
+    let user_id = user_id.into_inner();
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+
+    if !admin::revoke_token(pool, admin.id, user_id, body.reason.as_deref()).await? {
+        return Ok(HttpResponse::NotFound().body("User not found"));
+    }
+
+    info!("Admin {} revoked the token of user {}", admin.id, user_id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/admin/audit")]
+async fn list_audit(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+    audit: web::Query<AuditQuery>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    if let Err(e) = authenticate_admin(pool, query.id, &query.token).await {
+        return Ok(e);
+    }
+
+    let limit = audit
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let entries = AuditEntry::list(pool, audit.admin, audit.target, audit.before, limit).await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_users);
+    cfg.service(get_user);
+    cfg.service(update_user);
+    cfg.service(reset_usage);
+    cfg.service(revoke_token);
+    cfg.service(list_audit);
+}
@@ -0,0 +1,55 @@
+pub mod tar;
+pub mod zip;
+
+use self::{tar::TarWriter, zip::ZipWriter};
+use chrono::Utc;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+impl Default for ArchiveFormat {
+    fn default() -> ArchiveFormat {
+        ArchiveFormat::Zip
+    }
+}
+
+impl ArchiveFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+        }
+    }
+
+    /// Packs `(name, data)` entries in order, dated now.
+    pub fn pack(self, entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+        let now = Utc::now();
+        match self {
+            ArchiveFormat::Zip => {
+                let mut writer = ZipWriter::new(now);
+                for (name, data) in entries {
+                    writer.add(name, data);
+                }
+                writer.finish()
+            }
+            ArchiveFormat::Tar => {
+                let mut writer = TarWriter::new(now);
+                for (name, data) in entries {
+                    writer.add(name, data);
+                }
+                writer.finish()
+            }
+        }
+    }
+}
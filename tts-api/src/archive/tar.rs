@@ -0,0 +1,111 @@
+//! A minimal ustar writer.
+
+use chrono::{DateTime, Utc};
+
+const BLOCK_SIZE: usize = 512;
+const NAME_SIZE: usize = 100;
+
+/// Writes `value` as zero padded octal filling `field` but its last byte,
+/// which is left NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(digits.as_bytes());
+}
+
+pub struct TarWriter {
+    output: Vec<u8>,
+    modified: i64,
+}
+
+impl TarWriter {
+    /// Creates a writer stamping all entries with `modified`.
+    pub fn new(modified: DateTime<Utc>) -> TarWriter {
+        TarWriter {
+            output: Vec::new(),
+            modified: modified.timestamp().max(0),
+        }
+    }
+
+    /// Adds a regular file. Names must be at most 100 bytes long.
+    pub fn add(&mut self, name: &str, data: &[u8]) {
+        assert!(name.len() <= NAME_SIZE, "tar entry name too long: {}", name);
+        let mut header = [0u8; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], self.modified as u64);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // The checksum is computed with its own field filled with spaces.
+        header[148..156].copy_from_slice(b"        ");
+        let checksum = header.iter().map(|&byte| byte as u64).sum::<u64>();
+        octal(&mut header[148..155], checksum);
+        header[155] = b' ';
+
+        self.output.extend_from_slice(&header);
+        self.output.extend_from_slice(data);
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.output.resize(self.output.len() + padding, 0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.output.resize(self.output.len() + BLOCK_SIZE * 2, 0);
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn octal_fields_end_with_nul() {
+        let mut field = [0xffu8; 8];
+        octal(&mut field, 0o644);
+        assert_eq!(&field, b"0000644\0");
+    }
+
+    #[test]
+    fn round_trips_through_the_tar_crate() {
+        let entries: Vec<(&str, Vec<u8>)> = vec![
+            ("a.wav", vec![1; 512]),
+            ("b.wav", vec![2; 513]),
+            ("音声.mp3", b"ID3".to_vec()),
+            ("empty.ogg", Vec::new()),
+        ];
+        let modified = Utc.ymd(2021, 3, 4).and_hms(5, 6, 7);
+        let mut writer = TarWriter::new(modified);
+        for (name, data) in &entries {
+            writer.add(name, data);
+        }
+        let bytes = writer.finish();
+        assert_eq!(bytes.len() % BLOCK_SIZE, 0);
+        assert!(bytes[bytes.len() - 2 * BLOCK_SIZE..]
+            .iter()
+            .all(|&byte| byte == 0));
+
+        let mut archive = ::tar::Archive::new(Cursor::new(bytes));
+        let mut count = 0;
+        for (entry, (name, data)) in archive.entries().unwrap().zip(&entries) {
+            let mut entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(header.entry_type(), ::tar::EntryType::Regular);
+            assert_eq!(header.mode().unwrap(), 0o644);
+            assert_eq!(header.mtime().unwrap(), modified.timestamp() as u64);
+            assert_eq!(header.size().unwrap(), data.len() as u64);
+            assert_eq!(entry.path().unwrap().to_str(), Some(*name));
+            let mut read = Vec::new();
+            entry.read_to_end(&mut read).unwrap();
+            assert_eq!(&read, data);
+            count += 1;
+        }
+        assert_eq!(count, entries.len());
+    }
+}
@@ -0,0 +1,183 @@
+//! A minimal ZIP writer storing entries uncompressed. Encoded audio barely
+//! compresses, so deflate would cost time for little gain.
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Version 2.0, the first supporting directories and stored entries.
+const VERSION: u16 = 20;
+/// General purpose flag marking names as UTF-8.
+const UTF8_NAMES: u16 = 0x0800;
+const STORED: u16 = 0;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |mut crc, &byte| {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+/// Returns the MS-DOS time and date fields, which have two second precision
+/// and start in 1980.
+fn dos_time(time: DateTime<Utc>) -> (u16, u16) {
+    let year = (time.year() - 1980).clamp(0, 127) as u16;
+    let date = (year << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    let time =
+        ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    (time, date)
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+pub struct ZipWriter {
+    output: Vec<u8>,
+    entries: Vec<Entry>,
+    time: u16,
+    date: u16,
+}
+
+impl ZipWriter {
+    /// Creates a writer stamping all entries with `modified`.
+    pub fn new(modified: DateTime<Utc>) -> ZipWriter {
+        let (time, date) = dos_time(modified);
+        ZipWriter {
+            output: Vec::new(),
+            entries: Vec::new(),
+            time,
+            date,
+        }
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) {
+        let entry = Entry {
+            name: name.to_string(),
+            crc: crc32(data),
+            size: data.len() as u32,
+            offset: self.output.len() as u32,
+        };
+        let out = &mut self.output;
+        out.extend_from_slice(&LOCAL_FILE_HEADER.to_le_bytes());
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+        out.extend_from_slice(&STORED.to_le_bytes());
+        out.extend_from_slice(&self.time.to_le_bytes());
+        out.extend_from_slice(&self.date.to_le_bytes());
+        out.extend_from_slice(&entry.crc.to_le_bytes());
+        out.extend_from_slice(&entry.size.to_le_bytes());
+        out.extend_from_slice(&entry.size.to_le_bytes());
+        out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(data);
+        self.entries.push(entry);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let start = self.output.len();
+        let out = &mut self.output;
+        for entry in &self.entries {
+            out.extend_from_slice(&CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+            out.extend_from_slice(&VERSION.to_le_bytes());
+            out.extend_from_slice(&VERSION.to_le_bytes());
+            out.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+            out.extend_from_slice(&STORED.to_le_bytes());
+            out.extend_from_slice(&self.time.to_le_bytes());
+            out.extend_from_slice(&self.date.to_le_bytes());
+            out.extend_from_slice(&entry.crc.to_le_bytes());
+            out.extend_from_slice(&entry.size.to_le_bytes());
+            out.extend_from_slice(&entry.size.to_le_bytes());
+            out.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            // Extra field, comment, disk number, internal and external
+            // attributes.
+            out.extend_from_slice(&[0; 12]);
+            out.extend_from_slice(&entry.offset.to_le_bytes());
+            out.extend_from_slice(entry.name.as_bytes());
+        }
+        let size = out.len() - start;
+
+        let count = self.entries.len() as u16;
+        out.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&(size as u32).to_le_bytes());
+        out.extend_from_slice(&(start as u32).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn dos_time_starts_in_1980() {
+        let time = Utc.ymd(2021, 3, 4).and_hms(5, 6, 9);
+        assert_eq!(
+            dos_time(time),
+            ((5 << 11) | (6 << 5) | 4, (41 << 9) | (3 << 5) | 4)
+        );
+        assert_eq!(
+            dos_time(Utc.ymd(1970, 1, 1).and_hms(0, 0, 0)).1,
+            (1 << 5) | 1
+        );
+    }
+
+    #[test]
+    fn round_trips_through_the_zip_crate() {
+        let entries: Vec<(&str, Vec<u8>)> = vec![
+            ("a.wav", (0..=255).cycle().take(1000).collect()),
+            ("音声.mp3", b"ID3".to_vec()),
+            ("empty.ogg", Vec::new()),
+        ];
+        let mut writer = ZipWriter::new(Utc.ymd(2021, 3, 4).and_hms(5, 6, 8));
+        for (name, data) in &entries {
+            writer.add(name, data);
+        }
+        let bytes = writer.finish();
+
+        let mut archive = ::zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), entries.len());
+        for (index, (name, data)) in entries.iter().enumerate() {
+            let mut file = archive.by_index(index).unwrap();
+            assert_eq!(file.name(), *name);
+            assert_eq!(file.compression(), ::zip::CompressionMethod::Stored);
+            assert_eq!(file.crc32(), crc32(data));
+            let modified = file.last_modified();
+            assert_eq!(
+                (modified.year(), modified.month(), modified.day()),
+                (2021, 3, 4)
+            );
+            assert_eq!(
+                (modified.hour(), modified.minute(), modified.second()),
+                (5, 6, 8)
+            );
+            let mut read = Vec::new();
+            file.read_to_end(&mut read).unwrap();
+            assert_eq!(&read, data);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! A FLAC encoder using fixed linear predictors and Rice-coded residuals.
+
+use super::{
+    format::{self, BitDepth, OutputFormat, PcmFormat},
+    metadata::{self, Metadata},
+};
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+const MAX_RICE_PARAMETER: u32 = 14;
+const MAX_SAMPLE_RATE: u32 = 655_350;
+
+const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+
+/// Resolves the FLAC stream format. FLAC only stores integer samples.
+pub fn resolve(
+    output: &OutputFormat,
+    sample_rate: u32,
+    channels: u16,
+) -> Result<PcmFormat, String> {
+    let format = output.resolve(sample_rate, channels);
+    if format.encoding.law().is_some() {
+        return Err("FLAC output cannot use G.711 encoding.".to_string());
+    }
+    if format.bit_depth == BitDepth::Float32 {
+        return Err("FLAC output must be 16 or 24 bits.".to_string());
+    }
+    if format.sample_rate > MAX_SAMPLE_RATE {
+        return Err(format!("Unsupported sample rate: {}", format.sample_rate));
+    }
+    Ok(format)
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) {
+        for shift in (0..bits).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> shift) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.bytes.push(self.buffer as u8);
+                self.buffer = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1u64 << bits) - 1), bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        for _ in 0..zeros {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Encodes `value` the way FLAC frame headers store frame numbers: as an
+/// extended UTF-8 sequence.
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+    let mut continuation = Vec::new();
+    let mut rest = value;
+    let mut limit = 0x40;
+    while rest >= limit {
+        continuation.push(0x80 | (rest & 0x3f));
+        rest >>= 6;
+        limit >>= 1;
+    }
+    let count = continuation.len() as u32 + 1;
+    let prefix = (0xff00u64 >> count) & 0xff;
+    writer.write(prefix | rest, 8);
+    for byte in continuation.into_iter().rev() {
+        writer.write(byte, 8);
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |k: usize| samples[i - k];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// A Rice coding plan for a residual: partition order, per-partition
+/// parameters and the total size in bits.
+struct RicePlan {
+    order: u32,
+    parameters: Vec<u32>,
+    bits: u64,
+}
+
+fn rice_bits(residual: &[i64], parameter: u32) -> u64 {
+    residual
+        .iter()
+        .map(|&value| (zigzag(value) >> parameter) + 1 + parameter as u64)
+        .sum()
+}
+
+/// Picks the cheapest parameter near the estimate from the mean magnitude.
+fn best_parameter(residual: &[i64]) -> (u32, u64) {
+    let mean =
+        residual.iter().map(|&value| zigzag(value)).sum::<u64>() / residual.len().max(1) as u64;
+    let estimate = (64 - mean.leading_zeros()).min(MAX_RICE_PARAMETER);
+    (estimate.saturating_sub(2)..=(estimate + 1).min(MAX_RICE_PARAMETER))
+        .map(|parameter| (parameter, rice_bits(residual, parameter)))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+fn plan_rice(residual: &[i64], block_size: usize, predictor_order: usize) -> RicePlan {
+    let mut best: Option<RicePlan> = None;
+    for order in 0..=MAX_PARTITION_ORDER {
+        let partition = block_size >> order;
+        if block_size % (1 << order) != 0 || partition <= predictor_order {
+            break;
+        }
+        let mut parameters = Vec::new();
+        let mut bits = 2 + 4;
+        let mut start = 0;
+        for index in 0..1usize << order {
+            let length = if index == 0 {
+                partition - predictor_order
+            } else {
+                partition
+            };
+            let (parameter, partition_bits) = best_parameter(&residual[start..start + length]);
+            parameters.push(parameter);
+            bits += 4 + partition_bits;
+            start += length;
+        }
+        if best.as_ref().map_or(true, |best| bits < best.bits) {
+            best = Some(RicePlan {
+                order,
+                parameters,
+                bits,
+            });
+        }
+    }
+    best.expect("partition order 0 is always valid")
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32) {
+    let block_size = samples.len();
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        writer.write(0, 8);
+        writer.write_signed(samples[0], bits);
+        return;
+    }
+
+    let verbatim_bits = (block_size as u64) * bits as u64;
+    let best = (0..=MAX_FIXED_ORDER.min(block_size - 1))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let plan = plan_rice(&residual, block_size, order);
+            let total = order as u64 * bits as u64 + plan.bits;
+            (order, residual, plan, total)
+        })
+        .min_by_key(|(_, _, _, total)| *total);
+
+    match best {
+        Some((order, residual, plan, total)) if total < verbatim_bits => {
+            writer.write(0b001000 | order as u64, 7);
+            writer.write(0, 1);
+            for &sample in &samples[..order] {
+                writer.write_signed(sample, bits);
+            }
+            writer.write(0, 2);
+            writer.write(plan.order as u64, 4);
+            let partition = block_size >> plan.order;
+            let mut start = 0;
+            for (index, &parameter) in plan.parameters.iter().enumerate() {
+                let length = if index == 0 {
+                    partition - order
+                } else {
+                    partition
+                };
+                writer.write(parameter as u64, 4);
+                for &value in &residual[start..start + length] {
+                    let value = zigzag(value);
+                    writer.write_unary(value >> parameter);
+                    writer.write(value & ((1 << parameter) - 1), parameter);
+                }
+                start += length;
+            }
+        }
+        _ => {
+            writer.write(0b000010, 8);
+            for &sample in samples {
+                writer.write_signed(sample, bits);
+            }
+        }
+    }
+}
+
+fn encode_frame(channels: &[Vec<i64>], number: u64, bits: u32) -> Vec<u8> {
+    let block_size = channels[0].len();
+    let mut writer = BitWriter::new();
+    writer.write(0b11_1111_1111_1110, 14);
+    writer.write(0, 1);
+    // Fixed block size stream.
+    writer.write(0, 1);
+    // Block size stored as a 16-bit value after the frame number.
+    writer.write(0b0111, 4);
+    // Sample rate and sample size are taken from STREAMINFO.
+    writer.write(0, 4);
+    writer.write(channels.len() as u64 - 1, 4);
+    writer.write(0, 3);
+    writer.write(0, 1);
+    write_utf8(&mut writer, number);
+    writer.write(block_size as u64 - 1, 16);
+    let header = writer.bytes.clone();
+    writer.write(crc8(&header) as u64, 8);
+
+    for channel in channels {
+        write_subframe(&mut writer, channel, bits);
+    }
+    let mut frame = writer.into_bytes();
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+fn metadata_block(buffer: &mut Vec<u8>, kind: u8, last: bool, body: &[u8]) {
+    buffer.push(if last { 0x80 | kind } else { kind });
+    buffer.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    buffer.extend_from_slice(body);
+}
+
+/// Encodes interleaved samples as a FLAC stream, tagged with `metadata` when
+/// given.
+pub fn encode(samples: &[f32], format: &PcmFormat, metadata: Option<&Metadata>) -> Vec<u8> {
+    let bits = format.bit_depth.bits();
+    let channel_count = format.channels as usize;
+    let quantized = samples
+        .iter()
+        .map(|&sample| format::quantize(sample, bits) as i64)
+        .collect::<Vec<_>>();
+    let frames = quantized.len() / channel_count;
+
+    let mut md5 = md5::Context::new();
+    for &sample in &quantized {
+        md5.consume(&(sample as i32).to_le_bytes()[..bits as usize / 8]);
+    }
+
+    let mut encoded = Vec::new();
+    let (mut min_frame, mut max_frame) = (u32::MAX, 0u32);
+    for (number, block) in quantized.chunks(BLOCK_SIZE * channel_count).enumerate() {
+        let channels = (0..channel_count)
+            .map(|channel| {
+                block
+                    .iter()
+                    .skip(channel)
+                    .step_by(channel_count)
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let frame = encode_frame(&channels, number as u64, bits as u32);
+        min_frame = min_frame.min(frame.len() as u32);
+        max_frame = max_frame.max(frame.len() as u32);
+        encoded.extend(frame);
+    }
+    if encoded.is_empty() {
+        min_frame = 0;
+    }
+
+    let mut streaminfo = BitWriter::new();
+    let block_size = BLOCK_SIZE.min(frames.max(16)) as u64;
+    streaminfo.write(block_size, 16);
+    streaminfo.write(block_size, 16);
+    streaminfo.write(min_frame as u64, 24);
+    streaminfo.write(max_frame as u64, 24);
+    streaminfo.write(format.sample_rate as u64, 20);
+    streaminfo.write(channel_count as u64 - 1, 3);
+    streaminfo.write(bits as u64 - 1, 5);
+    streaminfo.write(frames as u64, 36);
+    let mut streaminfo = streaminfo.into_bytes();
+    streaminfo.extend_from_slice(&md5.compute().0);
+
+    let mut buffer = Vec::with_capacity(encoded.len() + 256);
+    buffer.extend_from_slice(b"fLaC");
+    metadata_block(&mut buffer, STREAMINFO, metadata.is_none(), &streaminfo);
+    if let Some(metadata) = metadata {
+        let comment = metadata::vorbis_comment(Some(metadata));
+        metadata_block(&mut buffer, VORBIS_COMMENT, true, &comment);
+    }
+    buffer.extend(encoded);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::format::Encoding;
+    use std::io::Cursor;
+
+    fn pcm_format(channels: u16, bit_depth: BitDepth) -> PcmFormat {
+        PcmFormat {
+            sample_rate: 48000,
+            channels,
+            bit_depth,
+            encoding: Encoding::Linear,
+        }
+    }
+
+    /// A tone with some noise, so that every predictor order gets used.
+    fn signal(frames: usize, channels: usize) -> Vec<f32> {
+        let mut seed = 12345u32;
+        (0..frames * channels)
+            .map(|index| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 65536.0 - 0.5;
+                let phase = (index / channels) as f32 * 0.05 + (index % channels) as f32;
+                0.6 * phase.sin() + 0.05 * noise
+            })
+            .collect()
+    }
+
+    fn round_trip(frames: usize, channels: u16, bit_depth: BitDepth) {
+        let format = pcm_format(channels, bit_depth);
+        let samples = signal(frames, channels as usize);
+        let encoded = encode(&samples, &format, None);
+
+        let mut reader = claxon::FlacReader::new(Cursor::new(encoded)).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.channels, channels as u32);
+        assert_eq!(info.bits_per_sample, bit_depth.bits() as u32);
+        assert_eq!(info.samples, Some(frames as u64));
+
+        let decoded = reader.samples().collect::<Result<Vec<_>, _>>().unwrap();
+        let expected = samples
+            .iter()
+            .map(|&sample| format::quantize(sample, bit_depth.bits()))
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn round_trips_mono_16_bit() {
+        round_trip(10_000, 1, BitDepth::Int16);
+    }
+
+    #[test]
+    fn round_trips_stereo_24_bit() {
+        round_trip(10_000, 2, BitDepth::Int24);
+    }
+
+    #[test]
+    fn round_trips_short_input() {
+        round_trip(100, 1, BitDepth::Int16);
+    }
+
+    #[test]
+    fn round_trips_silence() {
+        let format = pcm_format(1, BitDepth::Int16);
+        let encoded = encode(&[0.0; 5000], &format, None);
+        let mut reader = claxon::FlacReader::new(Cursor::new(encoded)).unwrap();
+        assert!(reader.samples().all(|sample| sample.unwrap() == 0));
+    }
+
+    #[test]
+    fn writes_vorbis_comments() {
+        let metadata = Metadata::new(
+            "こんにちは",
+            vec!["mei".to_string()],
+            "speed_rate=1".to_string(),
+        );
+        let encoded = encode(
+            &signal(1000, 1),
+            &pcm_format(1, BitDepth::Int16),
+            Some(&metadata),
+        );
+        let reader = claxon::FlacReader::new(Cursor::new(encoded)).unwrap();
+        assert_eq!(reader.get_tag("DESCRIPTION").next(), Some("こんにちは"));
+        assert_eq!(reader.get_tag("ARTIST").next(), Some("mei"));
+        assert_eq!(reader.get_tag("COMMENT").next(), Some("speed_rate=1"));
+        assert_eq!(reader.vendor(), Some(metadata::VENDOR));
+    }
+}
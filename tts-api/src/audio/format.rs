@@ -0,0 +1,177 @@
+use super::{g711, resample};
+
+/// Output sample rates accepted in requests.
+pub const SAMPLE_RATES: [u32; 11] = [
+    8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 88200, 96000,
+];
+pub const MAX_CHANNELS: u16 = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    #[serde(rename = "16")]
+    Int16,
+    #[serde(rename = "24")]
+    Int24,
+    #[serde(rename = "32f", alias = "32")]
+    Float32,
+}
+
+impl Default for BitDepth {
+    fn default() -> BitDepth {
+        BitDepth::Int16
+    }
+}
+
+impl BitDepth {
+    pub fn bits(self) -> u16 {
+        match self {
+            BitDepth::Int16 => 16,
+            BitDepth::Int24 => 24,
+            BitDepth::Float32 => 32,
+        }
+    }
+}
+
+/// How samples are coded: linear PCM at `bit_depth`, or G.711 companding.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Linear,
+    #[serde(alias = "ulaw")]
+    Mulaw,
+    Alaw,
+}
+
+impl Default for Encoding {
+    fn default() -> Encoding {
+        Encoding::Linear
+    }
+}
+
+impl Encoding {
+    pub fn law(self) -> Option<g711::Law> {
+        match self {
+            Encoding::Linear => None,
+            Encoding::Mulaw => Some(g711::Law::Mulaw),
+            Encoding::Alaw => Some(g711::Law::Alaw),
+        }
+    }
+}
+
+/// The PCM layout of an encoded response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bit_depth: BitDepth,
+    pub encoding: Encoding,
+}
+
+/// Output format options of a request. Unset options keep the engine's
+/// format.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct OutputFormat {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub bit_depth: Option<BitDepth>,
+    pub encoding: Option<Encoding>,
+}
+
+impl OutputFormat {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(sample_rate) = self.sample_rate {
+            if !SAMPLE_RATES.contains(&sample_rate) {
+                return Err(format!("Unsupported sample rate: {}", sample_rate));
+            }
+        }
+        if let Some(channels) = self.channels {
+            if channels == 0 || channels > MAX_CHANNELS {
+                return Err(format!("Channels must be between 1 and {}.", MAX_CHANNELS));
+            }
+        }
+        if self.encoding.and_then(Encoding::law).is_some() {
+            if self
+                .sample_rate
+                .map_or(false, |rate| rate != g711::SAMPLE_RATE)
+            {
+                return Err(format!("G.711 output is always {} Hz.", g711::SAMPLE_RATE));
+            }
+            if self.bit_depth.is_some() {
+                return Err("bit_depth does not apply to G.711 output.".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills unset options from `fallback`. The sample rate and bit depth
+    /// are not inherited by G.711 output, which fixes both.
+    pub fn or(&self, fallback: &OutputFormat) -> OutputFormat {
+        let encoding = self.encoding.or(fallback.encoding);
+        let linear = encoding.and_then(Encoding::law).is_none();
+        OutputFormat {
+            sample_rate: self.sample_rate.or(fallback.sample_rate.filter(|_| linear)),
+            channels: self.channels.or(fallback.channels),
+            bit_depth: self.bit_depth.or(fallback.bit_depth.filter(|_| linear)),
+            encoding,
+        }
+    }
+
+    /// Resolves the format of audio rendered at `sample_rate` with
+    /// `channels` channels.
+    pub fn resolve(&self, sample_rate: u32, channels: u16) -> PcmFormat {
+        let encoding = self.encoding.unwrap_or_default();
+        let sample_rate = match encoding.law() {
+            Some(_) => g711::SAMPLE_RATE,
+            None => self.sample_rate.unwrap_or(sample_rate),
+        };
+        PcmFormat {
+            sample_rate,
+            channels: self.channels.unwrap_or(channels),
+            bit_depth: self.bit_depth.unwrap_or_default(),
+            encoding,
+        }
+    }
+}
+
+/// Quantizes a float sample to a signed integer of `bits` bits.
+pub fn quantize(sample: f32, bits: u16) -> i32 {
+    let scale = (1i64 << (bits - 1)) as f64;
+    (sample.max(-1.0).min(1.0) as f64 * scale)
+        .round()
+        .min(scale - 1.0) as i32
+}
+
+/// Converts interleaved 16-bit samples at `sample_rate` with `channels`
+/// channels to interleaved float samples in `format`.
+///
+/// Mono is upmixed by copying it to every channel. Other layouts are first
+/// downmixed to mono by averaging.
+pub fn convert(samples: &[i16], sample_rate: u32, channels: u16, format: &PcmFormat) -> Vec<f32> {
+    let samples = samples
+        .iter()
+        .map(|&sample| sample as f32 / 32768.0)
+        .collect::<Vec<_>>();
+    let samples = resample::resample(&samples, channels as usize, sample_rate, format.sample_rate);
+    remix(samples, channels as usize, format.channels as usize)
+}
+
+fn remix(samples: Vec<f32>, from: usize, to: usize) -> Vec<f32> {
+    if from == to {
+        samples
+    } else if from == 1 {
+        samples
+            .into_iter()
+            .flat_map(|sample| std::iter::repeat(sample).take(to))
+            .collect()
+    } else {
+        let downmixed = samples
+            .chunks(from)
+            .map(|frame| frame.iter().sum::<f32>() / from as f32);
+        if to == 1 {
+            downmixed.collect()
+        } else {
+            remix(downmixed.collect(), 1, to)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! ITU-T G.711 companding, after the reference implementation.
+
+use super::format;
+
+const MULAW_SEGMENT_ENDS: [i32; 8] = [0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff, 0x1fff];
+const MULAW_BIAS: i32 = 0x84;
+const MULAW_CLIP: i32 = 8159;
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+
+pub const SAMPLE_RATE: u32 = 8000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Law {
+    Mulaw,
+    Alaw,
+}
+
+impl Law {
+    /// The WAVE format tag (`WAVE_FORMAT_MULAW` / `WAVE_FORMAT_ALAW`).
+    pub fn format_tag(self) -> u16 {
+        match self {
+            Law::Mulaw => 7,
+            Law::Alaw => 6,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Law::Mulaw => "audio/PCMU",
+            Law::Alaw => "audio/PCMA",
+        }
+    }
+}
+
+fn segment(value: i32, ends: &[i32; 8]) -> Option<i32> {
+    ends.iter()
+        .position(|&end| value <= end)
+        .map(|seg| seg as i32)
+}
+
+pub fn mulaw(sample: i16) -> u8 {
+    let mut value = sample as i32 >> 2;
+    let mask = if value < 0 {
+        value = -value;
+        0x7f
+    } else {
+        0xff
+    };
+    let value = value.min(MULAW_CLIP) + (MULAW_BIAS >> 2);
+    match segment(value, &MULAW_SEGMENT_ENDS) {
+        Some(seg) => (((seg << 4) | ((value >> (seg + 1)) & 0xf)) ^ mask) as u8,
+        None => (0x7f ^ mask) as u8,
+    }
+}
+
+pub fn alaw(sample: i16) -> u8 {
+    let mut value = sample as i32 >> 3;
+    let mask = if value >= 0 {
+        0xd5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+    match segment(value, &ALAW_SEGMENT_ENDS) {
+        Some(seg) => {
+            let mantissa = if seg < 2 { value >> 1 } else { value >> seg };
+            (((seg << 4) | (mantissa & 0xf)) ^ mask) as u8
+        }
+        None => (0x7f ^ mask) as u8,
+    }
+}
+
+/// Encodes interleaved float samples as 8-bit G.711 codes.
+pub fn encode(samples: &[f32], law: Law) -> Vec<u8> {
+    let compress = match law {
+        Law::Mulaw => mulaw,
+        Law::Alaw => alaw,
+    };
+    samples
+        .iter()
+        .map(|&sample| compress(format::quantize(sample, 16) as i16))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ulaw2linear` from the G.711 reference implementation.
+    fn mulaw_to_linear(code: u8) -> i16 {
+        let code = !code;
+        let mut value = (((code & 0x0f) as i32) << 3) + MULAW_BIAS;
+        value <<= (code & 0x70) >> 4;
+        if code & 0x80 != 0 {
+            (MULAW_BIAS - value) as i16
+        } else {
+            (value - MULAW_BIAS) as i16
+        }
+    }
+
+    /// `alaw2linear` from the G.711 reference implementation.
+    fn alaw_to_linear(code: u8) -> i16 {
+        let code = code ^ 0x55;
+        let mut value = ((code & 0x0f) as i32) << 4;
+        match (code & 0x70) >> 4 {
+            0 => value += 8,
+            1 => value += 0x108,
+            segment => value = (value + 0x108) << (segment - 1),
+        }
+        if code & 0x80 != 0 {
+            value as i16
+        } else {
+            -value as i16
+        }
+    }
+
+    #[test]
+    fn mulaw_matches_reference_values() {
+        assert_eq!(mulaw(0), 0xff);
+        assert_eq!(mulaw(1000), 0xce);
+        assert_eq!(mulaw(i16::MAX), 0x80);
+        assert_eq!(mulaw(i16::MIN), 0x00);
+    }
+
+    #[test]
+    fn alaw_matches_reference_values() {
+        assert_eq!(alaw(0), 0xd5);
+        assert_eq!(alaw(1000), 0xfa);
+        assert_eq!(alaw(i16::MAX), 0xaa);
+        assert_eq!(alaw(i16::MIN), 0x2a);
+    }
+
+    #[test]
+    fn mulaw_round_trips_every_code() {
+        for code in 0..=255u8 {
+            // Negative zero decodes to the same sample as positive zero.
+            if code == 0x7f {
+                continue;
+            }
+            assert_eq!(mulaw(mulaw_to_linear(code)), code, "code {:#04x}", code);
+        }
+    }
+
+    #[test]
+    fn alaw_round_trips_every_code() {
+        for code in 0..=255u8 {
+            assert_eq!(alaw(alaw_to_linear(code)), code, "code {:#04x}", code);
+        }
+    }
+
+    #[test]
+    fn encoding_is_monotonic() {
+        let decoded = |sample: i16| mulaw_to_linear(mulaw(sample));
+        let mut previous = decoded(i16::MIN);
+        for sample in (i16::MIN..=i16::MAX).step_by(7) {
+            let current = decoded(sample);
+            assert!(
+                current >= previous,
+                "{} decodes below its predecessor",
+                sample
+            );
+            previous = current;
+        }
+    }
+
+    #[test]
+    fn encodes_float_samples() {
+        assert_eq!(
+            encode(&[0.0, 1.0, -1.0], Law::Mulaw),
+            vec![0xff, 0x80, 0x00]
+        );
+        assert_eq!(encode(&[0.0, 1.0, -1.0], Law::Alaw), vec![0xd5, 0xaa, 0x2a]);
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+pub const VENDOR: &str = concat!("tts-api ", env!("CARGO_PKG_VERSION"));
+/// Text longer than this many characters is truncated in tags.
+const MAX_TEXT_LENGTH: usize = 200;
+
+/// Describes what an encoded file contains, for formats that carry tags.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// The spoken text, truncated.
+    pub text: String,
+    /// Voice names in order of appearance.
+    pub voices: Vec<String>,
+    /// Engine parameters of the first voice, as `name=value` pairs.
+    pub parameters: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Metadata {
+    pub fn new(text: &str, voices: Vec<String>, parameters: String) -> Metadata {
+        let text = if text.chars().count() > MAX_TEXT_LENGTH {
+            text.chars()
+                .take(MAX_TEXT_LENGTH)
+                .chain(std::iter::once('…'))
+                .collect()
+        } else {
+            text.to_string()
+        };
+        Metadata {
+            text,
+            voices,
+            parameters,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn date(&self) -> String {
+        self.created_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    /// Vorbis comment fields, as used by FLAC and Ogg.
+    pub fn comments(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("DESCRIPTION", self.text.clone()),
+            ("ARTIST", self.voices.join(", ")),
+            ("COMMENT", self.parameters.clone()),
+            ("DATE", self.date()),
+            ("ENCODER", VENDOR.to_string()),
+        ]
+    }
+
+    /// RIFF `INFO` list fields.
+    pub fn info(&self) -> Vec<(&'static [u8; 4], String)> {
+        vec![
+            (b"INAM", self.text.clone()),
+            (b"IART", self.voices.join(", ")),
+            (b"ICMT", self.parameters.clone()),
+            (b"ICRD", self.date()),
+            (b"ISFT", VENDOR.to_string()),
+        ]
+    }
+}
+
+/// Serializes a Vorbis comment header body, without any framing.
+pub fn vorbis_comment(metadata: Option<&Metadata>) -> Vec<u8> {
+    let comments = metadata
+        .map(Metadata::comments)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>();
+    let mut body = Vec::new();
+    body.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    body.extend_from_slice(VENDOR.as_bytes());
+    body.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        body.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        body.extend_from_slice(comment.as_bytes());
+    }
+    body
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    pub(crate) fn metadata() -> Metadata {
+        Metadata {
+            text: "こんにちは".to_string(),
+            voices: vec!["mei".to_string(), "takumi".to_string()],
+            parameters: String::new(),
+            created_at: Utc.ymd(2021, 3, 19).and_hms(9, 0, 0),
+        }
+    }
+
+    fn read_u32(bytes: &[u8]) -> usize {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+    }
+
+    /// Parses a Vorbis comment header body into its vendor and comments.
+    pub(crate) fn parse_vorbis_comment(body: &[u8]) -> (String, Vec<String>) {
+        let vendor_length = read_u32(body);
+        let vendor = String::from_utf8(body[4..4 + vendor_length].to_vec()).unwrap();
+        let mut rest = &body[4 + vendor_length..];
+        let count = read_u32(rest);
+        rest = &rest[4..];
+        let mut comments = Vec::new();
+        for _ in 0..count {
+            let length = read_u32(rest);
+            comments.push(String::from_utf8(rest[4..4 + length].to_vec()).unwrap());
+            rest = &rest[4 + length..];
+        }
+        assert!(rest.is_empty(), "trailing bytes after the comments");
+        (vendor, comments)
+    }
+
+    #[test]
+    fn vorbis_comment_lengths_count_bytes() {
+        let body = vorbis_comment(Some(&metadata()));
+        let (vendor, comments) = parse_vorbis_comment(&body);
+        assert_eq!(vendor, VENDOR);
+        // The empty COMMENT is left out.
+        assert_eq!(
+            comments,
+            vec![
+                "DESCRIPTION=こんにちは".to_string(),
+                "ARTIST=mei, takumi".to_string(),
+                "DATE=2021-03-19T09:00:00Z".to_string(),
+                format!("ENCODER={}", VENDOR),
+            ]
+        );
+        // Five kana are fifteen bytes of UTF-8.
+        let description = 4 + VENDOR.len() + 4;
+        assert_eq!(read_u32(&body[description..]), "DESCRIPTION=".len() + 15);
+    }
+
+    #[test]
+    fn vorbis_comment_without_metadata_has_only_a_vendor() {
+        let body = vorbis_comment(None);
+        assert_eq!(body.len(), 4 + VENDOR.len() + 4);
+        assert_eq!(
+            parse_vorbis_comment(&body),
+            (VENDOR.to_string(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn long_text_is_truncated() {
+        let metadata = Metadata::new(&"あ".repeat(300), Vec::new(), String::new());
+        assert_eq!(metadata.text.chars().count(), MAX_TEXT_LENGTH + 1);
+        assert!(metadata.text.ends_with('…'));
+        let metadata = Metadata::new("あ", Vec::new(), String::new());
+        assert_eq!(metadata.text, "あ");
+    }
+}
@@ -0,0 +1,28 @@
+pub mod flac;
+pub mod format;
+pub mod g711;
+pub mod metadata;
+pub mod ogg;
+pub mod opus;
+pub mod process;
+pub mod resample;
+pub mod wav;
+
+/// Returns `millis` milliseconds of silence at `sampling_rate`.
+pub fn silence(sampling_rate: u32, millis: u32) -> Vec<i16> {
+    vec![0; (sampling_rate as u64 * millis as u64 / 1000) as usize]
+}
+
+/// Converts mono samples to interleaved stereo with a constant-power pan,
+/// where `-1.0` is hard left and `1.0` is hard right.
+pub fn pan(samples: &[i16], position: f32) -> Vec<i16> {
+    let angle = (position.max(-1.0).min(1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    let (left, right) = (angle.cos(), angle.sin());
+    samples
+        .iter()
+        .flat_map(|&sample| {
+            let sample = sample as f32;
+            vec![(sample * left) as i16, (sample * right) as i16]
+        })
+        .collect()
+}
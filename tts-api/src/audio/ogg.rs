@@ -0,0 +1,305 @@
+//! A minimal Ogg page writer for a single logical stream.
+
+/// Pages are closed once they hold at least this much data.
+const PAGE_DATA_SIZE: usize = 4096;
+const MAX_SEGMENTS: usize = 255;
+
+const CONTINUED: u8 = 0x01;
+const BEGINNING_OF_STREAM: u8 = 0x02;
+const END_OF_STREAM: u8 = 0x04;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |mut crc, &byte| {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+pub struct OggWriter {
+    serial: u32,
+    sequence: u32,
+    output: Vec<u8>,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    /// Granule position of the last packet completed on the current page.
+    granule: Option<u64>,
+    continued: bool,
+}
+
+impl OggWriter {
+    pub fn new(serial: u32) -> OggWriter {
+        OggWriter {
+            serial,
+            sequence: 0,
+            output: Vec::new(),
+            segments: Vec::new(),
+            data: Vec::new(),
+            granule: None,
+            continued: false,
+        }
+    }
+
+    /// Appends a packet ending at `granule`. Packets are packed into pages,
+    /// spanning several pages when needed. Full pages are only closed once
+    /// another packet arrives, so that the last page can carry the end of
+    /// stream flag.
+    pub fn packet(&mut self, packet: &[u8], granule: u64) {
+        if self.data.len() >= PAGE_DATA_SIZE {
+            self.flush(false);
+        }
+        let mut rest = packet;
+        loop {
+            if self.segments.len() == MAX_SEGMENTS {
+                let continued = rest.len() < packet.len();
+                self.flush(false);
+                self.continued = continued;
+            }
+            let lace = rest.len().min(255);
+            self.segments.push(lace as u8);
+            self.data.extend_from_slice(&rest[..lace]);
+            rest = &rest[lace..];
+            if lace < 255 {
+                break;
+            }
+        }
+        self.granule = Some(granule);
+    }
+
+    /// Closes the current page, so the next packet starts a new one.
+    pub fn flush(&mut self, last: bool) {
+        if self.segments.is_empty() && !last {
+            return;
+        }
+        let mut flags = 0;
+        if self.continued {
+            flags |= CONTINUED;
+        }
+        if self.sequence == 0 {
+            flags |= BEGINNING_OF_STREAM;
+        }
+        if last {
+            flags |= END_OF_STREAM;
+        }
+        let granule = self.granule.map_or(-1, |granule| granule as i64);
+
+        let start = self.output.len();
+        self.output.extend_from_slice(b"OggS");
+        self.output.push(0);
+        self.output.push(flags);
+        self.output.extend_from_slice(&granule.to_le_bytes());
+        self.output.extend_from_slice(&self.serial.to_le_bytes());
+        self.output.extend_from_slice(&self.sequence.to_le_bytes());
+        self.output.extend_from_slice(&0u32.to_le_bytes());
+        self.output.push(self.segments.len() as u8);
+        self.output.extend_from_slice(&self.segments);
+        self.output.extend_from_slice(&self.data);
+        let crc = crc32(&self.output[start..]);
+        self.output[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+
+        self.sequence += 1;
+        self.segments.clear();
+        self.data.clear();
+        self.granule = None;
+        self.continued = false;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush(true);
+        self.output
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    pub(crate) struct Page {
+        pub flags: u8,
+        pub granule: i64,
+        pub serial: u32,
+        pub sequence: u32,
+        pub segments: Vec<u8>,
+        pub data: Vec<u8>,
+    }
+
+    impl Page {
+        /// Splits the page data into packet pieces at lacing values below
+        /// 255. The last piece continues on the next page if it is not
+        /// terminated.
+        pub fn pieces(&self) -> (Vec<&[u8]>, bool) {
+            let mut pieces = Vec::new();
+            let mut start = 0;
+            let mut end = 0;
+            for &lace in &self.segments {
+                end += lace as usize;
+                if lace < 255 {
+                    pieces.push(&self.data[start..end]);
+                    start = end;
+                }
+            }
+            let unterminated = start < end;
+            if unterminated {
+                pieces.push(&self.data[start..end]);
+            }
+            (pieces, unterminated)
+        }
+    }
+
+    /// Parses Ogg pages, checking their structure and CRC.
+    pub(crate) fn pages(mut bytes: &[u8]) -> Vec<Page> {
+        let mut pages = Vec::new();
+        while !bytes.is_empty() {
+            assert_eq!(&bytes[..4], b"OggS");
+            assert_eq!(bytes[4], 0, "stream structure version");
+            let count = bytes[26] as usize;
+            let segments = bytes[27..27 + count].to_vec();
+            let data_length: usize = segments.iter().map(|&lace| lace as usize).sum();
+            let length = 27 + count + data_length;
+            let mut page = bytes[..length].to_vec();
+            let crc = u32::from_le_bytes([page[22], page[23], page[24], page[25]]);
+            page[22..26].copy_from_slice(&[0; 4]);
+            assert_eq!(crc32(&page), crc, "CRC of page {}", pages.len());
+
+            let mut granule = [0; 8];
+            granule.copy_from_slice(&bytes[6..14]);
+            pages.push(Page {
+                flags: bytes[5],
+                granule: i64::from_le_bytes(granule),
+                serial: u32::from_le_bytes([bytes[14], bytes[15], bytes[16], bytes[17]]),
+                sequence: u32::from_le_bytes([bytes[18], bytes[19], bytes[20], bytes[21]]),
+                segments,
+                data: bytes[27 + count..length].to_vec(),
+            });
+            bytes = &bytes[length..];
+        }
+        pages
+    }
+
+    fn packet(length: usize, fill: u8) -> Vec<u8> {
+        vec![fill; length]
+    }
+
+    #[test]
+    fn crc_matches_the_ogg_polynomial() {
+        // CRC-32 with polynomial 0x04c11db7, no reflection, zero initial
+        // value and no final XOR.
+        assert_eq!(crc32(b"123456789"), 0x89a1_897f);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn packets_share_a_page_until_it_fills() {
+        let mut writer = OggWriter::new(0x1234_5678);
+        writer.packet(&packet(100, 1), 960);
+        writer.packet(&packet(100, 2), 1920);
+        writer.packet(&packet(600, 3), 2880);
+        let pages = pages(&writer.finish());
+
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert_eq!(page.flags, BEGINNING_OF_STREAM | END_OF_STREAM);
+        assert_eq!(page.serial, 0x1234_5678);
+        assert_eq!(page.sequence, 0);
+        assert_eq!(page.granule, 2880);
+        assert_eq!(page.segments, vec![100, 100, 255, 255, 90]);
+        let (pieces, unterminated) = page.pieces();
+        assert!(!unterminated);
+        assert_eq!(
+            pieces,
+            vec![
+                &packet(100, 1)[..],
+                &packet(100, 2)[..],
+                &packet(600, 3)[..]
+            ]
+        );
+    }
+
+    #[test]
+    fn packets_of_whole_segments_end_with_an_empty_lace() {
+        let mut writer = OggWriter::new(1);
+        writer.packet(&packet(255, 1), 960);
+        writer.packet(&packet(0, 0), 1920);
+        let pages = pages(&writer.finish());
+        assert_eq!(pages[0].segments, vec![255, 0, 0]);
+    }
+
+    #[test]
+    fn flushed_pages_carry_their_last_granule() {
+        let mut writer = OggWriter::new(1);
+        writer.packet(b"head", 0);
+        writer.flush(false);
+        // Flushing an empty page does nothing.
+        writer.flush(false);
+        for i in 1..=3 {
+            writer.packet(&packet(2100, i as u8), i * 960);
+        }
+        let pages = pages(&writer.finish());
+
+        let summary: Vec<(u32, u8, i64, usize)> = pages
+            .iter()
+            .map(|page| (page.sequence, page.flags, page.granule, page.data.len()))
+            .collect();
+        // The second page is closed once it holds at least 4096 bytes and
+        // another packet arrives.
+        assert_eq!(
+            summary,
+            vec![
+                (0, BEGINNING_OF_STREAM, 0, 4),
+                (1, 0, 1920, 4200),
+                (2, END_OF_STREAM, 2880, 2100),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_packets_span_pages() {
+        let long = (0..255 * 300).map(|i| i as u8).collect::<Vec<_>>();
+        let mut writer = OggWriter::new(7);
+        writer.packet(&long, 960);
+        writer.packet(&packet(10, 1), 1920);
+        let pages = pages(&writer.finish());
+
+        assert_eq!(pages.len(), 3);
+        // The first page completes no packet, so it has no granule position.
+        assert_eq!(pages[0].flags, BEGINNING_OF_STREAM);
+        assert_eq!(pages[0].granule, -1);
+        assert_eq!(pages[0].segments, vec![255; 255]);
+        let (pieces, unterminated) = pages[0].pieces();
+        assert!(unterminated);
+        assert_eq!(pieces, vec![&long[..255 * 255]]);
+
+        // The rest is a whole number of segments, so it ends with an empty
+        // lace.
+        assert_eq!(pages[1].flags, CONTINUED);
+        assert_eq!(pages[1].sequence, 1);
+        assert_eq!(pages[1].granule, 960);
+        let mut segments = vec![255; 45];
+        segments.push(0);
+        assert_eq!(pages[1].segments, segments);
+        let (pieces, unterminated) = pages[1].pieces();
+        assert!(!unterminated);
+        assert_eq!(pieces, vec![&long[255 * 255..]]);
+
+        assert_eq!(pages[2].flags, END_OF_STREAM);
+        assert_eq!(pages[2].sequence, 2);
+        assert_eq!(pages[2].granule, 1920);
+        assert_eq!(pages[2].segments, vec![10]);
+    }
+
+    #[test]
+    fn an_empty_stream_still_has_a_page() {
+        let pages = pages(&OggWriter::new(1).finish());
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].flags, BEGINNING_OF_STREAM | END_OF_STREAM);
+        assert_eq!(pages[0].granule, -1);
+        assert!(pages[0].segments.is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use super::{
+    format::{OutputFormat, PcmFormat},
+    metadata::{self, Metadata},
+    ogg::OggWriter,
+};
+use crate::error::AppError;
+
+/// Sample rates supported by the Opus encoder.
+pub const SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const MILLIS_PER_FRAME: usize = 20;
+/// The largest packet a single Opus frame can produce.
+const MAX_PACKET_SIZE: usize = 1275;
+/// Ogg Opus granule positions always count 48 kHz samples.
+const GRANULE_RATE: u64 = 48000;
+/// Encoder delay to skip on playback, in 48 kHz samples.
+const PRE_SKIP: u16 = 312;
+
+/// Resolves the Opus stream format. Opus always encodes at one of its own
+/// sample rates, so this defaults to 48 kHz rather than the engine's rate.
+pub fn resolve(output: &OutputFormat, channels: u16) -> Result<PcmFormat, String> {
+    let format = OutputFormat {
+        sample_rate: output.sample_rate.or(Some(DEFAULT_SAMPLE_RATE)),
+        ..output.clone()
+    }
+    .resolve(DEFAULT_SAMPLE_RATE, channels);
+
+    if !SAMPLE_RATES.contains(&format.sample_rate) {
+        return Err(format!("Opus supports sample rates of {:?}.", SAMPLE_RATES));
+    }
+    if format.encoding.law().is_some() {
+        return Err("Opus output cannot use G.711 encoding.".to_string());
+    }
+    if format.channels > 2 {
+        return Err("Opus output must be mono or stereo.".to_string());
+    }
+    Ok(format)
+}
+
+fn frame_size(format: &PcmFormat) -> usize {
+    format.sample_rate as usize * MILLIS_PER_FRAME / 1000
+}
+
+/// Encodes interleaved samples into 20ms Opus packets. The last frame is
+/// padded with silence.
+pub fn encode(samples: &[f32], format: &PcmFormat) -> Result<Vec<Vec<u8>>, AppError> {
+    let channels = match format.channels {
+        1 => ::opus::Channels::Mono,
+        _ => ::opus::Channels::Stereo,
+    };
+    let mut encoder =
+        ::opus::Encoder::new(format.sample_rate, channels, ::opus::Application::Audio)?;
+
+    let frame_length = frame_size(format) * format.channels as usize;
+    samples
+        .chunks(frame_length)
+        .map(|chunk| {
+            let mut frame = chunk.to_vec();
+            frame.resize(frame_length, 0.0);
+            let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+            let length = encoder.encode_float(&frame, &mut buffer)?;
+            buffer.truncate(length);
+            Ok(buffer)
+        })
+        .collect()
+}
+
+fn opus_head(format: &PcmFormat) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1);
+    head.push(format.channels as u8);
+    head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&format.sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// Encodes interleaved samples as an Ogg Opus file, with `OpusTags` comments
+/// from `metadata` when given.
+pub fn encode_ogg(
+    samples: &[f32],
+    format: &PcmFormat,
+    metadata: Option<&Metadata>,
+) -> Result<Vec<u8>, AppError> {
+    // Flush the encoder delay out with trailing silence; the end granule
+    // trims it again on playback.
+    let frames = (samples.len() / format.channels as usize) as u64;
+    let delay = PRE_SKIP as u64 * format.sample_rate as u64 / GRANULE_RATE;
+    let mut padded = samples.to_vec();
+    padded.resize(
+        samples.len() + (delay as usize) * format.channels as usize,
+        0.0,
+    );
+    let packets = encode(&padded, format)?;
+    let end = PRE_SKIP as u64 + frames * GRANULE_RATE / format.sample_rate as u64;
+    let packet_duration = GRANULE_RATE * MILLIS_PER_FRAME as u64 / 1000;
+
+    let mut writer = OggWriter::new(rand::random());
+    writer.packet(&opus_head(format), 0);
+    writer.flush(false);
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend(metadata::vorbis_comment(metadata));
+    writer.packet(&tags, 0);
+    writer.flush(false);
+
+    let mut granule = 0;
+    for packet in packets {
+        granule = (granule + packet_duration).min(end);
+        writer.packet(&packet, granule);
+    }
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{metadata::tests, ogg::tests::pages};
+
+    #[test]
+    fn ogg_opus_headers_get_their_own_pages() {
+        let output = OutputFormat {
+            sample_rate: Some(16000),
+            ..OutputFormat::default()
+        };
+        let format = resolve(&output, 1).unwrap();
+        let metadata = tests::metadata();
+        let ogg = encode_ogg(&[0.0; 1000], &format, Some(&metadata)).unwrap();
+        let pages = pages(&ogg);
+
+        // OpusHead, OpusTags, then 1104 padded samples in four packets.
+        assert_eq!(pages.len(), 3);
+        assert!(pages.iter().all(|page| page.serial == pages[0].serial));
+        let sequences = pages.iter().map(|page| page.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences, vec![0, 1, 2]);
+
+        assert_eq!(pages[0].flags, 0x02);
+        assert_eq!(pages[0].granule, 0);
+        assert_eq!(pages[0].pieces(), (vec![&opus_head(&format)[..]], false));
+
+        assert_eq!(pages[1].flags, 0);
+        assert_eq!(pages[1].granule, 0);
+        let (pieces, _) = pages[1].pieces();
+        assert_eq!(&pieces[0][..8], b"OpusTags");
+        let (vendor, comments) = tests::parse_vorbis_comment(&pieces[0][8..]);
+        assert_eq!(vendor, metadata::VENDOR);
+        assert_eq!(comments.len(), 4);
+
+        // The end granule trims the pre-skip padding: 312 + 1000 * 3.
+        assert_eq!(pages[2].flags, 0x04);
+        assert_eq!(pages[2].granule, 3312);
+        assert_eq!(pages[2].pieces().0.len(), 4);
+    }
+}
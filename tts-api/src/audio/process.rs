@@ -0,0 +1,458 @@
+//! Post-processing applied between engine output and encoding.
+
+use std::{f64::consts::PI, ops::Range};
+
+const DEFAULT_PEAK_TARGET: f64 = -1.0;
+const DEFAULT_RMS_TARGET: f64 = -20.0;
+const DEFAULT_LUFS_TARGET: f64 = -16.0;
+const DEFAULT_PEAK_CEILING: f64 = -1.0;
+const DEFAULT_TRIM_THRESHOLD: f64 = -50.0;
+/// Audio kept around the detected speech when trimming, so that soft onsets
+/// and releases are not clipped.
+const TRIM_MARGIN_MS: u32 = 20;
+const MAX_PAD_MS: u32 = 10_000;
+const MAX_FADE_MS: u32 = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoudnessMethod {
+    /// Disables normalization at this level, e.g. overriding `[postprocess]`
+    /// for a voice.
+    None,
+    /// Sample peak in dBFS.
+    Peak,
+    /// RMS level in dBFS.
+    Rms,
+    /// Gated, K-weighted integrated loudness in LUFS (ITU-R BS.1770).
+    Lufs,
+}
+
+/// A loudness normalization target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    pub method: LoudnessMethod,
+    pub target: f64,
+    /// Upper bound for the sample peak in dBFS after normalization.
+    pub ceiling: f64,
+}
+
+/// Post-processing options. Unset options fall back to the next level (request,
+/// then `[postprocess]`); see [`PostProcess::or`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PostProcess {
+    pub loudness: Option<LoudnessMethod>,
+    pub loudness_target: Option<f64>,
+    pub peak_ceiling: Option<f64>,
+    pub trim: Option<bool>,
+    pub trim_threshold: Option<f64>,
+    pub fade_in_ms: Option<u32>,
+    pub fade_out_ms: Option<u32>,
+    pub pad_start_ms: Option<u32>,
+    pub pad_end_ms: Option<u32>,
+}
+
+impl PostProcess {
+    pub fn or(&self, fallback: &PostProcess) -> PostProcess {
+        // A method change invalidates the fallback's target, which is in
+        // different units.
+        let loudness_target = match (self.loudness, fallback.loudness) {
+            (Some(method), Some(fallback_method)) if method != fallback_method => {
+                self.loudness_target
+            }
+            _ => self.loudness_target.or(fallback.loudness_target),
+        };
+        PostProcess {
+            loudness: self.loudness.or(fallback.loudness),
+            loudness_target,
+            peak_ceiling: self.peak_ceiling.or(fallback.peak_ceiling),
+            trim: self.trim.or(fallback.trim),
+            trim_threshold: self.trim_threshold.or(fallback.trim_threshold),
+            fade_in_ms: self.fade_in_ms.or(fallback.fade_in_ms),
+            fade_out_ms: self.fade_out_ms.or(fallback.fade_out_ms),
+            pad_start_ms: self.pad_start_ms.or(fallback.pad_start_ms),
+            pad_end_ms: self.pad_end_ms.or(fallback.pad_end_ms),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(target) = self.loudness_target {
+            if !(-70.0..=0.0).contains(&target) {
+                return Err(format!("Loudness target out of range: {}", target));
+            }
+        }
+        if let Some(ceiling) = self.peak_ceiling {
+            if !(-70.0..=0.0).contains(&ceiling) {
+                return Err(format!("Peak ceiling out of range: {}", ceiling));
+            }
+        }
+        if let Some(threshold) = self.trim_threshold {
+            if !(-96.0..=0.0).contains(&threshold) {
+                return Err(format!("Trim threshold out of range: {}", threshold));
+            }
+        }
+        for fade in [self.fade_in_ms, self.fade_out_ms].iter().flatten() {
+            if *fade > MAX_FADE_MS {
+                return Err(format!("Fades must be at most {}ms.", MAX_FADE_MS));
+            }
+        }
+        for pad in [self.pad_start_ms, self.pad_end_ms].iter().flatten() {
+            if *pad > MAX_PAD_MS {
+                return Err(format!("Padding must be at most {}ms.", MAX_PAD_MS));
+            }
+        }
+        Ok(())
+    }
+
+    /// The normalization target, if normalization is enabled.
+    pub fn loudness(&self) -> Option<Loudness> {
+        let method = self.loudness?;
+        let default_target = match method {
+            LoudnessMethod::None => return None,
+            LoudnessMethod::Peak => DEFAULT_PEAK_TARGET,
+            LoudnessMethod::Rms => DEFAULT_RMS_TARGET,
+            LoudnessMethod::Lufs => DEFAULT_LUFS_TARGET,
+        };
+        Some(Loudness {
+            method,
+            target: self.loudness_target.unwrap_or(default_target),
+            ceiling: self.peak_ceiling.unwrap_or(DEFAULT_PEAK_CEILING),
+        })
+    }
+
+    /// The silence threshold in dBFS, if trimming is enabled.
+    pub fn trim_threshold(&self) -> Option<f64> {
+        if self.trim.unwrap_or(false) {
+            Some(self.trim_threshold.unwrap_or(DEFAULT_TRIM_THRESHOLD))
+        } else {
+            None
+        }
+    }
+}
+
+impl Loudness {
+    /// Returns the same normalization aiming `decibels` louder or quieter.
+    pub fn shifted(self, decibels: f64) -> Loudness {
+        Loudness {
+            target: self.target + decibels,
+            ..self
+        }
+    }
+
+    /// Scales `samples` in place to the target level. Silent input is left
+    /// untouched.
+    pub fn apply(&self, samples: &mut [i16], sampling_rate: u32) {
+        let level = match self.method {
+            LoudnessMethod::None => return,
+            LoudnessMethod::Peak => peak_db(samples),
+            LoudnessMethod::Rms => rms_db(samples),
+            LoudnessMethod::Lufs => integrated_loudness(samples, sampling_rate),
+        };
+        let level = match level {
+            Some(level) => level,
+            None => return,
+        };
+        let mut gain = self.target - level;
+        if let Some(peak) = peak_db(samples) {
+            gain = gain.min(self.ceiling - peak);
+        }
+        apply_gain(samples, gain);
+    }
+}
+
+fn to_db(amplitude: f64) -> Option<f64> {
+    if amplitude > 0.0 {
+        Some(20.0 * amplitude.log10())
+    } else {
+        None
+    }
+}
+
+fn normalized(sample: i16) -> f64 {
+    sample as f64 / 32768.0
+}
+
+pub fn peak_db(samples: &[i16]) -> Option<f64> {
+    let peak = samples
+        .iter()
+        .map(|&sample| normalized(sample).abs())
+        .fold(0.0, f64::max);
+    to_db(peak)
+}
+
+pub fn rms_db(samples: &[i16]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let power = samples
+        .iter()
+        .map(|&sample| normalized(sample).powi(2))
+        .sum::<f64>()
+        / samples.len() as f64;
+    to_db(power.sqrt())
+}
+
+pub fn apply_gain(samples: &mut [i16], decibels: f64) {
+    let gain = 10f64.powf(decibels / 20.0);
+    for sample in samples.iter_mut() {
+        *sample = (*sample as f64 * gain)
+            .round()
+            .max(i16::MIN as f64)
+            .min(i16::MAX as f64) as i16;
+    }
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.state[0];
+        self.state[0] = self.b[1] * input - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// The two K-weighting stages of BS.1770, derived for `sampling_rate`.
+fn k_weighting(sampling_rate: u32) -> [Biquad; 2] {
+    let rate = sampling_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Integrated loudness of mono `samples` in LUFS, using 400ms blocks with 75%
+/// overlap, an absolute gate of -70 LUFS and a relative gate of -10 LU.
+pub fn integrated_loudness(samples: &[i16], sampling_rate: u32) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let [mut shelf, mut high_pass] = k_weighting(sampling_rate);
+    let weighted = samples
+        .iter()
+        .map(|&sample| high_pass.process(shelf.process(normalized(sample))).powi(2))
+        .collect::<Vec<_>>();
+
+    let block = (sampling_rate as usize * 400 / 1000).min(weighted.len());
+    let step = (block / 4).max(1);
+    let powers = (0..=weighted.len() - block)
+        .step_by(step)
+        .map(|start| weighted[start..start + block].iter().sum::<f64>() / block as f64)
+        .collect::<Vec<_>>();
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+
+    let gated = powers
+        .into_iter()
+        .filter(|&power| power > 0.0 && loudness(power) > -70.0)
+        .collect::<Vec<_>>();
+    if gated.is_empty() {
+        return None;
+    }
+    let threshold = loudness(mean(&gated)) - 10.0;
+    let gated = gated
+        .into_iter()
+        .filter(|&power| loudness(power) > threshold)
+        .collect::<Vec<_>>();
+
+    Some(loudness(mean(&gated)))
+}
+
+/// Returns the range of `samples` from the first to the last sample above
+/// `threshold` dBFS, widened by a short margin. Returns an empty range when
+/// nothing is above the threshold.
+pub fn trim_range(samples: &[i16], sampling_rate: u32, threshold: f64) -> Range<usize> {
+    let threshold = 10f64.powf(threshold / 20.0);
+    let is_audible = |sample: &i16| normalized(*sample).abs() > threshold;
+    let first = match samples.iter().position(is_audible) {
+        Some(first) => first,
+        None => return 0..0,
+    };
+    let last = samples.iter().rposition(is_audible).unwrap_or(first);
+    let margin = (sampling_rate as u64 * TRIM_MARGIN_MS as u64 / 1000) as usize;
+    first.saturating_sub(margin)..(last + 1 + margin).min(samples.len())
+}
+
+/// Applies linear fades to the start and end of `samples`.
+pub fn fade(samples: &mut [i16], sampling_rate: u32, fade_in_ms: u32, fade_out_ms: u32) {
+    let length = |millis: u32| {
+        ((sampling_rate as u64 * millis as u64 / 1000) as usize).min(samples.len())
+    };
+    let (fade_in, fade_out) = (length(fade_in_ms), length(fade_out_ms));
+    let total = samples.len();
+    for (index, sample) in samples.iter_mut().enumerate() {
+        let mut gain = 1.0;
+        if index < fade_in {
+            gain *= index as f64 / fade_in as f64;
+        }
+        if total - index <= fade_out {
+            gain *= (total - index - 1) as f64 / fade_out as f64;
+        }
+        if gain < 1.0 {
+            *sample = (*sample as f64 * gain).round() as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// One second of a 1 kHz sine at `amplitude` (0 to 1).
+    fn sine(amplitude: f64) -> Vec<i16> {
+        (0..RATE)
+            .map(|i| {
+                let phase = 2.0 * PI * 1000.0 * i as f64 / RATE as f64;
+                (amplitude * 32767.0 * phase.sin()).round() as i16
+            })
+            .collect()
+    }
+
+    fn loudness(method: LoudnessMethod, target: f64) -> Loudness {
+        Loudness {
+            method,
+            target,
+            ceiling: DEFAULT_PEAK_CEILING,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn trims_silent_edges_with_a_margin() {
+        let mut samples = vec![0; 100];
+        samples.extend(vec![10_000; 50]);
+        samples.extend(vec![0; 100]);
+        // 20ms at 1 kHz is 20 samples on each side.
+        assert_eq!(trim_range(&samples, 1000, -50.0), 80..170);
+    }
+
+    #[test]
+    fn keeps_non_silent_edges() {
+        let samples = vec![10_000; 50];
+        assert_eq!(trim_range(&samples, 1000, -50.0), 0..50);
+        let mut samples = vec![0; 5];
+        samples.extend(vec![10_000; 10]);
+        assert_eq!(trim_range(&samples, 1000, -50.0), 0..15);
+    }
+
+    #[test]
+    fn trim_threshold_decides_what_is_silent() {
+        // About -56 dBFS.
+        let quiet = vec![50; 100];
+        assert_eq!(trim_range(&quiet, 1000, -50.0), 0..0);
+        assert_eq!(trim_range(&quiet, 1000, -60.0), 0..100);
+        assert_eq!(trim_range(&[0; 100], 1000, -96.0), 0..0);
+        assert_eq!(trim_range(&[], 1000, -50.0), 0..0);
+    }
+
+    #[test]
+    fn fades_ramp_linearly() {
+        let mut samples = vec![1000; 100];
+        fade(&mut samples, 1000, 10, 10);
+        assert_eq!(&samples[..3], &[0, 100, 200]);
+        assert_eq!(samples[10], 1000);
+        assert_eq!(samples[89], 1000);
+        assert_eq!(&samples[97..], &[200, 100, 0]);
+    }
+
+    #[test]
+    fn fades_longer_than_the_clip_cover_it() {
+        let mut samples = vec![1000; 10];
+        fade(&mut samples, 1000, 10_000, 10_000);
+        // Both fades are cut to the ten samples of the clip.
+        let expected: Vec<i16> = (0..10)
+            .map(|i| (1000.0 * i as f64 / 10.0 * (9 - i) as f64 / 10.0).round() as i16)
+            .collect();
+        assert_eq!(samples, expected);
+
+        let mut empty: Vec<i16> = Vec::new();
+        fade(&mut empty, 1000, 100, 100);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn no_fade_leaves_samples() {
+        let mut samples = vec![1000; 10];
+        fade(&mut samples, 1000, 0, 0);
+        assert_eq!(samples, vec![1000; 10]);
+    }
+
+    #[test]
+    fn measures_a_sine() {
+        let samples = sine(0.5);
+        assert_close(peak_db(&samples).unwrap(), -6.02, 0.01);
+        assert_close(rms_db(&samples).unwrap(), -9.03, 0.01);
+        // A full-scale 1 kHz sine reads -3.01 LUFS in BS.1770.
+        assert_close(integrated_loudness(&samples, RATE).unwrap(), -9.03, 0.1);
+        assert_eq!(peak_db(&[0; 10]), None);
+        assert_eq!(integrated_loudness(&[0; 48_000], RATE), None);
+    }
+
+    #[test]
+    fn normalizes_a_sine_to_the_target() {
+        let mut samples = sine(0.5);
+        loudness(LoudnessMethod::Peak, -3.0).apply(&mut samples, RATE);
+        assert_close(peak_db(&samples).unwrap(), -3.0, 0.01);
+
+        let mut samples = sine(0.5);
+        loudness(LoudnessMethod::Rms, -20.0).apply(&mut samples, RATE);
+        assert_close(rms_db(&samples).unwrap(), -20.0, 0.01);
+
+        let mut samples = sine(0.5);
+        loudness(LoudnessMethod::Lufs, -16.0).apply(&mut samples, RATE);
+        assert_close(integrated_loudness(&samples, RATE).unwrap(), -16.0, 0.1);
+    }
+
+    #[test]
+    fn normalization_stops_at_the_ceiling() {
+        let mut samples = sine(0.5);
+        // Reaching -1 LUFS would need a peak of about +2 dBFS.
+        loudness(LoudnessMethod::Lufs, -1.0).apply(&mut samples, RATE);
+        assert_close(peak_db(&samples).unwrap(), DEFAULT_PEAK_CEILING, 0.01);
+    }
+
+    #[test]
+    fn silence_is_not_normalized() {
+        let mut samples = vec![0; 1000];
+        loudness(LoudnessMethod::Lufs, -16.0).apply(&mut samples, RATE);
+        assert_eq!(samples, vec![0; 1000]);
+    }
+}
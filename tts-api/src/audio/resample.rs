@@ -0,0 +1,150 @@
+//! Band-limited sample rate conversion using a polyphase windowed-sinc filter.
+
+use std::f64::consts::PI;
+
+/// Filter half-length in zero crossings of the sinc at the cutoff.
+const ZERO_CROSSINGS: f64 = 24.0;
+/// Cutoff relative to the lower Nyquist frequency, leaving room for the
+/// transition band.
+const ROLLOFF: f64 = 0.94;
+const KAISER_BETA: f64 = 8.6;
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Zeroth-order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Resamples interleaved `samples` with `channels` channels from `from` Hz to
+/// `to` Hz.
+pub fn resample(samples: &[f32], channels: usize, from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let divisor = gcd(from, to);
+    let (up, down) = ((to / divisor) as usize, (from / divisor) as usize);
+    let cutoff = (to as f64 / from as f64).min(1.0) * ROLLOFF;
+    let half = (ZERO_CROSSINGS / cutoff).ceil() as usize;
+    let window_norm = bessel_i0(KAISER_BETA);
+
+    // One filter per output phase; tap `j` of phase `p` weights the input
+    // sample at offset `j - (half - 1)` from the output position's floor.
+    let filters = (0..up)
+        .map(|phase| {
+            (0..2 * half)
+                .map(|tap| {
+                    let x = tap as f64 - (half - 1) as f64 - phase as f64 / up as f64;
+                    let ratio = (x / half as f64).min(1.0).max(-1.0);
+                    let window =
+                        bessel_i0(KAISER_BETA * (1.0 - ratio * ratio).sqrt()) / window_norm;
+                    (cutoff * sinc(cutoff * x) * window) as f32
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let frames = samples.len() / channels;
+    let output_frames = (frames * up + down - 1) / down;
+    let mut output = Vec::with_capacity(output_frames * channels);
+    for frame in 0..output_frames {
+        let position = frame * down;
+        let (base, phase) = (position / up, position % up);
+        let filter = &filters[phase];
+        for channel in 0..channels {
+            let mut sum = 0.0;
+            for (tap, weight) in filter.iter().enumerate() {
+                let index = (base + tap) as isize - (half - 1) as isize;
+                if index >= 0 && (index as usize) < frames {
+                    sum += samples[index as usize * channels + channel] * weight;
+                }
+            }
+            output.push(sum);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that every frame away from the edges, where the filter runs
+    /// off the input, is `expected` on each channel.
+    fn assert_constant(output: &[f32], channels: usize, expected: &[f32], edge: usize) {
+        let frames = output.len() / channels;
+        for frame in edge..frames - edge {
+            for channel in 0..channels {
+                let sample = output[frame * channels + channel];
+                assert!(
+                    (sample - expected[channel]).abs() < 1e-3,
+                    "frame {} channel {}: {} != {}",
+                    frame,
+                    channel,
+                    sample,
+                    expected[channel]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn equal_rates_are_identity() {
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.1).sin()).collect();
+        assert_eq!(resample(&samples, 1, 48000, 48000), samples);
+        assert_eq!(resample(&samples, 2, 24000, 24000), samples);
+    }
+
+    #[test]
+    fn output_length_follows_rate_ratio() {
+        assert_eq!(resample(&[0.0; 48000], 1, 48000, 8000).len(), 8000);
+        assert_eq!(resample(&[0.0; 8000], 1, 8000, 48000).len(), 48000);
+        assert_eq!(resample(&[0.0; 2 * 4410], 2, 44100, 48000).len(), 2 * 4800);
+        assert!(resample(&[], 1, 48000, 8000).is_empty());
+    }
+
+    #[test]
+    fn downsampling_preserves_dc() {
+        let output = resample(&[0.5; 48000], 1, 48000, 8000);
+        assert_constant(&output, 1, &[0.5], 100);
+    }
+
+    #[test]
+    fn upsampling_preserves_dc() {
+        let output = resample(&[0.5; 8000], 1, 8000, 48000);
+        assert_constant(&output, 1, &[0.5], 1000);
+    }
+
+    #[test]
+    fn channels_are_resampled_independently() {
+        let samples: Vec<f32> = (0..2 * 24000)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.25 })
+            .collect();
+        let output = resample(&samples, 2, 24000, 16000);
+        assert_eq!(output.len(), 2 * 16000);
+        assert_constant(&output, 2, &[0.5, -0.25], 200);
+    }
+}
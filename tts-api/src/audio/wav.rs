@@ -0,0 +1,203 @@
+use super::{
+    format::{self, BitDepth, PcmFormat},
+    g711,
+    metadata::Metadata,
+};
+
+const PCM_FORMAT_TAG: u16 = 1;
+const IEEE_FLOAT_FORMAT_TAG: u16 = 3;
+const EXTENSIBLE_FORMAT_TAG: u16 = 0xfffe;
+/// The tail shared by all `KSDATAFORMAT_SUBTYPE_*` GUIDs, after the format tag.
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+fn chunk(buffer: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    buffer.extend_from_slice(id);
+    buffer.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        buffer.push(0);
+    }
+}
+
+/// Builds the `fmt ` chunk body, and whether the format needs a `fact` chunk.
+/// `WAVE_FORMAT_EXTENSIBLE` is used where plain format tags are ambiguous:
+/// linear PCM with more than two channels or more than 16 bits.
+fn fmt_body(format: &PcmFormat) -> (Vec<u8>, bool) {
+    let (format_tag, bits) = match (format.encoding.law(), format.bit_depth) {
+        (Some(law), _) => (law.format_tag(), 8),
+        (None, BitDepth::Float32) => (IEEE_FLOAT_FORMAT_TAG, 32),
+        (None, bit_depth) => (PCM_FORMAT_TAG, bit_depth.bits()),
+    };
+    let extensible = format.encoding.law().is_none() && (format.channels > 2 || bits > 16);
+    let block_align = format.channels * bits / 8;
+    let byte_rate = format.sample_rate * block_align as u32;
+
+    let mut body = Vec::with_capacity(40);
+    let tag = if extensible {
+        EXTENSIBLE_FORMAT_TAG
+    } else {
+        format_tag
+    };
+    body.extend_from_slice(&tag.to_le_bytes());
+    body.extend_from_slice(&format.channels.to_le_bytes());
+    body.extend_from_slice(&format.sample_rate.to_le_bytes());
+    body.extend_from_slice(&byte_rate.to_le_bytes());
+    body.extend_from_slice(&block_align.to_le_bytes());
+    body.extend_from_slice(&bits.to_le_bytes());
+    if extensible {
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body.extend_from_slice(&channel_mask(format.channels).to_le_bytes());
+        body.extend_from_slice(&format_tag.to_le_bytes());
+        body.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+    } else if format_tag != PCM_FORMAT_TAG {
+        body.extend_from_slice(&0u16.to_le_bytes());
+    }
+    (body, format_tag != PCM_FORMAT_TAG)
+}
+
+/// Speaker positions in the default order, up to 7.1.
+fn channel_mask(channels: u16) -> u32 {
+    match channels {
+        1 => 0x4,
+        2 => 0x3,
+        3 => 0x7,
+        4 => 0x33,
+        5 => 0x37,
+        6 => 0x3f,
+        7 => 0x13f,
+        _ => 0x63f,
+    }
+}
+
+fn encode_samples(samples: &[f32], bit_depth: BitDepth) -> Vec<u8> {
+    let bytes = bit_depth.bits() as usize / 8;
+    let mut data = Vec::with_capacity(samples.len() * bytes);
+    for &sample in samples {
+        match bit_depth {
+            BitDepth::Int16 | BitDepth::Int24 => {
+                let sample = format::quantize(sample, bit_depth.bits());
+                data.extend_from_slice(&sample.to_le_bytes()[..bytes]);
+            }
+            BitDepth::Float32 => data.extend_from_slice(&sample.max(-1.0).min(1.0).to_le_bytes()),
+        }
+    }
+    data
+}
+
+/// Builds a `LIST` chunk body of type `INFO`.
+fn info_body(metadata: &Metadata) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(b"INFO");
+    for (id, value) in metadata.info() {
+        if value.is_empty() {
+            continue;
+        }
+        let mut value = value.into_bytes();
+        value.push(0);
+        chunk(&mut body, id, &value);
+    }
+    body
+}
+
+/// Encodes interleaved samples as a RIFF/WAVE file in `format`, with an
+/// `INFO` list when `metadata` is given.
+pub fn encode(samples: &[f32], format: &PcmFormat, metadata: Option<&Metadata>) -> Vec<u8> {
+    let (fmt, needs_fact) = fmt_body(format);
+    let data = match format.encoding.law() {
+        Some(law) => g711::encode(samples, law),
+        None => encode_samples(samples, format.bit_depth),
+    };
+
+    let mut body = Vec::with_capacity(data.len() + 80);
+    body.extend_from_slice(b"WAVE");
+    chunk(&mut body, b"fmt ", &fmt);
+    if needs_fact {
+        let frames = (samples.len() / format.channels as usize) as u32;
+        chunk(&mut body, b"fact", &frames.to_le_bytes());
+    }
+    if let Some(metadata) = metadata {
+        chunk(&mut body, b"LIST", &info_body(metadata));
+    }
+    chunk(&mut body, b"data", &data);
+
+    let mut buffer = Vec::with_capacity(body.len() + 8);
+    chunk(&mut buffer, b"RIFF", &body);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{
+        format::OutputFormat,
+        metadata::{tests::metadata, VENDOR},
+    };
+
+    /// Splits a sequence of RIFF chunks, checking each pad byte.
+    fn chunks(mut bytes: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = Vec::new();
+        while !bytes.is_empty() {
+            let id = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+            chunks.push((id, &bytes[8..8 + size]));
+            let padded = size + size % 2;
+            if size % 2 == 1 {
+                assert_eq!(bytes[8 + size], 0, "pad byte of {:?}", id);
+            }
+            bytes = &bytes[8 + padded..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn info_chunks_are_padded_to_even_sizes() {
+        let mut metadata = metadata();
+        metadata.parameters = "alpha=0.55".to_string();
+        let format = OutputFormat::default().resolve(16000, 1);
+        let wav = encode(&[0.0, 0.5, -0.5], &format, Some(&metadata));
+
+        assert_eq!(wav.len() % 2, 0);
+        let riff = chunks(&wav);
+        assert_eq!(riff.len(), 1);
+        assert_eq!(&riff[0].0, b"RIFF");
+        assert_eq!(riff[0].1.len(), wav.len() - 8);
+        assert_eq!(&riff[0].1[..4], b"WAVE");
+
+        let body = chunks(&riff[0].1[4..]);
+        let ids = body.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![b"fmt ", b"LIST", b"data"]);
+        assert_eq!(body[2].1.len(), 6);
+
+        let list = body[1].1;
+        assert_eq!(&list[..4], b"INFO");
+        let info = chunks(&list[4..]);
+        let values = info
+            .iter()
+            .map(|(id, value)| (id, *value))
+            .collect::<Vec<_>>();
+        let vendor = format!("{}\0", VENDOR);
+        assert_eq!(
+            values,
+            vec![
+                (b"INAM", "こんにちは\0".as_bytes()),
+                (b"IART", b"mei, takumi\0" as &[u8]),
+                (b"ICMT", b"alpha=0.55\0" as &[u8]),
+                (b"ICRD", b"2021-03-19T09:00:00Z\0" as &[u8]),
+                (b"ISFT", vendor.as_bytes()),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_metadata_means_no_list() {
+        let format = OutputFormat::default().resolve(16000, 1);
+        let wav = encode(&[0.0], &format, None);
+        let riff = chunks(&wav);
+        let body = chunks(&riff[0].1[4..]);
+        let ids = body.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![b"fmt ", b"data"]);
+    }
+}
@@ -4,10 +4,24 @@ pub mod token;
 pub use self::routes::init;
 pub use self::routes::GitHubUserData;
 
+use crate::{
+    auth::token::Token,
+    error::AppError,
+    models::{account_status::AccountStatus, users::User},
+    trace,
+};
+use actix_web::HttpResponse;
 use oauth2::basic::BasicClient;
 use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
+use sqlx::PgPool;
 use std::env;
 
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    pub id: i64,
+    pub token: String,
+}
+
 pub fn create_auth_client() -> BasicClient {
     let client_id =
         ClientId::new(env::var("GITHUB_CLIENT_ID").expect("GITHUB_CLIENT_ID is not set"));
@@ -24,3 +38,40 @@ pub fn create_auth_client() -> BasicClient {
     BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
         .set_redirect_url(redirect_url)
 }
+
+pub async fn authenticate(pool: &PgPool, id: i64, token: &str) -> Result<User, HttpResponse> {
+    let _stage = trace::stage("authenticate");
+    let token = Token::new(token);
+
+    match token.verify(pool, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(HttpResponse::Unauthorized().body("Invalid token.")),
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => {
+            return Err(HttpResponse::NotFound().body("User not found"))
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            return Err(HttpResponse::InternalServerError().body("Unexpected Error"));
+        }
+    }
+
+    let user = match User::get_or_create(pool, id).await {
+        Ok(user) => user,
+        Err(_) => return Err(HttpResponse::InternalServerError().body("Unexpected Error")),
+    };
+
+    match user.status() {
+        AccountStatus::Active => Ok(user),
+        AccountStatus::Suspended => Err(HttpResponse::Forbidden().body("Account suspended.")),
+        AccountStatus::Banned => Err(HttpResponse::Forbidden().body("Account banned.")),
+    }
+}
+
+pub async fn authenticate_admin(pool: &PgPool, id: i64, token: &str) -> Result<User, HttpResponse> {
+    let user = authenticate(pool, id, token).await?;
+    if user.is_admin() {
+        Ok(user)
+    } else {
+        Err(HttpResponse::Forbidden().body("Administrator privileges required."))
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{AppState, auth::token::Token, error::AppError, models::users::User};
+use crate::{
+    auth::{authenticate, token::Token},
+    error::AppError,
+    AppState,
+};
 use actix_web::{get, http::header, web, HttpResponse};
 use http::{HeaderMap, HeaderValue, Method};
 use oauth2::{
@@ -80,7 +84,7 @@ async fn auth(
 
     let user: GitHubUserData = serde_json::from_slice(&resp.body)?;
 
-    eprintln!("{:?}", user);
+    debug!("Authorized {:?}", user);
 
     let token = match Token::get(&pool, user.id).await? {
         Some(token) => token,
@@ -111,26 +115,8 @@ struct RevokeQuery {
 async fn revoke(pool: web::Data<PgPool>, query: web::Query<RevokeQuery>) -> Result<HttpResponse, HttpResponse> {
     let query = query.into_inner();
     let pool = pool.get_ref();
-    
-    let token = Token::new(&query.token);
-    let id = query.id;
-
-    match token.verify(pool, id).await {
-        Ok(true) => {}
-        Ok(false) => return Err(HttpResponse::Unauthorized().body("Invalid token.")),
-        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => {
-            return Err(HttpResponse::NotFound().body("User not found"))
-        },
-        Err(e) => {
-            error!("{:?}", e);
-            return Err(HttpResponse::InternalServerError().body("Unexpected Error"));
-        }
-    }
 
-    let user = match User::get_or_create(pool, id).await {
-        Ok(user) => user,
-        Err(_) => return Err(HttpResponse::InternalServerError().body("Unexpected Error")),
-    };
+    let user = authenticate(pool, query.id, &query.token).await?;
 
     let token = Token::generate(24);
     if token.register(pool, user.id).await.is_err() {
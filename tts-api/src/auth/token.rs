@@ -1,7 +1,7 @@
 use std::fmt::{self, Formatter};
 
 use rand::Rng;
-use sqlx::{query, PgPool};
+use sqlx::{pool::PoolConnection, query, PgConnection, PgPool, Transaction};
 
 use crate::error::AppError;
 
@@ -62,6 +62,24 @@ impl Token {
         Ok(())
     }
 
+    /// Replaces the user's token with a fresh one that is not shown to
+    /// anyone, so the user has to log in again to learn it. Returns false if
+    /// the user has no token.
+    pub async fn revoke(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        user_id: i64,
+    ) -> Result<bool, AppError> {
+        let token = Token::generate(24);
+        let updated = query!(
+            "UPDATE user_secret SET token = $2 WHERE users_id = $1",
+            user_id,
+            token.0
+        )
+        .execute(tx)
+        .await?;
+        Ok(updated > 0)
+    }
+
     pub async fn verify(&self, pool: &PgPool, user_id: i64) -> Result<bool, AppError> {
         let digested = query!(
             r#"
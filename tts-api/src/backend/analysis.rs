@@ -0,0 +1,182 @@
+const TEXT_ANALYSIS_SECTION: &str = "[Text analysis result]";
+const NJD_FIELD_COUNT: usize = 13;
+
+/// A word as analyzed by NJD, the text analysis stage of OpenJTalk.
+#[derive(Serialize, Debug, Clone)]
+pub struct WordAnalysis {
+    pub surface: String,
+    pub part_of_speech: String,
+    pub reading: String,
+    pub pronunciation: String,
+    pub accent_type: i32,
+    pub mora_count: i32,
+    #[serde(skip)]
+    chain_flag: i32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AccentPhrase {
+    pub pronunciation: String,
+    pub accent: i32,
+    pub mora_count: i32,
+    pub words: Vec<WordAnalysis>,
+}
+
+impl WordAnalysis {
+    /// Parses a line written by `NJD_fprint`:
+    /// `string,pos,group1,group2,group3,ctype,cform,orig,read,pron,acc/mora,chain_rule,chain_flag`.
+    pub fn parse(line: &str) -> Option<WordAnalysis> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < NJD_FIELD_COUNT {
+            return None;
+        }
+        // Only a comma itself (as surface and base form) can add fields, so
+        // the surface is read from the left and the rest from the right.
+        let (surface, pos_start) = if line.starts_with(",,") {
+            (",", 2)
+        } else {
+            (fields[0], 1)
+        };
+        let n = fields.len();
+
+        let mut accent = fields[n - 3].split('/');
+        let accent_type = accent.next()?.parse().ok()?;
+        let mora_count = accent.next()?.parse().ok()?;
+
+        let part_of_speech = fields[pos_start..pos_start + 4]
+            .iter()
+            .filter(|field| **field != "*")
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Some(WordAnalysis {
+            surface: surface.to_string(),
+            part_of_speech,
+            reading: fields[n - 5].to_string(),
+            pronunciation: fields[n - 4].to_string(),
+            accent_type,
+            mora_count,
+            chain_flag: fields[n - 1].trim().parse().ok()?,
+        })
+    }
+}
+
+/// Extracts the NJD words from an `open_jtalk` trace log.
+pub fn parse_trace(trace: &str) -> Vec<WordAnalysis> {
+    trace
+        .lines()
+        .skip_while(|line| line.trim() != TEXT_ANALYSIS_SECTION)
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(WordAnalysis::parse)
+        .collect()
+}
+
+/// Groups words into accent phrases. A word whose chain flag is not set starts
+/// a new phrase, and the phrase accent is carried by its first word.
+pub fn group_accent_phrases(words: Vec<WordAnalysis>) -> Vec<AccentPhrase> {
+    let mut phrases: Vec<AccentPhrase> = Vec::new();
+
+    for word in words {
+        match phrases.last_mut() {
+            Some(phrase) if word.chain_flag == 1 => {
+                phrase.pronunciation.push_str(&word.pronunciation);
+                phrase.mora_count += word.mora_count;
+                phrase.words.push(word);
+            }
+            _ => phrases.push(AccentPhrase {
+                pronunciation: word.pronunciation.clone(),
+                accent: word.accent_type,
+                mora_count: word.mora_count,
+                words: vec![word],
+            }),
+        }
+    }
+
+    phrases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trace of `open_jtalk -ot` for "こんにちは、世界です。".
+    const TRACE: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/open_jtalk_trace.txt"
+    ));
+
+    #[test]
+    fn parses_njd_lines() {
+        let word =
+            WordAnalysis::parse("世界,名詞,一般,*,*,*,*,世界,セカイ,セカイ,1/3,C1,0").unwrap();
+        assert_eq!(word.surface, "世界");
+        assert_eq!(word.part_of_speech, "名詞,一般");
+        assert_eq!(word.reading, "セカイ");
+        assert_eq!(word.pronunciation, "セカイ");
+        assert_eq!(word.accent_type, 1);
+        assert_eq!(word.mora_count, 3);
+        assert_eq!(word.chain_flag, 0);
+    }
+
+    #[test]
+    fn parses_a_comma_surface() {
+        let word = WordAnalysis::parse(",,記号,読点,*,*,*,*,,,、,、,0/0,*,0").unwrap();
+        assert_eq!(word.surface, ",");
+        assert_eq!(word.part_of_speech, "記号,読点");
+        assert_eq!(word.reading, "、");
+        assert_eq!(word.pronunciation, "、");
+        assert_eq!(word.mora_count, 0);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(WordAnalysis::parse("").is_none());
+        assert!(WordAnalysis::parse("世界,名詞,一般,*,*,*,*,世界,セカイ,セカイ,1/3,C1").is_none());
+        assert!(
+            WordAnalysis::parse("世界,名詞,一般,*,*,*,*,世界,セカイ,セカイ,x/3,C1,0").is_none()
+        );
+        assert!(WordAnalysis::parse("世界,名詞,一般,*,*,*,*,世界,セカイ,セカイ,1,C1,0").is_none());
+        assert!(
+            WordAnalysis::parse("世界,名詞,一般,*,*,*,*,世界,セカイ,セカイ,1/3,C1,x").is_none()
+        );
+    }
+
+    #[test]
+    fn reads_the_text_analysis_section() {
+        let words = parse_trace(TRACE);
+        let surfaces: Vec<&str> = words.iter().map(|word| word.surface.as_str()).collect();
+        assert_eq!(surfaces, vec!["こんにちは", "、", "世界", "です", "。"]);
+        assert_eq!(words[0].reading, "コンニチハ");
+        assert_eq!(words[0].pronunciation, "コンニチワ");
+        assert_eq!(words[3].part_of_speech, "助動詞");
+        assert_eq!(words[3].pronunciation, "デス’");
+        assert!(parse_trace("[Output label]\n0 100 xx^xx-sil+a=xx\n").is_empty());
+    }
+
+    #[test]
+    fn chained_words_share_an_accent_phrase() {
+        let phrases = group_accent_phrases(parse_trace(TRACE));
+        let summary: Vec<(&str, i32, i32, usize)> = phrases
+            .iter()
+            .map(|phrase| {
+                (
+                    phrase.pronunciation.as_str(),
+                    phrase.accent,
+                    phrase.mora_count,
+                    phrase.words.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("コンニチワ", 0, 5, 1),
+                ("、", 0, 0, 1),
+                ("セカイデス’", 1, 5, 2),
+                ("。", 0, 0, 1),
+            ]
+        );
+    }
+}
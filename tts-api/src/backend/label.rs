@@ -0,0 +1,178 @@
+const HTK_TIME_UNIT: f64 = 1e7;
+const OUTPUT_LABEL_SECTION: &str = "[Output label]";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PhonemeLabel {
+    pub phoneme: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Mora {
+    pub phonemes: Vec<String>,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl PhonemeLabel {
+    /// Parses a `start end full-context-label` line as written by `open_jtalk -ot`.
+    pub fn parse(line: &str) -> Option<PhonemeLabel> {
+        let mut fields = line.split_whitespace();
+        let start: f64 = fields.next()?.parse().ok()?;
+        let end: f64 = fields.next()?.parse().ok()?;
+        let context = fields.next()?;
+
+        let phoneme = context.split('-').nth(1)?.split('+').next()?;
+
+        Some(PhonemeLabel {
+            phoneme: phoneme.to_string(),
+            start: start / HTK_TIME_UNIT,
+            end: end / HTK_TIME_UNIT,
+        })
+    }
+
+    pub fn is_pause(&self) -> bool {
+        matches!(self.phoneme.as_str(), "sil" | "pau")
+    }
+
+    fn ends_mora(&self) -> bool {
+        matches!(
+            self.phoneme.as_str(),
+            "a" | "i" | "u" | "e" | "o" | "A" | "I" | "U" | "E" | "O" | "N" | "cl"
+        )
+    }
+}
+
+/// Extracts the output labels from an `open_jtalk` trace log.
+pub fn parse_trace(trace: &str) -> Vec<PhonemeLabel> {
+    trace
+        .lines()
+        .skip_while(|line| line.trim() != OUTPUT_LABEL_SECTION)
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(PhonemeLabel::parse)
+        .collect()
+}
+
+/// Groups phonemes into morae, dropping pauses.
+pub fn group_moras(labels: &[PhonemeLabel]) -> Vec<Mora> {
+    let mut moras = Vec::new();
+    let mut current: Option<Mora> = None;
+
+    for label in labels {
+        if label.is_pause() {
+            current = None;
+            continue;
+        }
+        let mora = current.get_or_insert_with(|| Mora {
+            phonemes: Vec::new(),
+            start: label.start,
+            end: label.end,
+        });
+        mora.phonemes.push(label.phoneme.clone());
+        mora.end = label.end;
+        if label.ends_mora() {
+            moras.extend(current.take());
+        }
+    }
+
+    moras
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trace of `open_jtalk -ot` for "こんにちは、世界です。".
+    const TRACE: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/open_jtalk_trace.txt"
+    ));
+
+    #[test]
+    fn parses_label_lines() {
+        let label = PhonemeLabel::parse(
+            "2050000 2650000 xx^sil-k+o=N/A:-4+1+5/B:xx-xx_xx/C:04_xx+xx/D:xx+xx_xx\
+             /E:xx_xx!xx_xx-xx/F:5_5#0_xx@1_1|1_5/G:xx_xx%xx_xx_xx/H:xx_xx\
+             /I:1-5@1+2&1-2|1+9/J:xx_xx/K:2+3-9",
+        )
+        .unwrap();
+        assert_eq!(label.phoneme, "k");
+        assert!((label.start - 0.205).abs() < 1e-9);
+        assert!((label.end - 0.265).abs() < 1e-9);
+        assert!(!label.is_pause());
+    }
+
+    #[test]
+    fn recognizes_pauses() {
+        let sil = PhonemeLabel::parse("0 2050000 xx^xx-sil+k=o/A:xx+xx+xx").unwrap();
+        let pau = PhonemeLabel::parse("100 200 w^a-pau+s=e/A:xx+xx+xx").unwrap();
+        assert!(sil.is_pause());
+        assert!(pau.is_pause());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(PhonemeLabel::parse("").is_none());
+        assert!(PhonemeLabel::parse("0 2050000").is_none());
+        assert!(PhonemeLabel::parse("start 2050000 xx^xx-sil+k=o").is_none());
+        assert!(PhonemeLabel::parse("0 end xx^xx-sil+k=o").is_none());
+        assert!(PhonemeLabel::parse("0 2050000 sil").is_none());
+    }
+
+    #[test]
+    fn reads_the_output_label_section() {
+        let labels = parse_trace(TRACE);
+        let phonemes: Vec<&str> = labels.iter().map(|label| label.phoneme.as_str()).collect();
+        assert_eq!(
+            phonemes,
+            vec![
+                "sil", "k", "o", "N", "n", "i", "ch", "i", "w", "a", "pau", "s", "e", "k", "a",
+                "i", "d", "e", "s", "U", "sil",
+            ]
+        );
+        assert_eq!(labels[0].start, 0.0);
+        assert!((labels[20].end - 1.67).abs() < 1e-9);
+        // Labels follow each other without gaps.
+        for pair in labels.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn skips_malformed_lines_in_the_trace() {
+        let trace = "[Output label]\n0 100 xx^xx-sil+a=xx\ngarbage\n100 200 xx^sil-a+xx=xx\n\n";
+        let phonemes: Vec<String> = parse_trace(trace)
+            .into_iter()
+            .map(|label| label.phoneme)
+            .collect();
+        assert_eq!(phonemes, vec!["sil", "a"]);
+        assert!(parse_trace("[Text analysis result]\n").is_empty());
+    }
+
+    #[test]
+    fn groups_phonemes_into_moras() {
+        let moras = group_moras(&parse_trace(TRACE));
+        let phonemes: Vec<String> = moras.iter().map(|mora| mora.phonemes.concat()).collect();
+        assert_eq!(
+            phonemes,
+            vec!["ko", "N", "ni", "chi", "wa", "se", "ka", "i", "de", "sU"]
+        );
+        assert!((moras[0].start - 0.205).abs() < 1e-9);
+        assert!((moras[0].end - 0.31).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pauses_drop_unfinished_moras() {
+        let labels = parse_trace(
+            "[Output label]\n\
+             0 100 xx^xx-k+pau=xx\n\
+             100 200 xx^k-pau+a=xx\n\
+             200 300 k^pau-a+xx=xx\n\n",
+        );
+        let moras = group_moras(&labels);
+        assert_eq!(moras.len(), 1);
+        assert_eq!(moras[0].phonemes, vec!["a"]);
+    }
+}
@@ -1,4 +1,9 @@
+pub mod analysis;
+pub mod label;
 pub mod openjtalk;
+pub mod prosody;
+
+use self::{analysis::WordAnalysis, label::PhonemeLabel};
 
 pub trait TtsEngine
 where
@@ -12,4 +17,6 @@ where
     fn generate_i16(&self, _text: &str) -> Result<Vec<i16>, Self::Error> {
         unimplemented!()
     }
+    fn generate_labeled(&self, text: &str) -> Result<(Vec<i16>, Vec<PhonemeLabel>), Self::Error>;
+    fn analyze(&self, text: &str) -> Result<Vec<WordAnalysis>, Self::Error>;
 }
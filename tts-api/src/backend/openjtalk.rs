@@ -1,12 +1,35 @@
-use super::TtsEngine;
+use super::{
+    analysis::{self, WordAnalysis},
+    label::{self, PhonemeLabel},
+    TtsEngine,
+};
 use crate::{config::OpenJTalkConfig, error::AppError};
-use std::io::{Read, Write};
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
 use tempfile::NamedTempFile;
 
+const NULL_DEVICE: &str = "/dev/null";
+
 pub struct OpenJTalk {
     config: OpenJTalkConfig,
 }
 
+impl OpenJTalk {
+    fn read_samples(output_file: &mut NamedTempFile) -> Result<Vec<i16>, AppError> {
+        let (_header, body) = wav::read(output_file)?;
+        if let wav::bit_depth::BitDepth::Sixteen(body) = body {
+            Ok(body)
+        } else {
+            Err(AppError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid data",
+            )))
+        }
+    }
+}
+
 impl TtsEngine for OpenJTalk {
     type Config = OpenJTalkConfig;
     type Error = AppError;
@@ -37,14 +60,47 @@ impl TtsEngine for OpenJTalk {
 
         self.config.execute(input_file.path(), output_file.path())?;
 
-        let (_header, body) = wav::read(&mut output_file)?;
-        if let wav::bit_depth::BitDepth::Sixteen(body) = body {
-            Ok(body)
-        } else {
-            Err(AppError::IoError(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "invalid data",
-            )))
-        }
+        Self::read_samples(&mut output_file)
+    }
+
+    fn generate_labeled(&self, text: &str) -> Result<(Vec<i16>, Vec<PhonemeLabel>), AppError> {
+        let mut input_file = NamedTempFile::new()?;
+        let mut output_file = NamedTempFile::new()?;
+        let mut trace_file = NamedTempFile::new()?;
+
+        input_file.write_all(text.as_bytes())?;
+
+        self.config.execute_with_trace(
+            input_file.path(),
+            output_file.path(),
+            Some(trace_file.path()),
+        )?;
+
+        let mut trace = String::new();
+        trace_file.read_to_string(&mut trace)?;
+
+        Ok((
+            Self::read_samples(&mut output_file)?,
+            label::parse_trace(&trace),
+        ))
+    }
+
+    fn analyze(&self, text: &str) -> Result<Vec<WordAnalysis>, AppError> {
+        let mut input_file = NamedTempFile::new()?;
+        let mut trace_file = NamedTempFile::new()?;
+
+        input_file.write_all(text.as_bytes())?;
+
+        // Only the trace is read, so the audio is not written anywhere.
+        self.config.execute_with_trace(
+            input_file.path(),
+            Path::new(NULL_DEVICE),
+            Some(trace_file.path()),
+        )?;
+
+        let mut trace = String::new();
+        trace_file.read_to_string(&mut trace)?;
+
+        Ok(analysis::parse_trace(&trace))
     }
 }
@@ -0,0 +1,62 @@
+use crate::config::OpenJTalkConfig;
+use std::ops::RangeInclusive;
+
+pub const RATE_RANGE: RangeInclusive<f64> = 0.25..=4.0;
+pub const PITCH_RANGE: RangeInclusive<f64> = -24.0..=24.0;
+pub const VOLUME_RANGE: RangeInclusive<f64> = -40.0..=20.0;
+
+/// Relative prosody adjustments applied on top of a voice.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Prosody {
+    /// Speaking rate multiplier.
+    pub rate: f64,
+    /// Pitch shift in semitones.
+    pub pitch: f64,
+    /// Volume change in dB.
+    pub volume: f64,
+}
+
+impl Default for Prosody {
+    fn default() -> Prosody {
+        Prosody {
+            rate: 1.0,
+            pitch: 0.0,
+            volume: 0.0,
+        }
+    }
+}
+
+impl Prosody {
+    pub fn validate(&self) -> Result<(), String> {
+        if !RATE_RANGE.contains(&self.rate) {
+            return Err(format!("Prosody rate out of range: {}", self.rate));
+        }
+        if !PITCH_RANGE.contains(&self.pitch) {
+            return Err(format!("Prosody pitch out of range: {}", self.pitch));
+        }
+        if !VOLUME_RANGE.contains(&self.volume) {
+            return Err(format!("Prosody volume out of range: {}", self.volume));
+        }
+        Ok(())
+    }
+
+    /// Combines a nested adjustment with this one.
+    pub fn then(&self, inner: &Prosody) -> Prosody {
+        Prosody {
+            rate: self.rate * inner.rate,
+            pitch: self.pitch + inner.pitch,
+            volume: self.volume + inner.volume,
+        }
+    }
+
+    pub fn apply(&self, config: &OpenJTalkConfig) -> OpenJTalkConfig {
+        let mut config = config.clone();
+        config.speed_rate *= self.rate;
+        config.additional_half_tone += self.pitch;
+        if self.volume != 0.0 {
+            config.volume = Some(config.volume.unwrap_or_default() + self.volume);
+        }
+        config
+    }
+}
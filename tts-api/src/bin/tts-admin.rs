@@ -0,0 +1,288 @@
+#![warn(clippy::all)]
+
+use anyhow::{anyhow, bail, Result};
+use dotenv::dotenv;
+use sqlx::PgPool;
+use std::{env, fs, path::PathBuf};
+use structopt::StructOpt;
+use tts_api::{
+    admin,
+    audio::{
+        format::{self, OutputFormat},
+        process::PostProcess,
+    },
+    auth::token::Token,
+    checks,
+    config::Config,
+    dictionary::ActiveDictionary,
+    error::AppError,
+    migrate,
+    models::{
+        account_status::AccountStatus,
+        plan::Plan,
+        role::Role,
+        users::{User, UserChanges},
+    },
+    text::{normalize::Normalizer, Preprocessor},
+    tts::{
+        request::{self, AudioFormat, SynthesisOptions},
+        synthesis,
+    },
+};
+
+/// The admin id audit entries of changes made here are recorded with, as
+/// tts-admin runs without an admin account.
+const CLI_ADMIN_ID: i64 = 0;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tts-admin", about = "Maintenance tasks for tts-api")]
+enum Command {
+    /// Applies pending database migrations
+    Migrate {
+        /// Only list pending migrations
+        #[structopt(long)]
+        check: bool,
+    },
+    /// Creates a user and issues their token
+    CreateUser { id: i64 },
+    /// Issues a new token for a user, replacing the current one
+    IssueToken { id: i64 },
+    /// Revokes a user's token; they get a new one by logging in again
+    RevokeToken {
+        id: i64,
+        /// Recorded in the audit log
+        #[structopt(long)]
+        reason: Option<String>,
+    },
+    /// Changes a user's character limit, plan, role or account status
+    SetLimits {
+        id: i64,
+        #[structopt(long)]
+        character_limit: Option<i64>,
+        /// free, standard or premium
+        #[structopt(long)]
+        plan: Option<Plan>,
+        /// user or admin
+        #[structopt(long)]
+        role: Option<Role>,
+        /// active, suspended or banned
+        #[structopt(long)]
+        status: Option<AccountStatus>,
+        /// Recorded in the audit log
+        #[structopt(long)]
+        reason: Option<String>,
+    },
+    /// Resets the characters a user has used against their limit
+    ResetQuota {
+        id: i64,
+        /// Recorded in the audit log
+        #[structopt(long)]
+        reason: Option<String>,
+    },
+    /// Checks the OpenJTalk installation against the config
+    Check,
+    /// Synthesizes a phrase to a file, encoded by its extension
+    Synthesize {
+        output: PathBuf,
+        #[structopt(long, default_value = "こんにちは。音声合成のテストです。")]
+        text: String,
+        #[structopt(long)]
+        voice: Option<String>,
+    },
+}
+
+async fn connect() -> Result<PgPool> {
+    let database_url = env::var("DATABASE_URL").map_err(|_| anyhow!("DATABASE_URL is not set"))?;
+    Ok(PgPool::new(&database_url).await?)
+}
+
+async fn existing_user(pool: &PgPool, id: i64) -> Result<User> {
+    match User::get(pool, id).await {
+        Ok(user) => Ok(user),
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => bail!("User {} not found", id),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn print_user(user: &User) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(user)?);
+    Ok(())
+}
+
+async fn migrate(check: bool) -> Result<()> {
+    let pool = connect().await?;
+    if check {
+        let pending = migrate::pending(&pool).await?;
+        if pending.is_empty() {
+            println!("No pending migrations");
+        }
+        for migration in pending {
+            println!("Pending {}", migration.name);
+        }
+        return Ok(());
+    }
+    let applied = migrate::run(&pool).await?;
+    if applied.is_empty() {
+        println!("No pending migrations");
+    }
+    for name in applied {
+        println!("Applied {}", name);
+    }
+    Ok(())
+}
+
+async fn create_user(id: i64) -> Result<()> {
+    let pool = connect().await?;
+    match User::get(&pool, id).await {
+        Ok(_) => bail!("User {} already exists", id),
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => {}
+        Err(e) => return Err(e.into()),
+    }
+    User::create(&pool, id).await?;
+    let token = Token::generate(24);
+    token.register(&pool, id).await?;
+    print_user(&User::get(&pool, id).await?)?;
+    println!("Token: {}", token);
+    Ok(())
+}
+
+async fn issue_token(id: i64) -> Result<()> {
+    let pool = connect().await?;
+    existing_user(&pool, id).await?;
+    let token = Token::generate(24);
+    token.register(&pool, id).await?;
+    println!("Token: {}", token);
+    Ok(())
+}
+
+async fn revoke_token(id: i64, reason: Option<String>) -> Result<()> {
+    let pool = connect().await?;
+    if !admin::revoke_token(&pool, CLI_ADMIN_ID, id, reason.as_deref()).await? {
+        bail!("User {} has no token", id);
+    }
+    println!("Revoked the token of user {}", id);
+    Ok(())
+}
+
+async fn set_limits(id: i64, changes: UserChanges, reason: Option<String>) -> Result<()> {
+    if let Some(limit) = changes.character_limit {
+        if limit < 0 {
+            bail!("--character-limit must not be negative");
+        }
+    }
+    let pool = connect().await?;
+    existing_user(&pool, id).await?;
+    let user = admin::update_user(&pool, CLI_ADMIN_ID, id, &changes, reason.as_deref()).await?;
+    print_user(&user)
+}
+
+async fn reset_quota(id: i64, reason: Option<String>) -> Result<()> {
+    let pool = connect().await?;
+    existing_user(&pool, id).await?;
+    let user = admin::reset_usage(&pool, CLI_ADMIN_ID, id, reason.as_deref()).await?;
+    print_user(&user)
+}
+
+async fn check(config: &Config) -> Result<()> {
+    let checks = checks::all(config).await;
+    for check in &checks {
+        let result = if check.ok { "ok" } else { "FAILED" };
+        println!("{:<24} {:<6} {}", check.name, result, check.detail);
+    }
+    if checks.iter().any(|check| !check.ok) {
+        bail!("Some checks failed");
+    }
+    Ok(())
+}
+
+/// Synthesizes with the system dictionary and default processing, without
+/// any user or global dictionary.
+async fn synthesize(
+    config: &Config,
+    output: PathBuf,
+    text: String,
+    voice: Option<String>,
+) -> Result<()> {
+    let extension = output
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    let audio_format = [
+        AudioFormat::Wav,
+        AudioFormat::Ogg,
+        AudioFormat::Flac,
+        AudioFormat::Ulaw,
+        AudioFormat::Alaw,
+    ]
+    .iter()
+    .copied()
+    .find(|format| format.extension() == extension)
+    .ok_or_else(|| anyhow!("Unsupported file extension: {:?}", extension))?;
+
+    let options = SynthesisOptions {
+        text,
+        voice,
+        ..SynthesisOptions::default()
+    };
+    let input = options.input(config).map_err(|e| anyhow!(e))?;
+    let normalizer = Normalizer::from_config(&config.normalize);
+    let preprocessor = Preprocessor::new(&normalizer, true);
+    let parts = request::parts(input, &preprocessor, config, &ActiveDictionary::new())?;
+
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let mut rendered = synthesis::render(&config.synthesis, parts, false).await?;
+    rendered.post_process(
+        sampling_rate,
+        &config.post_process(options.voice.as_deref(), &PostProcess::default()),
+    );
+    let format = audio_format
+        .resolve(&OutputFormat::default(), sampling_rate)
+        .map_err(|e| anyhow!(e))?;
+    let samples = format::convert(&rendered.samples, sampling_rate, 1, &format);
+    let data = audio_format.encode(&samples, &format, None)?;
+    fs::write(&output, &data)?;
+
+    println!(
+        "Wrote {} ({:.2} s, {} bytes)",
+        output.display(),
+        rendered.samples.len() as f64 / sampling_rate as f64,
+        data.len()
+    );
+    Ok(())
+}
+
+#[actix_web::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    env_logger::init();
+
+    match Command::from_args() {
+        Command::Migrate { check } => migrate(check).await,
+        Command::CreateUser { id } => create_user(id).await,
+        Command::IssueToken { id } => issue_token(id).await,
+        Command::RevokeToken { id, reason } => revoke_token(id, reason).await,
+        Command::SetLimits {
+            id,
+            character_limit,
+            plan,
+            role,
+            status,
+            reason,
+        } => {
+            let changes = UserChanges {
+                character_limit,
+                plan,
+                role,
+                status,
+            };
+            set_limits(id, changes, reason).await
+        }
+        Command::ResetQuota { id, reason } => reset_quota(id, reason).await,
+        Command::Check => check(&Config::from_config()?).await,
+        Command::Synthesize {
+            output,
+            text,
+            voice,
+        } => synthesize(&Config::from_config()?, output, text, voice).await,
+    }
+}
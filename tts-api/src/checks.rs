@@ -0,0 +1,149 @@
+//! Checks of the OpenJTalk installation against the config.
+
+use crate::{
+    backend::{openjtalk::OpenJTalk, TtsEngine},
+    config::{Config, OpenJTalkConfig},
+    trace,
+};
+use sqlx::PgPool;
+use std::{fs::File, path::Path, process::Command, time::Instant};
+
+/// Files a compiled MeCab dictionary directory must contain.
+const DICTIONARY_FILES: &[&str] = &["sys.dic", "unk.dic", "char.bin", "matrix.bin"];
+const TEST_PHRASE: &str = "テスト";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(name: &str, detail: String) -> Check {
+        Check {
+            name: name.to_string(),
+            ok: true,
+            detail,
+        }
+    }
+
+    fn fail(name: &str, detail: String) -> Check {
+        Check {
+            name: name.to_string(),
+            ok: false,
+            detail,
+        }
+    }
+}
+
+/// Finds `open_jtalk` on the `PATH` and reads its version from the usage
+/// text it prints.
+pub fn open_jtalk() -> Check {
+    let output = match Command::new("open_jtalk").arg("-h").output() {
+        Ok(output) => output,
+        Err(e) => return Check::fail("open_jtalk", format!("Failed to run open_jtalk: {}", e)),
+    };
+    let usage = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let version = usage
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Version "))
+        .and_then(|rest| rest.split_whitespace().next());
+    match version {
+        Some(version) => Check::pass("open_jtalk", format!("Version {}", version)),
+        None => Check::fail(
+            "open_jtalk",
+            "open_jtalk ran but printed no version".to_string(),
+        ),
+    }
+}
+
+fn readable(path: &Path) -> Result<u64, String> {
+    let size = File::open(path)
+        .and_then(|file| file.metadata())
+        .map(|metadata| metadata.len())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if size == 0 {
+        return Err(format!("{}: empty file", path.display()));
+    }
+    Ok(size)
+}
+
+pub async fn postgres(pool: &PgPool) -> Check {
+    let started = Instant::now();
+    match sqlx::query("SELECT 1").execute(pool).await {
+        Ok(_) => Check::pass(
+            "postgres",
+            format!("Connected in {} ms", started.elapsed().as_millis()),
+        ),
+        Err(e) => Check::fail("postgres", e.to_string()),
+    }
+}
+
+/// Checks that a dictionary directory holds a compiled dictionary.
+pub fn dictionary(name: &str, path: &Path) -> Check {
+    let missing: Vec<String> = DICTIONARY_FILES
+        .iter()
+        .filter_map(|file| readable(&path.join(file)).err())
+        .collect();
+    if missing.is_empty() {
+        Check::pass(name, path.display().to_string())
+    } else {
+        Check::fail(name, missing.join("; "))
+    }
+}
+
+/// Checks the htsvoice file of the default voice and of every named voice.
+pub fn voices(config: &Config) -> Vec<Check> {
+    let mut voices: Vec<_> = config
+        .voices
+        .iter()
+        .map(|(name, voice)| (format!("voice:{}", name), &voice.hts_path))
+        .collect();
+    voices.sort();
+    voices.insert(0, ("voice".to_string(), &config.openjtalk.hts_path));
+    voices
+        .into_iter()
+        .map(|(name, path)| match readable(path) {
+            Ok(size) => Check::pass(&name, format!("{} ({} bytes)", path.display(), size)),
+            Err(e) => Check::fail(&name, e),
+        })
+        .collect()
+}
+
+/// Synthesizes a short phrase with `jtalk_config`.
+pub async fn synthesis(jtalk_config: OpenJTalkConfig) -> Check {
+    let started = Instant::now();
+    let engine = match OpenJTalk::from_config(jtalk_config) {
+        Ok(engine) => engine,
+        Err(e) => return Check::fail("synthesis", e.to_string()),
+    };
+    match trace::block(move || engine.generate_i16(TEST_PHRASE)).await {
+        Ok(samples) if !samples.is_empty() => Check::pass(
+            "synthesis",
+            format!(
+                "{} samples in {} ms",
+                samples.len(),
+                started.elapsed().as_millis()
+            ),
+        ),
+        Ok(_) => Check::fail("synthesis", "open_jtalk produced no audio".to_string()),
+        Err(e) => Check::fail("synthesis", e.to_string()),
+    }
+}
+
+/// Runs every check of the installation in order, synthesizing with the
+/// default voice and system dictionary.
+pub async fn all(config: &Config) -> Vec<Check> {
+    let mut checks = vec![
+        open_jtalk(),
+        dictionary("dictionary", &config.openjtalk.dictionary),
+    ];
+    checks.extend(voices(config));
+    checks.push(synthesis(config.openjtalk.clone()).await);
+    checks
+}
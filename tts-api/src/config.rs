@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     env,
     fmt::Debug,
     fs,
@@ -6,11 +7,55 @@ use std::{
     process::Command,
 };
 
-use crate::error::AppError;
+use crate::{
+    audio::process::{Loudness, LoudnessMethod, PostProcess},
+    error::AppError,
+    metrics,
+    models::plan::Plan,
+    shutdown, trace,
+};
+
+const MIB: i64 = 1024 * 1024;
+const DEFAULT_PUBLIC_URL: &str = "http://localhost:8080";
+/// Bytes allowed per character of text in a JSON body, the length of a
+/// `\uXXXX` escape.
+const JSON_BYTES_PER_CHAR: usize = 6;
+/// Bytes allowed for the fields around each text in a JSON body.
+const JSON_ITEM_OVERHEAD: usize = 1024;
 
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct Config {
     pub openjtalk: OpenJTalkConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub synthesis: SynthesisConfig,
+    #[serde(default)]
+    pub plans: PlansConfig,
+    #[serde(default)]
+    pub normalize: NormalizeConfig,
+    #[serde(default)]
+    pub global_dictionary: GlobalDictionaryConfig,
+    #[serde(default)]
+    pub postprocess: PostProcess,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub voices: HashMap<String, VoiceConfig>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -26,6 +71,29 @@ pub struct OpenJTalkConfig {
     pub unvoiced_threshold: f64,
     pub spectrum_weight: f64,
     pub spectrum_f0: f64,
+    pub volume: Option<f64>,
+}
+
+/// A named voice. Unset parameters fall back to `[openjtalk]`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VoiceConfig {
+    pub hts_path: PathBuf,
+    pub all_pass: Option<f64>,
+    pub postfilter_coef: Option<f64>,
+    pub speed_rate: Option<f64>,
+    pub additional_half_tone: Option<f64>,
+    pub unvoiced_threshold: Option<f64>,
+    pub spectrum_weight: Option<f64>,
+    pub spectrum_f0: Option<f64>,
+    pub volume: Option<f64>,
+    pub loudness: Option<LoudnessMethod>,
+    pub loudness_target: Option<f64>,
+    pub trim: Option<bool>,
+    pub trim_threshold: Option<f64>,
+    pub fade_in_ms: Option<u32>,
+    pub fade_out_ms: Option<u32>,
+    pub pad_start_ms: Option<u32>,
+    pub pad_end_ms: Option<u32>,
 }
 
 impl Default for OpenJTalkConfig {
@@ -42,6 +110,363 @@ impl Default for OpenJTalkConfig {
             unvoiced_threshold: 0.5,
             spectrum_weight: 1.0,
             spectrum_f0: 1.0,
+            volume: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SynthesisConfig {
+    pub max_segment_length: usize,
+    pub sentence_silence_ms: u32,
+    pub clause_silence_ms: u32,
+    pub concurrency: usize,
+}
+
+impl Default for SynthesisConfig {
+    fn default() -> SynthesisConfig {
+        SynthesisConfig {
+            max_segment_length: 100,
+            sentence_silence_ms: 300,
+            clause_silence_ms: 100,
+            concurrency: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct NormalizeConfig {
+    pub width: bool,
+    pub url: bool,
+    pub url_replacement: String,
+    pub discord: bool,
+    pub mention_replacement: String,
+    pub channel_replacement: String,
+    pub role_replacement: String,
+    pub emoji: bool,
+    pub emoji_names: HashMap<String, String>,
+    pub repeat: bool,
+    pub max_repeat: usize,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> NormalizeConfig {
+        NormalizeConfig {
+            width: true,
+            url: true,
+            url_replacement: "URL省略".to_string(),
+            discord: true,
+            mention_replacement: "メンション".to_string(),
+            channel_replacement: "チャンネル".to_string(),
+            role_replacement: "ロール".to_string(),
+            emoji: true,
+            emoji_names: HashMap::new(),
+            repeat: true,
+            max_repeat: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GlobalDictionaryConfig {
+    pub compiler: PathBuf,
+    pub output_dir: PathBuf,
+    pub cost: i32,
+}
+
+impl Default for GlobalDictionaryConfig {
+    fn default() -> GlobalDictionaryConfig {
+        GlobalDictionaryConfig {
+            compiler: PathBuf::from("mecab-dict-index"),
+            output_dir: PathBuf::from("resources/dictionary"),
+            cost: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Base URL of this server, used for links handed out to clients.
+    /// Falls back to the deprecated `[webhooks] public_url`.
+    pub public_url: Option<String>,
+}
+
+impl ServerConfig {
+    /// Returns the absolute URL of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        let base = self.public_url.as_deref().unwrap_or(DEFAULT_PUBLIC_URL);
+        format!("{}{}", base.trim_end_matches('/'), path)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// Apply pending migrations at startup. When disabled, the server
+    /// refuses to start until they are applied.
+    pub auto_migrate: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig { auto_migrate: true }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// How long a readiness result is reused, so that frequent probes do not
+    /// each run a test synthesis.
+    pub cache_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> HealthConfig {
+        HealthConfig { cache_secs: 10 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long in-flight requests and jobs may run after SIGTERM before
+    /// they are cut off.
+    pub deadline_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> ShutdownConfig {
+        ShutdownConfig { deadline_secs: 30 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// `json` writes one object per line for log collectors.
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            format: LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    pub max_items: usize,
+    /// Number of items rendered at a time by one batch request.
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> BatchConfig {
+        BatchConfig {
+            max_items: 500,
+            concurrency: 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// Number of jobs rendered at a time by this process.
+    pub workers: usize,
+    pub poll_interval_ms: u64,
+    /// A running job whose worker sent no heartbeat for this long is retried.
+    pub stale_after_secs: i64,
+    pub max_attempts: i32,
+    /// Finished jobs and their audio are deleted after this many days.
+    pub retention_days: i64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> JobsConfig {
+        JobsConfig {
+            workers: 2,
+            poll_interval_ms: 1000,
+            stale_after_secs: 60,
+            max_attempts: 3,
+            retention_days: 7,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    /// Deprecated in favor of `[server] public_url`, which it stands in for
+    /// when that is unset.
+    pub public_url: Option<String>,
+    pub max_attempts: i32,
+    /// Delay before the first retry, doubled for each further retry up to
+    /// `max_delay_secs`.
+    pub base_delay_secs: i64,
+    pub max_delay_secs: i64,
+    pub timeout_secs: u64,
+    pub poll_interval_ms: u64,
+    /// Hosts callbacks may reach even though they resolve to loopback,
+    /// private or link-local addresses, e.g. receivers on an internal
+    /// network.
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> WebhooksConfig {
+        WebhooksConfig {
+            public_url: None,
+            max_attempts: 8,
+            base_delay_secs: 10,
+            max_delay_secs: 3600,
+            timeout_secs: 10,
+            poll_interval_ms: 1000,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Root directory of the local backend.
+    pub path: PathBuf,
+    pub s3: S3Config,
+    /// Stored clips are deleted this long after creation.
+    pub retention_days: i64,
+    pub cleanup_interval_secs: u64,
+    /// Key for signing share URLs, falling back to `AUDIO_SIGNING_KEY`.
+    /// Sharing is disabled when neither is set.
+    pub signing_key: String,
+    pub max_share_secs: i64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            backend: StorageBackend::Local,
+            path: PathBuf::from("resources/audio"),
+            s3: S3Config::default(),
+            retention_days: 30,
+            cleanup_interval_secs: 3600,
+            signing_key: String::new(),
+            max_share_secs: 7 * 24 * 3600,
+        }
+    }
+}
+
+/// An S3-compatible bucket, addressed path-style. Empty keys fall back to
+/// `S3_ACCESS_KEY` and `S3_SECRET_KEY`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub timeout_secs: u64,
+}
+
+impl Default for S3Config {
+    fn default() -> S3Config {
+        S3Config {
+            endpoint: "http://localhost:9000".to_string(),
+            bucket: "tts-audio".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PlansConfig {
+    pub free: PlanLimits,
+    pub standard: PlanLimits,
+    pub premium: PlanLimits,
+}
+
+impl Default for PlansConfig {
+    fn default() -> PlansConfig {
+        PlansConfig {
+            free: PlanLimits {
+                max_text_length: 200,
+                max_dictionary_entries: 100,
+                max_storage_bytes: 50 * MIB,
+            },
+            standard: PlanLimits {
+                max_text_length: 1000,
+                max_dictionary_entries: 1000,
+                max_storage_bytes: 1024 * MIB,
+            },
+            premium: PlanLimits {
+                max_text_length: 5000,
+                max_dictionary_entries: 5000,
+                max_storage_bytes: 10240 * MIB,
+            },
+        }
+    }
+}
+
+impl PlansConfig {
+    pub fn limits(&self, plan: Plan) -> &PlanLimits {
+        match plan {
+            Plan::Free => &self.free,
+            Plan::Standard => &self.standard,
+            Plan::Premium => &self.premium,
+        }
+    }
+
+    /// The longest text any plan allows.
+    pub fn max_text_length(&self) -> usize {
+        self.free
+            .max_text_length
+            .max(self.standard.max_text_length)
+            .max(self.premium.max_text_length)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlanLimits {
+    pub max_text_length: usize,
+    pub max_dictionary_entries: usize,
+    pub max_storage_bytes: i64,
+}
+
+impl Default for PlanLimits {
+    fn default() -> PlanLimits {
+        PlanLimits {
+            max_text_length: 200,
+            max_dictionary_entries: 100,
+            max_storage_bytes: 50 * MIB,
         }
     }
 }
@@ -60,16 +485,131 @@ impl Config {
         let config = fs::read_to_string(&config_file)
             .map_err(|e| AppError::FileNotFound(config_file.clone(), e))?;
 
-        let config: Config = toml::from_str(&config)
+        let mut config: Config = toml::from_str(&config)
             .map_err(|e| AppError::ConfigDeserializationError(config_file, e))?;
+        if config.server.public_url.is_none() {
+            config.server.public_url = config.webhooks.public_url.clone();
+        }
 
         Ok(config)
     }
+
+    /// Resolves a voice from the registry, or the default `[openjtalk]` voice
+    /// when `name` is `None`.
+    pub fn voice(&self, name: Option<&str>) -> Option<OpenJTalkConfig> {
+        let name = match name {
+            Some(name) => name,
+            None => return Some(self.openjtalk.clone()),
+        };
+        let voice = self.voices.get(name)?;
+        let base = &self.openjtalk;
+        Some(OpenJTalkConfig {
+            dictionary: base.dictionary.clone(),
+            hts_path: voice.hts_path.clone(),
+            sampling: base.sampling,
+            frame_period: base.frame_period,
+            all_pass: voice.all_pass.or(base.all_pass),
+            postfilter_coef: voice.postfilter_coef.unwrap_or(base.postfilter_coef),
+            speed_rate: voice.speed_rate.unwrap_or(base.speed_rate),
+            additional_half_tone: voice
+                .additional_half_tone
+                .unwrap_or(base.additional_half_tone),
+            unvoiced_threshold: voice.unvoiced_threshold.unwrap_or(base.unvoiced_threshold),
+            spectrum_weight: voice.spectrum_weight.unwrap_or(base.spectrum_weight),
+            spectrum_f0: voice.spectrum_f0.unwrap_or(base.spectrum_f0),
+            volume: voice.volume.or(base.volume),
+        })
+    }
+
+    /// The post-processing set on a voice, without fallbacks.
+    fn voice_post_process(&self, name: Option<&str>) -> PostProcess {
+        let voice = match name.and_then(|name| self.voices.get(name)) {
+            Some(voice) => voice,
+            None => return PostProcess::default(),
+        };
+        PostProcess {
+            loudness: voice.loudness,
+            loudness_target: voice.loudness_target,
+            peak_ceiling: None,
+            trim: voice.trim,
+            trim_threshold: voice.trim_threshold,
+            fade_in_ms: voice.fade_in_ms,
+            fade_out_ms: voice.fade_out_ms,
+            pad_start_ms: voice.pad_start_ms,
+            pad_end_ms: voice.pad_end_ms,
+        }
+    }
+
+    /// Resolves the loudness normalization applied to speech in a voice.
+    pub fn loudness(&self, name: Option<&str>) -> Option<Loudness> {
+        self.voice_post_process(name)
+            .or(&self.postprocess)
+            .loudness()
+    }
+
+    /// Resolves the processing applied to a whole track in `voice`, from the
+    /// request, then the voice, then `[postprocess]`. Loudness normalization
+    /// from the config is applied per voice instead (see
+    /// [`Config::loudness`]), so only a requested normalization carries over.
+    pub fn post_process(&self, voice: Option<&str>, request: &PostProcess) -> PostProcess {
+        let defaults = PostProcess {
+            loudness: None,
+            loudness_target: None,
+            ..self.voice_post_process(voice).or(&self.postprocess)
+        };
+        request.or(&defaults)
+    }
+
+    /// The size limit for JSON bodies carrying `texts` texts of the longest
+    /// length any plan allows, split across up to `items` items.
+    pub fn json_limit(&self, texts: usize, items: usize) -> usize {
+        texts * self.plans.max_text_length() * JSON_BYTES_PER_CHAR
+            + items.max(1) * JSON_ITEM_OVERHEAD
+    }
 }
 
 impl OpenJTalkConfig {
+    pub fn sampling_rate(&self) -> u32 {
+        self.sampling.unwrap_or(48000) as u32
+    }
+
+    /// Describes the synthesis parameters as `name=value` pairs.
+    pub fn parameters(&self) -> String {
+        let mut parameters = vec![
+            format!("all_pass={}", self.all_pass.unwrap_or_default()),
+            format!("postfilter_coef={}", self.postfilter_coef),
+            format!("speed_rate={}", self.speed_rate),
+            format!("additional_half_tone={}", self.additional_half_tone),
+            format!("unvoiced_threshold={}", self.unvoiced_threshold),
+            format!("spectrum_weight={}", self.spectrum_weight),
+            format!("spectrum_f0={}", self.spectrum_f0),
+        ];
+        if let Some(volume) = self.volume {
+            parameters.push(format!("volume={}", volume));
+        }
+        parameters.join(" ")
+    }
+
     pub fn execute<P: AsRef<Path>>(&self, input_path: P, output_path: P) -> Result<(), AppError> {
-        let output = Command::new("open_jtalk")
+        self.execute_with_trace(input_path.as_ref(), output_path.as_ref(), None)
+    }
+
+    /// Runs `open_jtalk`, additionally writing its trace log (text analysis
+    /// result and output labels) to `trace_path` when given.
+    pub fn execute_with_trace(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        trace_path: Option<&Path>,
+    ) -> Result<(), AppError> {
+        let mut command = Command::new("open_jtalk");
+        if let Some(sampling) = self.sampling {
+            command.arg("-s").arg(format!("{}", sampling));
+        }
+        if let Some(frame_period) = self.frame_period {
+            command.arg("-p").arg(format!("{}", frame_period));
+        }
+        command
             .arg("-x")
             .arg(&self.dictionary)
             .arg("-m")
@@ -87,16 +627,29 @@ impl OpenJTalkConfig {
             .arg("-jm")
             .arg(format!("{}", self.spectrum_weight))
             .arg("-jf")
-            .arg(format!("{}", self.spectrum_f0))
-            .arg("-ow")
-            .arg(output_path.as_ref())
-            .arg(input_path.as_ref())
-            .output()
-            .map_err(|e| AppError::CommandSpawnError(e))?;
+            .arg(format!("{}", self.spectrum_f0));
+        if let Some(volume) = self.volume {
+            command.arg("-g").arg(format!("{}", volume));
+        }
+        if let Some(trace_path) = trace_path {
+            command.arg("-ot").arg(trace_path);
+        }
+        let _stage = trace::stage("open_jtalk");
+        command.arg("-ow").arg(output_path).arg(input_path);
+        let output = shutdown::output(&mut command).map_err(|e| {
+            metrics::OPEN_JTALK_FAILURES.inc(&["spawn"]);
+            AppError::CommandSpawnError(e)
+        })?;
 
         if output.status.success() {
             Ok(())
         } else {
+            warn!("open_jtalk failed with {}", output.status);
+            let code = output
+                .status
+                .code()
+                .map_or_else(|| "signal".to_string(), |code| code.to_string());
+            metrics::OPEN_JTALK_FAILURES.inc(&[&code]);
             Err(AppError::CommandError(
                 String::from_utf8_lossy(&output.stdout).into(),
                 String::from_utf8_lossy(&output.stderr).into(),
@@ -0,0 +1,134 @@
+use crate::{
+    config::{GlobalDictionaryConfig, OpenJTalkConfig},
+    error::AppError,
+    metrics,
+    models::global_dictionary::GlobalDictionaryEntry,
+    shutdown,
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, RwLock},
+};
+
+/// The compiled dictionary directory currently handed to `open_jtalk`.
+///
+/// Swapping only replaces the path, so syntheses that already resolved their
+/// config keep reading the previous version until they finish.
+#[derive(Clone, Default)]
+pub struct ActiveDictionary {
+    path: Arc<RwLock<Option<PathBuf>>>,
+}
+
+impl ActiveDictionary {
+    pub fn new() -> ActiveDictionary {
+        ActiveDictionary::default()
+    }
+
+    pub fn get(&self) -> Option<PathBuf> {
+        self.path.read().unwrap().clone()
+    }
+
+    pub fn swap(&self, path: PathBuf) {
+        *self.path.write().unwrap() = Some(path);
+    }
+
+    pub fn resolve(&self, config: &OpenJTalkConfig) -> OpenJTalkConfig {
+        let mut config = config.clone();
+        if let Some(path) = self.get() {
+            config.dictionary = path;
+        }
+        config
+    }
+}
+
+/// Builds a dictionary directory for `version` containing the system dictionary
+/// and a MeCab user dictionary compiled from `entries`.
+///
+/// The directory is assembled in a fresh temporary directory and renamed into
+/// place, so an existing version directory is always complete and is reused as
+/// is, and concurrent compiles of the same version do not share files.
+pub fn compile(
+    config: &GlobalDictionaryConfig,
+    system_dictionary: &Path,
+    version: i32,
+    entries: &[GlobalDictionaryEntry],
+) -> Result<PathBuf, AppError> {
+    fs::create_dir_all(&config.output_dir)?;
+    let output_dir = fs::canonicalize(&config.output_dir)?;
+    let target = output_dir.join(format!("v{}", version));
+    if target.exists() {
+        metrics::CACHE_REQUESTS.inc(&["dictionary", "hit"]);
+        return Ok(target);
+    }
+    metrics::CACHE_REQUESTS.inc(&["dictionary", "miss"]);
+
+    let staging_dir = tempfile::Builder::new()
+        .prefix(&format!(".v{}.", version))
+        .tempdir_in(&output_dir)?;
+    let staging = staging_dir.path();
+
+    for entry in fs::read_dir(system_dictionary)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), staging.join(entry.file_name()))?;
+        }
+    }
+
+    let source = staging.join("user.csv");
+    let mut csv = fs::File::create(&source)?;
+    for entry in entries {
+        let reading = entry.katakana();
+        writeln!(
+            csv,
+            "{surface},,,{cost},{pos},*,*,{surface},{reading},{reading},{accent}/{mora},*",
+            surface = entry.surface,
+            cost = config.cost,
+            pos = entry.part_of_speech,
+            reading = reading,
+            accent = entry.accent_type,
+            mora = entry.mora_count(),
+        )?;
+    }
+    drop(csv);
+
+    if !entries.is_empty() {
+        let mut command = Command::new(&config.compiler);
+        command
+            .arg("-d")
+            .arg(system_dictionary)
+            .arg("-u")
+            .arg(staging.join("user.dic"))
+            .arg("-f")
+            .arg("utf-8")
+            .arg("-t")
+            .arg("utf-8")
+            .arg(&source);
+        let output = shutdown::output(&mut command).map_err(AppError::CommandSpawnError)?;
+        if !output.status.success() {
+            return Err(AppError::CommandError(
+                String::from_utf8_lossy(&output.stdout).into(),
+                String::from_utf8_lossy(&output.stderr).into(),
+                output.status.code(),
+            ));
+        }
+
+        let mut dicrc = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(staging.join("dicrc"))?;
+        writeln!(dicrc, "userdic = {}", target.join("user.dic").display())?;
+    }
+
+    match fs::rename(&staging, &target) {
+        Ok(()) => {
+            staging_dir.into_path();
+            Ok(target)
+        }
+        // Another compile of the same version finished first.
+        Err(_) if target.exists() => Ok(target),
+        Err(e) => Err(e.into()),
+    }
+}
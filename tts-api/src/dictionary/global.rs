@@ -0,0 +1,150 @@
+use crate::{
+    auth::{authenticate_admin, Credentials},
+    config::Config,
+    dictionary::{self, ActiveDictionary},
+    error::AppError,
+    models::{
+        audit::AuditEntry,
+        global_dictionary::{GlobalDictionaryEntry, GlobalDictionaryVersion},
+    },
+};
+use actix_web::{get, post, web, HttpResponse};
+use serde_json::json;
+use sqlx::{pool::PoolConnection, PgConnection, PgPool, Transaction};
+use std::path::PathBuf;
+
+/// Compiles `version` and marks it active in `tx`. The compiled dictionary
+/// is swapped in with [`swap`] once the transaction commits.
+async fn compile_and_activate(
+    tx: &mut Transaction<PoolConnection<PgConnection>>,
+    config: &Config,
+    version: i32,
+    entries: Vec<GlobalDictionaryEntry>,
+) -> Result<PathBuf, AppError> {
+    let path = dictionary::build(config, version, entries).await?;
+    GlobalDictionaryVersion::activate(tx, version).await?;
+    Ok(path)
+}
+
+fn swap(active: &ActiveDictionary, version: i32, path: PathBuf) {
+    active.swap(path);
+    info!("Activated global dictionary v{}", version);
+}
+
+#[get("/admin/dictionary/versions")]
+async fn list_versions(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    if let Err(e) = authenticate_admin(pool, query.id, &query.token).await {
+        return Ok(e);
+    }
+
+    let versions = GlobalDictionaryVersion::list(pool).await?;
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+#[get("/admin/dictionary/versions/{version}")]
+async fn list_version_entries(
+    pool: web::Data<PgPool>,
+    version: web::Path<i32>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    if let Err(e) = authenticate_admin(pool, query.id, &query.token).await {
+        return Ok(e);
+    }
+
+    let entries = GlobalDictionaryEntry::list(pool, version.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[post("/admin/dictionary/versions")]
+async fn create_version(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    active: web::Data<ActiveDictionary>,
+    query: web::Query<Credentials>,
+    body: web::Json<Vec<GlobalDictionaryEntry>>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let admin = match authenticate_admin(pool, query.id, &query.token).await {
+        Ok(admin) => admin,
+        Err(e) => return Ok(e),
+    };
+
+    let entries = body.into_inner();
+    for entry in &entries {
+        if let Err(message) = entry.validate() {
+            return Ok(HttpResponse::BadRequest().body(message));
+        }
+    }
+
+    // A version that fails to compile is rolled back with its audit entry.
+    let mut tx = pool.begin().await?;
+    let version = GlobalDictionaryVersion::create(&mut tx, &entries).await?;
+    let count = entries.len();
+    let path = compile_and_activate(&mut tx, config.get_ref(), version.version, entries).await?;
+    let details = json!({ "version": version.version, "entries": count });
+    AuditEntry::record(
+        &mut tx,
+        admin.id,
+        "create_dictionary_version",
+        None,
+        &details,
+    )
+    .await?;
+    tx.commit().await?;
+    swap(active.get_ref(), version.version, path);
+
+    let version = GlobalDictionaryVersion::get(pool, version.version).await?;
+    Ok(HttpResponse::Created().json(version))
+}
+
+#[post("/admin/dictionary/versions/{version}/activate")]
+async fn activate_version(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    active: web::Data<ActiveDictionary>,
+    version: web::Path<i32>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let admin = match authenticate_admin(pool, query.id, &query.token).await {
+        Ok(admin) => admin,
+        Err(e) => return Ok(e),
+    };
+
+    let version = match GlobalDictionaryVersion::get(pool, version.into_inner()).await {
+        Ok(version) => version.version,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => {
+            return Ok(HttpResponse::NotFound().body("Version not found"))
+        }
+        Err(e) => return Err(e),
+    };
+    let entries = GlobalDictionaryEntry::list(pool, version).await?;
+    let mut tx = pool.begin().await?;
+    let path = compile_and_activate(&mut tx, config.get_ref(), version, entries).await?;
+    let details = json!({ "version": version });
+    AuditEntry::record(
+        &mut tx,
+        admin.id,
+        "activate_dictionary_version",
+        None,
+        &details,
+    )
+    .await?;
+    tx.commit().await?;
+    swap(active.get_ref(), version, path);
+
+    let version = GlobalDictionaryVersion::get(pool, version).await?;
+    Ok(HttpResponse::Ok().json(version))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_versions);
+    cfg.service(list_version_entries);
+    cfg.service(create_version);
+    cfg.service(activate_version);
+}
@@ -0,0 +1,52 @@
+pub mod compiler;
+pub mod global;
+pub mod routes;
+
+pub use self::compiler::ActiveDictionary;
+
+use crate::{
+    config::Config,
+    error::AppError,
+    models::global_dictionary::{GlobalDictionaryEntry, GlobalDictionaryVersion},
+    trace,
+};
+use actix_web::web;
+use sqlx::PgPool;
+use std::path::PathBuf;
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    routes::init(cfg);
+    global::init(cfg);
+}
+
+/// Compiles (if needed) and activates the version marked active in the database.
+pub async fn load_active(
+    pool: &PgPool,
+    config: &Config,
+    active: &ActiveDictionary,
+) -> Result<(), AppError> {
+    let version = match GlobalDictionaryVersion::active(pool).await? {
+        Some(version) => version.version,
+        None => return Ok(()),
+    };
+    let entries = GlobalDictionaryEntry::list(pool, version).await?;
+    let path = build(config, version, entries).await?;
+    info!("Using global dictionary v{} ({})", version, path.display());
+    active.swap(path);
+    Ok(())
+}
+
+/// Compiles `version` on the blocking thread pool and returns its directory.
+pub async fn build(
+    config: &Config,
+    version: i32,
+    entries: Vec<GlobalDictionaryEntry>,
+) -> Result<PathBuf, AppError> {
+    let dictionary_config = config.global_dictionary.clone();
+    let system_dictionary = config.openjtalk.dictionary.clone();
+    let path = trace::block(move || {
+        compiler::compile(&dictionary_config, &system_dictionary, version, &entries)
+    })
+    .await?;
+    Ok(path)
+}
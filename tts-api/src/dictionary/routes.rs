@@ -0,0 +1,190 @@
+use crate::{
+    auth::{authenticate, Credentials},
+    config::Config,
+    error::AppError,
+    models::dictionary::{Creation, DictionaryEntry},
+};
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use sqlx::PgPool;
+
+#[derive(Deserialize, Debug)]
+struct UpdateEntryBody {
+    reading: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportQuery {
+    id: i64,
+    token: String,
+    replace: Option<bool>,
+}
+
+fn limit_exceeded(limit: usize) -> HttpResponse {
+    HttpResponse::Forbidden().body(format!(
+        "Dictionary size must be at most {} entries.",
+        limit
+    ))
+}
+
+#[get("/dictionary")]
+async fn list_entries(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let entries = DictionaryEntry::list(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[post("/dictionary")]
+async fn create_entry(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    query: web::Query<Credentials>,
+    body: web::Json<DictionaryEntry>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let entry = body.into_inner();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    if let Err(message) = entry.validate() {
+        return Ok(HttpResponse::BadRequest().body(message));
+    }
+
+    let limit = config.plans.limits(user.plan()).max_dictionary_entries;
+    match entry.create(pool, user.id, limit).await? {
+        Creation::Created => Ok(HttpResponse::Created().json(entry)),
+        Creation::Exists => Ok(HttpResponse::Conflict().body("Entry already exists.")),
+        Creation::LimitExceeded => Ok(limit_exceeded(limit)),
+    }
+}
+
+#[put("/dictionary/{surface}")]
+async fn update_entry(
+    pool: web::Data<PgPool>,
+    surface: web::Path<String>,
+    query: web::Query<Credentials>,
+    body: web::Json<UpdateEntryBody>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let entry = DictionaryEntry {
+        surface: surface.into_inner(),
+        reading: body.into_inner().reading,
+    };
+    if let Err(message) = entry.validate() {
+        return Ok(HttpResponse::BadRequest().body(message));
+    }
+
+    let entries = DictionaryEntry::list(pool, user.id).await?;
+    if !entries.iter().any(|e| e.surface == entry.surface) {
+        return Ok(HttpResponse::NotFound().body("Entry not found"));
+    }
+
+    entry.upsert(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(entry))
+}
+
+#[delete("/dictionary/{surface}")]
+async fn delete_entry(
+    pool: web::Data<PgPool>,
+    surface: web::Path<String>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    if DictionaryEntry::delete(pool, user.id, &surface).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::NotFound().body("Entry not found"))
+    }
+}
+
+#[get("/dictionary/export.csv")]
+async fn export_csv(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let entries = DictionaryEntry::list(pool, user.id).await?;
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+    let buffer = writer
+        .into_inner()
+        .map_err(|e| AppError::IoError(e.into_error()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .body(buffer))
+}
+
+#[post("/dictionary/import.csv")]
+async fn import_csv(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    query: web::Query<ImportQuery>,
+    body: String,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let mut imported = Vec::new();
+    for record in reader.deserialize::<DictionaryEntry>() {
+        let entry = match record {
+            Ok(entry) => entry,
+            Err(e) => return Ok(HttpResponse::BadRequest().body(format!("Invalid CSV: {}", e))),
+        };
+        if let Err(message) = entry.validate() {
+            return Ok(HttpResponse::BadRequest().body(message));
+        }
+        imported.push(entry);
+    }
+
+    let replace = query.replace.unwrap_or(false);
+    let limit = config.plans.limits(user.plan()).max_dictionary_entries;
+    if !DictionaryEntry::import(pool, user.id, &imported, replace, limit).await? {
+        return Ok(limit_exceeded(limit));
+    }
+
+    let entries = DictionaryEntry::list(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_entries);
+    cfg.service(export_csv);
+    cfg.service(import_csv);
+    cfg.service(create_entry);
+    cfg.service(update_entry);
+    cfg.service(delete_entry);
+}
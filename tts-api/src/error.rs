@@ -1,4 +1,7 @@
-use actix_web::error;
+use actix_web::{
+    error::{self, BlockingError},
+    HttpResponse,
+};
 use std::{io::Error as IoError, path::PathBuf};
 use thiserror::Error;
 use toml::de::Error as TomlDeserializationError;
@@ -29,6 +32,30 @@ pub enum AppError {
     InvalidHeaderError(#[from] http::header::InvalidHeaderValue),
     #[error("Crypt Error")]
     CryptError(#[from] pwhash::error::Error),
+    #[error("CSV Error: {0:#?}")]
+    CsvError(#[from] csv::Error),
+    #[error("Unknown voice: {0}")]
+    UnknownVoice(String),
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Storage Error: {0}")]
+    StorageError(String),
+    #[error("Pending migrations (run `tts-admin migrate` or enable database.auto_migrate): {}", .0.join(", "))]
+    PendingMigrations(Vec<String>),
 }
 
-impl error::ResponseError for AppError {}
+impl error::ResponseError for AppError {
+    fn error_response(&self) -> HttpResponse {
+        error!("{:?}", self);
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+impl From<BlockingError<AppError>> for AppError {
+    fn from(e: BlockingError<AppError>) -> AppError {
+        match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => AppError::SubprocessError(),
+        }
+    }
+}
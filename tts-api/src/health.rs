@@ -0,0 +1,144 @@
+//! Liveness and readiness probes.
+
+use crate::{
+    checks::{self, Check},
+    config::Config,
+    dictionary::ActiveDictionary,
+    metrics, shutdown, trace,
+};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Serialize, Debug, Clone)]
+struct Readiness {
+    ready: bool,
+    checked_at: DateTime<Utc>,
+    checks: Vec<Check>,
+}
+
+/// The last readiness result, reused for `[health] cache_secs`.
+#[derive(Clone, Default)]
+pub struct ReadinessCache {
+    last: Arc<Mutex<Option<(Instant, Readiness)>>>,
+}
+
+impl ReadinessCache {
+    pub fn new() -> ReadinessCache {
+        ReadinessCache::default()
+    }
+
+    fn get(&self, max_age: Duration) -> Option<Readiness> {
+        match &*self.last.lock().unwrap() {
+            Some((at, readiness)) if at.elapsed() < max_age => Some(readiness.clone()),
+            _ => None,
+        }
+    }
+
+    fn set(&self, readiness: Readiness) {
+        *self.last.lock().unwrap() = Some((Instant::now(), readiness));
+    }
+}
+
+/// Runs the checks that spawn `open_jtalk` or stat files, off the async
+/// worker threads.
+async fn installation(config: web::Data<Config>, active: &ActiveDictionary) -> Vec<Check> {
+    let global = active.get();
+    let checks = trace::block(move || {
+        let mut checks = vec![
+            checks::open_jtalk(),
+            checks::dictionary("dictionary", &config.openjtalk.dictionary),
+        ];
+        if let Some(path) = global {
+            checks.push(checks::dictionary("global_dictionary", &path));
+        }
+        checks.extend(checks::voices(&config));
+        Ok::<_, ()>(checks)
+    })
+    .await;
+    checks.unwrap_or_else(|e| {
+        vec![Check {
+            name: "installation".to_string(),
+            ok: false,
+            detail: e.to_string(),
+        }]
+    })
+}
+
+async fn check(pool: &PgPool, config: web::Data<Config>, active: &ActiveDictionary) -> Readiness {
+    let mut checks = vec![checks::postgres(pool).await];
+    checks.extend(installation(config.clone(), active).await);
+    checks.push(checks::synthesis(active.resolve(&config.openjtalk)).await);
+
+    Readiness {
+        ready: checks.iter().all(|check| check.ok),
+        checked_at: Utc::now(),
+        checks,
+    }
+}
+
+/// Answers as long as the process is serving requests.
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Checks every dependency of synthesis. Responds with 503 if any check
+/// fails, listing the result of each, or while shutting down.
+#[get("/readyz")]
+async fn readyz(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    active: web::Data<ActiveDictionary>,
+    cache: web::Data<ReadinessCache>,
+) -> HttpResponse {
+    // Take the instance out of rotation while in-flight work drains.
+    if shutdown::draining() {
+        return HttpResponse::ServiceUnavailable().json(Readiness {
+            ready: false,
+            checked_at: Utc::now(),
+            checks: vec![Check {
+                name: "shutdown".to_string(),
+                ok: false,
+                detail: "Shutting down.".to_string(),
+            }],
+        });
+    }
+    let max_age = Duration::from_secs(config.health.cache_secs);
+    let readiness = match cache.get(max_age) {
+        Some(readiness) => {
+            metrics::CACHE_REQUESTS.inc(&["readiness", "hit"]);
+            readiness
+        }
+        None => {
+            metrics::CACHE_REQUESTS.inc(&["readiness", "miss"]);
+            let readiness = check(&pool, config.clone(), &active).await;
+            if !readiness.ready {
+                let failed: Vec<&str> = readiness
+                    .checks
+                    .iter()
+                    .filter(|check| !check.ok)
+                    .map(|check| check.name.as_str())
+                    .collect();
+                warn!("Readiness checks failed: {}", failed.join(", "));
+            }
+            cache.set(readiness.clone());
+            readiness
+        }
+    };
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(healthz);
+    cfg.service(readyz);
+}
@@ -0,0 +1,28 @@
+pub mod routes;
+pub mod worker;
+
+pub use self::routes::init;
+
+use crate::{
+    audio::{format::OutputFormat, process::PostProcess},
+    tts::request::{AudioFormat, SynthesisOptions},
+};
+
+/// A synthesis request queued as a job, stored as JSON with the job.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRequest {
+    pub format: AudioFormat,
+    #[serde(flatten)]
+    pub options: SynthesisOptions,
+    #[serde(default)]
+    pub postprocess: PostProcess,
+    #[serde(default)]
+    pub output: OutputFormat,
+    /// Notified when the job finishes. Kept in its own column rather than in
+    /// the stored request.
+    #[serde(skip_serializing)]
+    pub callback_url: Option<String>,
+    /// Applied when the job is queued, so the stored request is complete.
+    #[serde(skip_serializing)]
+    pub preset: Option<String>,
+}
@@ -0,0 +1,170 @@
+use crate::{
+    auth::{authenticate, Credentials},
+    config::Config,
+    error::AppError,
+    jobs::JobRequest,
+    models::job::{Job, JobStatus},
+    presets,
+    tts::routes::{charge, check_length, spoken_length},
+    webhooks,
+};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+#[derive(Serialize, Debug)]
+struct JobResponse {
+    id: i64,
+    status: JobStatus,
+    segments_done: i32,
+    segments_total: i32,
+    attempts: i32,
+    callback_url: Option<String>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    audio_url: Option<String>,
+}
+
+impl From<Job> for JobResponse {
+    fn from(job: Job) -> JobResponse {
+        let status = job.status();
+        JobResponse {
+            id: job.id,
+            status,
+            segments_done: job.segments_done,
+            segments_total: job.segments_total,
+            attempts: job.attempts,
+            callback_url: job.callback_url,
+            error: job.error,
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            audio_url: match status {
+                JobStatus::Done => Some(format!("/jobs/{}/audio", job.id)),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn job_not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("Job not found.")
+}
+
+/// Validates a request and queues it. Characters are charged when the job is
+/// queued, as for synchronous requests.
+async fn create_job(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    query: web::Query<Credentials>,
+    body: web::Json<JobRequest>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let config = config.get_ref();
+    let mut request = body.into_inner();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+    let preset = match presets::load(pool, user.id, request.preset.as_deref()).await {
+        Ok(preset) => preset,
+        Err(e) => return Ok(e),
+    };
+    preset.apply(&mut request.options);
+    request.postprocess = request.postprocess.or(&preset.postprocess);
+    request.output = request.output.or(&preset.output);
+
+    if let Err(e) = request.postprocess.validate() {
+        return Ok(HttpResponse::BadRequest().body(e));
+    }
+    let sampling_rate = config.openjtalk.sampling_rate();
+    if let Err(e) = request.format.resolve(&request.output, sampling_rate) {
+        return Ok(HttpResponse::BadRequest().body(e));
+    }
+    if let Some(url) = &request.callback_url {
+        if let Err(e) = webhooks::validate_url(&config.webhooks, url).await {
+            return Ok(HttpResponse::BadRequest().body(e));
+        }
+    }
+    let input = match request.options.input(config) {
+        Ok(input) => input,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+    };
+    let length = spoken_length(&input);
+    if let Err(e) = check_length(length, &user, config) {
+        return Ok(e);
+    }
+    let user = match charge(pool, &user, length).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let job = Job::create(
+        pool,
+        user.id,
+        &serde_json::to_string(&request)?,
+        request.callback_url.as_deref(),
+    )
+    .await?;
+    Ok(HttpResponse::Accepted().json(JobResponse::from(job)))
+}
+
+#[get("/jobs/{id}")]
+async fn get_job(
+    pool: web::Data<PgPool>,
+    id: web::Path<i64>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    match Job::get(pool, *id, user.id).await {
+        Ok(job) => Ok(HttpResponse::Ok().json(JobResponse::from(job))),
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => Ok(job_not_found()),
+        Err(e) => Err(e),
+    }
+}
+
+#[get("/jobs/{id}/audio")]
+async fn get_job_audio(
+    pool: web::Data<PgPool>,
+    id: web::Path<i64>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let job = match Job::get(pool, *id, user.id).await {
+        Ok(job) => job,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => return Ok(job_not_found()),
+        Err(e) => return Err(e),
+    };
+    if job.status() != JobStatus::Done {
+        return Ok(HttpResponse::Conflict().body("Job has not finished."));
+    }
+
+    match Job::audio(pool, job.id, user.id).await? {
+        Some((audio, content_type)) => {
+            Ok(HttpResponse::Ok().content_type(content_type).body(audio))
+        }
+        None => Ok(job_not_found()),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig, config: &Config) {
+    cfg.service(
+        web::resource("/jobs")
+            .app_data(web::JsonConfig::default().limit(config.json_limit(1, 1)))
+            .route(web::post().to(create_job)),
+    );
+    cfg.service(get_job);
+    cfg.service(get_job_audio);
+}
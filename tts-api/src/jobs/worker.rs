@@ -0,0 +1,184 @@
+use crate::{
+    audio::format,
+    config::Config,
+    dictionary::ActiveDictionary,
+    error::AppError,
+    jobs::JobRequest,
+    metrics,
+    models::job::Job,
+    shutdown,
+    text::normalize::Normalizer,
+    trace,
+    tts::synthesis::{self, Progress},
+    webhooks,
+};
+use actix_web::rt::{self, time::delay_for};
+use chrono::Utc;
+use futures::{
+    future::{self, Either},
+    pin_mut,
+};
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+
+/// How often a running job's progress and heartbeat are written.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often finished jobs past `[jobs] retention_days` are deleted.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Starts `[jobs] workers` job workers and the purge of expired jobs on the
+/// current arbiter.
+pub fn spawn(pool: PgPool, config: Config, normalizer: Normalizer, dictionary: ActiveDictionary) {
+    rt::spawn(purge(pool.clone(), config.jobs.retention_days));
+    for _ in 0..config.jobs.workers {
+        rt::spawn(run(
+            pool.clone(),
+            config.clone(),
+            normalizer.clone(),
+            dictionary.clone(),
+        ));
+    }
+}
+
+async fn run(pool: PgPool, config: Config, normalizer: Normalizer, dictionary: ActiveDictionary) {
+    let interval = Duration::from_millis(config.jobs.poll_interval_ms);
+    // Stop claiming jobs once shutdown starts; the running one finishes.
+    while !shutdown::draining() {
+        match poll(&pool, &config, &normalizer, &dictionary).await {
+            // Keep draining the queue while there is work.
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => error!("Job worker error: {:?}", e),
+        }
+        delay_for(interval).await;
+    }
+}
+
+async fn purge(pool: PgPool, retention_days: i64) {
+    loop {
+        let finished_before = Utc::now() - chrono::Duration::days(retention_days);
+        match Job::purge(&pool, finished_before).await {
+            Ok(0) => {}
+            Ok(count) => info!("Deleted {} expired jobs", count),
+            Err(e) => error!("Job purge failed: {:?}", e),
+        }
+        delay_for(PURGE_INTERVAL).await;
+    }
+}
+
+/// Claims and runs one job. Returns whether there was a job to run.
+async fn poll(
+    pool: &PgPool,
+    config: &Config,
+    normalizer: &Normalizer,
+    dictionary: &ActiveDictionary,
+) -> Result<bool, AppError> {
+    let stale_before = Utc::now() - chrono::Duration::seconds(config.jobs.stale_after_secs);
+    for job in Job::fail_stale(pool, stale_before, config.jobs.max_attempts).await? {
+        webhooks::notify(pool, config, &job).await?;
+    }
+    let job = match Job::claim(pool, stale_before, config.jobs.max_attempts).await? {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+
+    let _running = shutdown::track_job(job.id);
+    // Logs written while rendering carry the job in place of a request id.
+    let job_id = Some(format!("job-{}", job.id));
+    let result = trace::scope(job_id, async {
+        info!("Running job {} (attempt {})", job.id, job.attempts);
+        process(pool, config, normalizer, dictionary, &job).await
+    })
+    .await;
+    let finished = match result {
+        Ok((audio, content_type)) => {
+            Job::complete(pool, job.id, job.attempts, &audio, content_type).await?
+        }
+        // A job cut off by shutdown, e.g. by its open_jtalk process being
+        // killed, is retried by the next instance rather than failed.
+        Err(e) if shutdown::draining() => {
+            warn!("Job {} interrupted by shutdown: {:?}", job.id, e);
+            Job::requeue(pool, job.id).await?;
+            return Ok(true);
+        }
+        Err(e) => {
+            error!("Job {} failed: {:?}", job.id, e);
+            let message = match e {
+                AppError::InvalidRequest(_) | AppError::UnknownVoice(_) => e.to_string(),
+                _ => "Synthesis failed.".to_string(),
+            };
+            Job::fail(pool, job.id, job.attempts, &message).await?
+        }
+    };
+    // A job that went stale while running was failed or claimed again
+    // elsewhere; its webhook is sent from there.
+    if !finished {
+        warn!(
+            "Job {} was taken over before attempt {} finished",
+            job.id, job.attempts
+        );
+        return Ok(true);
+    }
+    let job = Job::get(pool, job.id, job.users_id).await?;
+    webhooks::notify(pool, config, &job).await?;
+    Ok(true)
+}
+
+/// Renders and encodes a job, reporting progress while rendering.
+async fn process(
+    pool: &PgPool,
+    config: &Config,
+    normalizer: &Normalizer,
+    dictionary: &ActiveDictionary,
+    job: &Job,
+) -> Result<(Vec<u8>, &'static str), AppError> {
+    let request: JobRequest = serde_json::from_str(&job.request)?;
+    let options = &request.options;
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let format = request
+        .format
+        .resolve(&request.output, sampling_rate)
+        .map_err(AppError::InvalidRequest)?;
+    let input = options.input(config).map_err(AppError::InvalidRequest)?;
+    let metadata = options.describe(&input, config);
+    let parts = options
+        .prepare(input, job.users_id, pool, config, normalizer, dictionary)
+        .await?;
+
+    let progress = Progress::default();
+    let started = Instant::now();
+    let render = synthesis::render_with_progress(&config.synthesis, parts, false, &progress);
+    let heartbeat = async {
+        loop {
+            delay_for(HEARTBEAT_INTERVAL).await;
+            let (done, total) = progress.get();
+            if let Err(e) = Job::report(pool, job.id, done as i32, total as i32).await {
+                warn!("Failed to report progress of job {}: {:?}", job.id, e);
+            }
+        }
+    };
+    pin_mut!(render);
+    pin_mut!(heartbeat);
+    let mut rendered = match future::select(render, heartbeat).await {
+        Either::Left((rendered, _)) => rendered?,
+        Either::Right(_) => unreachable!("heartbeat never finishes"),
+    };
+    metrics::observe_synthesis(
+        "job",
+        options.voice.as_deref(),
+        request.format.extension(),
+        started,
+    );
+    let (done, total) = progress.get();
+    Job::report(pool, job.id, done as i32, total as i32).await?;
+
+    rendered.post_process(
+        sampling_rate,
+        &config.post_process(options.voice.as_deref(), &request.postprocess),
+    );
+    let samples = format::convert(&rendered.samples, sampling_rate, 1, &format);
+    let audio = request
+        .format
+        .encode(&samples, &format, metadata.as_ref())?;
+    Ok((audio, request.format.content_type()))
+}
@@ -0,0 +1,37 @@
+#![warn(clippy::all)]
+
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+use oauth2::basic::BasicClient;
+
+pub mod admin;
+pub mod archive;
+pub mod audio;
+pub mod auth;
+pub mod backend;
+pub mod checks;
+pub mod config;
+pub mod crypto;
+pub mod dictionary;
+pub mod error;
+pub mod health;
+pub mod jobs;
+pub mod metrics;
+pub mod migrate;
+pub mod models;
+pub mod presets;
+pub mod shutdown;
+pub mod storage;
+pub mod text;
+pub mod trace;
+pub mod tts;
+pub mod webhooks;
+
+pub struct AppState {
+    pub oauth: BasicClient,
+}
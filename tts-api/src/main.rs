@@ -2,27 +2,24 @@
 
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate serde_derive;
 
+use actix_service::Service;
 use actix_web::{get, middleware::Logger, App, HttpResponse, HttpServer, Responder};
 use anyhow::Result;
 use dotenv::dotenv;
 use listenfd::ListenFd;
-use oauth2::basic::BasicClient;
 use sqlx::PgPool;
-use std::env;
-
-mod auth;
-mod backend;
-mod config;
-mod error;
-mod models;
-mod tts;
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+use tts_api::{
+    admin, auth, config, dictionary, health, jobs, metrics, migrate, presets, shutdown, storage,
+    text, trace, tts, webhooks, AppState,
+};
 
-pub struct AppState {
-    pub oauth: BasicClient,
-}
+/// The default access log format, plus the request id.
+const LOG_FORMAT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %{X-Request-Id}o %T"#;
 
 #[get("/")]
 async fn index() -> impl Responder {
@@ -32,14 +29,44 @@ async fn index() -> impl Responder {
 #[actix_web::main]
 async fn main() -> Result<()> {
     dotenv().ok();
-    env_logger::init();
+    let config = config::Config::from_config()?;
+    trace::init_logging(&config.logging);
+    if config.webhooks.public_url.is_some() {
+        warn!("[webhooks] public_url is deprecated, set [server] public_url instead");
+    }
 
     let mut listenfd = ListenFd::from_env();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
     let pool = PgPool::new(&database_url).await?;
 
-    let config = config::Config::from_config()?;
+    migrate::startup(&pool, config.database.auto_migrate).await?;
+    let normalizer = text::normalize::Normalizer::from_config(&config.normalize);
+
+    let active_dictionary = dictionary::ActiveDictionary::new();
+    if let Err(e) = dictionary::load_active(&pool, &config, &active_dictionary).await {
+        error!("Failed to load global dictionary: {:?}", e);
+    }
+
+    jobs::worker::spawn(
+        pool.clone(),
+        config.clone(),
+        normalizer.clone(),
+        active_dictionary.clone(),
+    );
+    webhooks::dispatcher::spawn(pool.clone(), config.clone());
+
+    let storage = storage::Storage::from_config(&config.storage)?;
+    storage::cleanup::spawn(
+        pool.clone(),
+        storage.clone(),
+        Duration::from_secs(config.storage.cleanup_interval_secs),
+    );
+
+    let readiness = health::ReadinessCache::new();
+
+    let deadline = Duration::from_secs(config.shutdown.deadline_secs);
+    let shutdown_pool = pool.clone();
 
     let mut server = HttpServer::new(move || {
         let oauth = auth::create_auth_client();
@@ -47,12 +74,41 @@ async fn main() -> Result<()> {
             .data(AppState { oauth })
             .data(pool.clone())
             .data(config.clone())
-            .wrap(Logger::default())
+            .data(normalizer.clone())
+            .data(active_dictionary.clone())
+            .data(storage.clone())
+            .data(readiness.clone())
+            .wrap_fn(shutdown::middleware)
+            .wrap_fn(trace::middleware)
+            .wrap(Logger::new(LOG_FORMAT))
+            .wrap_fn(|req, srv| {
+                let started = Instant::now();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    metrics::observe_request(&response, started.elapsed());
+                    Ok(response)
+                }
+            })
             .service(index)
-            .configure(tts::init)
+            .configure(health::init)
+            .configure(metrics::init)
+            .configure(|cfg| tts::init(cfg, &config))
+            .configure(|cfg| jobs::init(cfg, &config))
+            .configure(webhooks::init)
+            .configure(storage::init)
+            .configure(presets::init)
+            .configure(dictionary::init)
+            .configure(admin::init)
             .configure(auth::init)
     });
 
+    // The server stops accepting connections on SIGTERM or SIGINT and gives
+    // in-flight requests until the deadline. Signals are handled by
+    // `shutdown::listen`.
+    server = server
+        .shutdown_timeout(deadline.as_secs())
+        .disable_signals();
     server = match listenfd.take_tcp_listener(0)? {
         Some(listener) => server.listen(listener)?,
         None => {
@@ -63,7 +119,10 @@ async fn main() -> Result<()> {
     };
 
     info!("Starting server");
-    server.run().await?;
+    let server = server.run();
+    shutdown::listen(server.clone(), deadline);
+    server.await?;
+    shutdown::finish(&shutdown_pool, deadline).await;
 
     Ok(())
 }
@@ -0,0 +1,316 @@
+//! Process-wide Prometheus metrics, rendered in the text exposition format.
+
+use actix_web::{dev::ServiceResponse, get, web, HttpResponse};
+use sqlx::PgPool;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+const SYNTHESIS_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: Counter = Counter::new(
+        "tts_http_requests_total",
+        "HTTP requests by route, method and status.",
+        &["route", "method", "status"],
+    );
+    pub static ref HTTP_REQUEST_DURATION: Histogram = Histogram::new(
+        "tts_http_request_duration_seconds",
+        "HTTP request latency by route, method and status.",
+        &["route", "method", "status"],
+        LATENCY_BUCKETS,
+    );
+    pub static ref SYNTHESIS_DURATION: Histogram = Histogram::new(
+        "tts_synthesis_duration_seconds",
+        "Time spent rendering speech, by endpoint, voice and output format.",
+        &["mode", "voice", "format"],
+        SYNTHESIS_BUCKETS,
+    );
+    pub static ref SYNTHESIS_IN_FLIGHT: Gauge =
+        Gauge::new("tts_synthesis_in_flight", "Renders currently in progress.");
+    pub static ref OPEN_JTALK_FAILURES: Counter = Counter::new(
+        "tts_open_jtalk_failures_total",
+        "Failed open_jtalk runs by exit code.",
+        &["exit_code"],
+    );
+    pub static ref CHARACTERS: Counter = Counter::new(
+        "tts_characters_total",
+        "Characters charged to user quotas.",
+        &[],
+    );
+    pub static ref QUOTA_REJECTIONS: Counter = Counter::new(
+        "tts_quota_rejections_total",
+        "Requests refused for exceeding a quota.",
+        &["quota"],
+    );
+    pub static ref CACHE_REQUESTS: Counter = Counter::new(
+        "tts_cache_requests_total",
+        "Cache lookups by cache and result.",
+        &["cache", "result"],
+    );
+}
+
+type Labels = Vec<String>;
+
+fn labels(values: &[&str]) -> Labels {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Formats `{name="value",...}`, or nothing without labels.
+fn format_labels(names: &[&str], values: &[String], extra: Option<(&str, &str)>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    if let Some((name, value)) = extra {
+        pairs.push(format!("{}=\"{}\"", name, value));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    values: Mutex<BTreeMap<Labels, f64>>,
+}
+
+impl Counter {
+    fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> Counter {
+        let mut values = BTreeMap::new();
+        if label_names.is_empty() {
+            values.insert(Vec::new(), 0.0);
+        }
+        Counter {
+            name,
+            help,
+            label_names,
+            values: Mutex::new(values),
+        }
+    }
+
+    pub fn inc(&self, label_values: &[&str]) {
+        self.inc_by(label_values, 1.0);
+    }
+
+    pub fn inc_by(&self, label_values: &[&str], value: f64) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(labels(label_values))
+            .or_insert(0.0) += value;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        for (values, value) in self.values.lock().unwrap().iter() {
+            let labels = format_labels(self.label_names, values, None);
+            let _ = writeln!(out, "{}{} {}", self.name, labels, value);
+        }
+    }
+}
+
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: Mutex<f64>,
+}
+
+impl Gauge {
+    fn new(name: &'static str, help: &'static str) -> Gauge {
+        Gauge {
+            name,
+            help,
+            value: Mutex::new(0.0),
+        }
+    }
+
+    pub fn add(&self, value: f64) {
+        *self.value.lock().unwrap() += value;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "gauge");
+        let _ = writeln!(out, "{} {}", self.name, self.value.lock().unwrap());
+    }
+}
+
+#[derive(Default)]
+struct Observations {
+    /// Observations per bucket, not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<Labels, Observations>>,
+}
+
+impl Histogram {
+    fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+        bounds: &'static [f64],
+    ) -> Histogram {
+        Histogram {
+            name,
+            help,
+            label_names,
+            bounds,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, label_values: &[&str], value: f64) {
+        let mut values = self.values.lock().unwrap();
+        let observations = values
+            .entry(labels(label_values))
+            .or_insert_with(|| Observations {
+                buckets: vec![0; self.bounds.len()],
+                ..Observations::default()
+            });
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            observations.buckets[bucket] += 1;
+        }
+        observations.sum += value;
+        observations.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+        for (values, observations) in self.values.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.bounds.iter().zip(&observations.buckets) {
+                cumulative += count;
+                let le = bound.to_string();
+                let labels = format_labels(self.label_names, values, Some(("le", &le)));
+                let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, cumulative);
+            }
+            let labels = format_labels(self.label_names, values, Some(("le", "+Inf")));
+            let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, observations.count);
+            let labels = format_labels(self.label_names, values, None);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, labels, observations.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, labels, observations.count);
+        }
+    }
+}
+
+/// Counts a render as in flight until dropped.
+pub struct InFlight(());
+
+impl InFlight {
+    pub fn start() -> InFlight {
+        SYNTHESIS_IN_FLIGHT.add(1.0);
+        InFlight(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        SYNTHESIS_IN_FLIGHT.add(-1.0);
+    }
+}
+
+/// Records a finished request under its route pattern, so that path
+/// parameters do not each get their own series.
+pub fn observe_request<B>(response: &ServiceResponse<B>, elapsed: Duration) {
+    let request = response.request();
+    let route = request
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().as_str();
+    let status = response.status().as_u16().to_string();
+    let labels = [route.as_str(), method, status.as_str()];
+    HTTP_REQUESTS.inc(&labels);
+    HTTP_REQUEST_DURATION.observe(&labels, elapsed.as_secs_f64());
+}
+
+/// Records the time spent rendering since `started`. `mode` names the kind
+/// of request (`speech`, `batch`, `job` or `dialogue`). Requests without a
+/// voice are counted under `default`.
+pub fn observe_synthesis(mode: &str, voice: Option<&str>, format: &str, started: Instant) {
+    SYNTHESIS_DURATION.observe(
+        &[mode, voice.unwrap_or("default"), format],
+        started.elapsed().as_secs_f64(),
+    );
+}
+
+fn render_pool(out: &mut String, pool: &PgPool) {
+    let size = pool.size();
+    let idle = pool.idle() as u32;
+    header(
+        out,
+        "tts_db_pool_connections",
+        "Database connections by state.",
+        "gauge",
+    );
+    let _ = writeln!(
+        out,
+        "tts_db_pool_connections{{state=\"active\"}} {}",
+        size.saturating_sub(idle)
+    );
+    let _ = writeln!(out, "tts_db_pool_connections{{state=\"idle\"}} {}", idle);
+    header(
+        out,
+        "tts_db_pool_max_connections",
+        "Maximum size of the database pool.",
+        "gauge",
+    );
+    let _ = writeln!(out, "tts_db_pool_max_connections {}", pool.max_size());
+}
+
+#[get("/metrics")]
+async fn metrics(pool: web::Data<PgPool>) -> HttpResponse {
+    let mut out = String::new();
+    HTTP_REQUESTS.render(&mut out);
+    HTTP_REQUEST_DURATION.render(&mut out);
+    SYNTHESIS_DURATION.render(&mut out);
+    SYNTHESIS_IN_FLIGHT.render(&mut out);
+    OPEN_JTALK_FAILURES.render(&mut out);
+    CHARACTERS.render(&mut out);
+    QUOTA_REJECTIONS.render(&mut out);
+    CACHE_REQUESTS.render(&mut out);
+    render_pool(&mut out, &pool);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics);
+}
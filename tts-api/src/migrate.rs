@@ -0,0 +1,151 @@
+//! Database migrations embedded in the binary. Applied migrations are
+//! recorded in the `__migrations` table kept by sqlx-cli, so databases
+//! migrated with `sqlx migrate run` are picked up as they are.
+
+use crate::error::AppError;
+use sqlx::{Executor, PgPool, Row};
+
+/// Advisory lock key held while applying a migration, so that instances
+/// starting together do not apply the same migration twice.
+const LOCK_KEY: i64 = 0x7474_735f_6d69_6772;
+
+pub struct Migration {
+    /// File name in `migrations/`, as recorded by sqlx-cli.
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migrations {
+    ($($name:literal),* $(,)?) => {
+        &[$(Migration {
+            name: concat!($name, ".sql"),
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }),*]
+    };
+}
+
+/// Every migration, in the order they are applied. New files in
+/// `migrations/` must be added here; a test checks the two agree.
+pub const MIGRATIONS: &[Migration] = migrations![
+    "20210302021749_init",
+    "20210302105249_user_secret",
+    "20210302141357_pk_secret",
+    "20210302142818_add_unique",
+    "20210305120000_user_plan",
+    "20210308093000_user_dictionary",
+    "20210310101500_user_role",
+    "20210310102000_global_dictionary",
+    "20210315090000_synthesis_jobs",
+    "20210316090000_webhooks",
+    "20210317090000_stored_audio",
+    "20210318090000_user_presets",
+    "20210319090000_admin_audit_log",
+];
+
+/// Returns the names of the migrations recorded as applied, without
+/// creating the table if it is missing.
+pub async fn applied(pool: &PgPool) -> Result<Vec<String>, AppError> {
+    let exists: bool = sqlx::query("SELECT to_regclass('__migrations') IS NOT NULL AS exists")
+        .fetch_one(pool)
+        .await?
+        .get("exists");
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let rows = sqlx::query("SELECT migration FROM __migrations")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(|row| row.get("migration")).collect())
+}
+
+pub async fn pending(pool: &PgPool) -> Result<Vec<&'static Migration>, AppError> {
+    let applied = applied(pool).await?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.iter().any(|name| name == migration.name))
+        .collect())
+}
+
+/// Applies pending migrations in order, each in its own transaction, and
+/// returns their names.
+pub async fn run(pool: &PgPool) -> Result<Vec<&'static str>, AppError> {
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS __migrations (
+                migration VARCHAR (255) PRIMARY KEY,
+                created TIMESTAMP NOT NULL DEFAULT current_timestamp
+            )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    let mut names = Vec::new();
+    for migration in pending(pool).await? {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(LOCK_KEY)
+            .execute(&mut tx)
+            .await?;
+        // Another instance may have applied it while we waited for the lock.
+        let applied = sqlx::query("SELECT migration FROM __migrations WHERE migration = $1")
+            .bind(migration.name)
+            .fetch_all(&mut tx)
+            .await?;
+        if !applied.is_empty() {
+            continue;
+        }
+        tx.execute(migration.sql).await?;
+        sqlx::query("INSERT INTO __migrations (migration) VALUES ($1)")
+            .bind(migration.name)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        info!("Applied migration {}", migration.name);
+        names.push(migration.name);
+    }
+    Ok(names)
+}
+
+/// Brings the database up to date at startup. With `auto_migrate` off,
+/// pending migrations are only listed in the returned error.
+pub async fn startup(pool: &PgPool, auto_migrate: bool) -> Result<(), AppError> {
+    for name in applied(pool).await? {
+        if !MIGRATIONS.iter().any(|migration| migration.name == name) {
+            warn!("Database has migration {} unknown to this build", name);
+        }
+    }
+
+    if auto_migrate {
+        run(pool).await?;
+        return Ok(());
+    }
+    let pending: Vec<String> = pending(pool)
+        .await?
+        .iter()
+        .map(|migration| migration.name.to_string())
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::PendingMigrations(pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_migration_file_is_embedded_in_order() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".sql"))
+            .collect();
+        files.sort();
+        let names: Vec<&str> = MIGRATIONS.iter().map(|migration| migration.name).collect();
+        assert_eq!(names, files);
+    }
+}
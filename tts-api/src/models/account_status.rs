@@ -0,0 +1,39 @@
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::str::FromStr;
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum AccountStatus {
+    Active = 0,
+    Suspended = 1,
+    Banned = 2,
+}
+
+impl From<i32> for AccountStatus {
+    fn from(value: i32) -> AccountStatus {
+        match value {
+            1 => AccountStatus::Suspended,
+            2 => AccountStatus::Banned,
+            _ => AccountStatus::Active,
+        }
+    }
+}
+
+impl Default for AccountStatus {
+    fn default() -> AccountStatus {
+        AccountStatus::Active
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccountStatus, String> {
+        match s {
+            "active" => Ok(AccountStatus::Active),
+            "suspended" => Ok(AccountStatus::Suspended),
+            "banned" => Ok(AccountStatus::Banned),
+            _ => Err(format!("Unknown account status: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{error::AppError, models::users::User};
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, PgPool};
+
+/// A clip kept in audio storage under `storage_key`.
+#[derive(Serialize, Debug, Clone)]
+pub struct StoredAudio {
+    pub id: i64,
+    pub users_id: i64,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub content_type: String,
+    pub size: i64,
+    pub duration: f64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl StoredAudio {
+    /// Records a clip if the user's stored bytes stay within `limit`, and
+    /// returns `None` otherwise. The user row is locked while checking, so
+    /// concurrent requests cannot together go over the limit.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create(
+        pool: &PgPool,
+        users_id: i64,
+        storage_key: &str,
+        content_type: &str,
+        size: i64,
+        duration: f64,
+        expires_at: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Option<StoredAudio>, AppError> {
+        let mut tx = pool.begin().await?;
+        User::lock(&mut tx, users_id).await?;
+        let usage = query!(
+            "SELECT COALESCE(SUM(size), 0)::BIGINT AS usage FROM stored_audio WHERE users_id = $1",
+            users_id
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .usage
+        .unwrap_or(0);
+        if usage + size > limit {
+            return Ok(None);
+        }
+        let audio = query_as!(
+            StoredAudio,
+            r#"
+                INSERT INTO stored_audio
+                (users_id, storage_key, content_type, size, duration, expires_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING *
+            "#,
+            users_id,
+            storage_key,
+            content_type,
+            size,
+            duration,
+            expires_at
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(audio))
+    }
+
+    /// Gets an unexpired clip of a user.
+    pub async fn get(pool: &PgPool, id: i64, users_id: i64) -> Result<StoredAudio, AppError> {
+        let audio = query_as!(
+            StoredAudio,
+            r#"
+                SELECT * FROM stored_audio
+                WHERE id = $1 AND users_id = $2 AND (expires_at IS NULL OR expires_at > now())
+            "#,
+            id,
+            users_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(audio)
+    }
+
+    /// Gets an unexpired clip of any user, for signed share URLs.
+    pub async fn find(pool: &PgPool, id: i64) -> Result<StoredAudio, AppError> {
+        let audio = query_as!(
+            StoredAudio,
+            r#"
+                SELECT * FROM stored_audio
+                WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(audio)
+    }
+
+    pub async fn list(pool: &PgPool, users_id: i64) -> Result<Vec<StoredAudio>, AppError> {
+        let audio = query_as!(
+            StoredAudio,
+            r#"
+                SELECT * FROM stored_audio
+                WHERE users_id = $1 AND (expires_at IS NULL OR expires_at > now())
+                ORDER BY id DESC
+            "#,
+            users_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(audio)
+    }
+
+    /// Returns the bytes a user has in storage, including expired clips not
+    /// yet cleaned up.
+    pub async fn usage(pool: &PgPool, users_id: i64) -> Result<i64, AppError> {
+        let row = query!(
+            "SELECT COALESCE(SUM(size), 0)::BIGINT AS usage FROM stored_audio WHERE users_id = $1",
+            users_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(row.usage.unwrap_or(0))
+    }
+
+    /// Returns up to `limit` expired clips.
+    pub async fn expired(pool: &PgPool, limit: i64) -> Result<Vec<StoredAudio>, AppError> {
+        let audio = query_as!(
+            StoredAudio,
+            r#"
+                SELECT * FROM stored_audio
+                WHERE expires_at <= now()
+                ORDER BY expires_at
+                LIMIT $1
+            "#,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(audio)
+    }
+
+    pub async fn delete(pool: &PgPool, id: i64) -> Result<(), AppError> {
+        query!("DELETE FROM stored_audio WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
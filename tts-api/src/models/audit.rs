@@ -0,0 +1,91 @@
+use crate::error::AppError;
+use chrono::{DateTime, Utc};
+use sqlx::{pool::PoolConnection, query, query_as, PgConnection, PgPool, Transaction};
+
+/// A record of an action taken through the admin API. Entries are written in
+/// the same transaction as the change they describe.
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub admin_id: i64,
+    pub action: String,
+    pub target_id: Option<i64>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+struct AuditRow {
+    id: i64,
+    admin_id: i64,
+    action: String,
+    target_id: Option<i64>,
+    details: String,
+    created_at: DateTime<Utc>,
+}
+
+impl AuditRow {
+    fn into_entry(self) -> Result<AuditEntry, AppError> {
+        Ok(AuditEntry {
+            id: self.id,
+            admin_id: self.admin_id,
+            action: self.action,
+            target_id: self.target_id,
+            details: serde_json::from_str(&self.details)?,
+            created_at: self.created_at,
+        })
+    }
+}
+
+impl AuditEntry {
+    pub async fn record(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        admin_id: i64,
+        action: &str,
+        target_id: Option<i64>,
+        details: &serde_json::Value,
+    ) -> Result<(), AppError> {
+        let details = serde_json::to_string(details)?;
+        query!(
+            r#"
+                INSERT INTO admin_audit_log (admin_id, action, target_id, details)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            admin_id,
+            action,
+            target_id,
+            details
+        )
+        .execute(tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Lists entries newest first, optionally narrowed to an admin or a
+    /// target user. `before` pages through older entries by id.
+    pub async fn list(
+        pool: &PgPool,
+        admin_id: Option<i64>,
+        target_id: Option<i64>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, AppError> {
+        let rows = query_as!(
+            AuditRow,
+            r#"
+                SELECT id, admin_id, action, target_id, details, created_at FROM admin_audit_log
+                WHERE ($1::BIGINT IS NULL OR admin_id = $1)
+                    AND ($2::BIGINT IS NULL OR target_id = $2)
+                    AND ($3::BIGINT IS NULL OR id < $3)
+                ORDER BY id DESC
+                LIMIT $4
+            "#,
+            admin_id,
+            target_id,
+            before,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+        rows.into_iter().map(AuditRow::into_entry).collect()
+    }
+}
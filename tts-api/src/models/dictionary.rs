@@ -0,0 +1,267 @@
+use crate::{error::AppError, models::users::User};
+use sqlx::{query, query_as, PgPool};
+use std::collections::HashSet;
+
+const MAX_SURFACE_LENGTH: usize = 64;
+const MAX_READING_LENGTH: usize = 128;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DictionaryEntry {
+    pub surface: String,
+    pub reading: String,
+}
+
+/// The outcome of [`DictionaryEntry::create`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Creation {
+    Created,
+    Exists,
+    LimitExceeded,
+}
+
+impl DictionaryEntry {
+    pub fn validate(&self) -> Result<(), String> {
+        let surface_length = self.surface.chars().count();
+        if surface_length == 0 || surface_length > MAX_SURFACE_LENGTH {
+            return Err(format!(
+                "Surface must be between 1 and {} characters.",
+                MAX_SURFACE_LENGTH
+            ));
+        }
+        let reading_length = self.reading.chars().count();
+        if reading_length == 0 || reading_length > MAX_READING_LENGTH {
+            return Err(format!(
+                "Reading must be between 1 and {} characters.",
+                MAX_READING_LENGTH
+            ));
+        }
+        if !self.reading.chars().all(is_kana) {
+            return Err(format!("Reading must be written in kana: {}", self.reading));
+        }
+        Ok(())
+    }
+
+    pub async fn list(pool: &PgPool, user_id: i64) -> Result<Vec<DictionaryEntry>, AppError> {
+        let entries = query_as!(
+            DictionaryEntry,
+            "SELECT surface, reading FROM user_dictionary WHERE users_id = $1 ORDER BY surface",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(entries)
+    }
+
+    /// Adds a new entry if the user has fewer than `limit`. The user row is
+    /// locked while checking, so concurrent requests cannot together go over
+    /// the limit.
+    pub async fn create(
+        &self,
+        pool: &PgPool,
+        user_id: i64,
+        limit: usize,
+    ) -> Result<Creation, AppError> {
+        let mut tx = pool.begin().await?;
+        User::lock(&mut tx, user_id).await?;
+        let counts = query!(
+            r#"
+                SELECT COUNT(*) AS count, COUNT(*) FILTER (WHERE surface = $2) AS existing
+                FROM user_dictionary WHERE users_id = $1
+            "#,
+            user_id,
+            self.surface
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        if counts.existing.unwrap_or(0) > 0 {
+            return Ok(Creation::Exists);
+        }
+        if counts.count.unwrap_or(0) as usize >= limit {
+            return Ok(Creation::LimitExceeded);
+        }
+        query!(
+            "INSERT INTO user_dictionary (users_id, surface, reading) VALUES ($1, $2, $3)",
+            user_id,
+            self.surface,
+            self.reading
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Creation::Created)
+    }
+
+    pub async fn upsert(&self, pool: &PgPool, user_id: i64) -> Result<(), AppError> {
+        query!(
+            r#"
+                INSERT INTO user_dictionary (users_id, surface, reading) VALUES ($1, $2, $3)
+                ON CONFLICT (users_id, surface)
+                DO UPDATE SET reading = $3
+            "#,
+            user_id,
+            self.surface,
+            self.reading
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(pool: &PgPool, user_id: i64, surface: &str) -> Result<bool, AppError> {
+        let deleted = query!(
+            "DELETE FROM user_dictionary WHERE users_id = $1 AND surface = $2",
+            user_id,
+            surface
+        )
+        .execute(pool)
+        .await?;
+        Ok(deleted > 0)
+    }
+
+    /// Adds or updates `entries`, first removing every other entry if
+    /// `replace` is set. Returns `false` without changing anything if the
+    /// user would end up with more than `limit` entries. The user row is
+    /// locked for the whole import.
+    pub async fn import(
+        pool: &PgPool,
+        user_id: i64,
+        entries: &[DictionaryEntry],
+        replace: bool,
+        limit: usize,
+    ) -> Result<bool, AppError> {
+        let mut tx = pool.begin().await?;
+        User::lock(&mut tx, user_id).await?;
+        let mut surfaces: HashSet<&str> = entries.iter().map(|e| e.surface.as_str()).collect();
+        let existing = if replace {
+            query!("DELETE FROM user_dictionary WHERE users_id = $1", user_id)
+                .execute(&mut tx)
+                .await?;
+            Vec::new()
+        } else {
+            query!(
+                "SELECT surface FROM user_dictionary WHERE users_id = $1",
+                user_id
+            )
+            .fetch_all(&mut tx)
+            .await?
+        };
+        surfaces.extend(existing.iter().map(|row| row.surface.as_str()));
+        if surfaces.len() > limit {
+            return Ok(false);
+        }
+        for entry in entries {
+            query!(
+                r#"
+                    INSERT INTO user_dictionary (users_id, surface, reading) VALUES ($1, $2, $3)
+                    ON CONFLICT (users_id, surface)
+                    DO UPDATE SET reading = $3
+                "#,
+                user_id,
+                entry.surface,
+                entry.reading
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+pub fn is_kana(c: char) -> bool {
+    matches!(c, 'ぁ'..='ゖ' | 'ァ'..='ヺ' | 'ー' | 'ゝ' | 'ゞ' | 'ヽ' | 'ヾ')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrate;
+    use futures::future;
+
+    /// Connects to the scratch database at `TEST_DATABASE_URL`, or returns
+    /// `None` to skip the test when it is not set.
+    async fn database() -> Option<PgPool> {
+        let url = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("TEST_DATABASE_URL is not set, skipping");
+                return None;
+            }
+        };
+        let pool = PgPool::new(&url).await.unwrap();
+        migrate::run(&pool).await.unwrap();
+        Some(pool)
+    }
+
+    fn entry(surface: &str) -> DictionaryEntry {
+        DictionaryEntry {
+            surface: surface.to_string(),
+            reading: "テスト".to_string(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn concurrent_creates_stay_within_the_limit() {
+        let pool = match database().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let user_id = i64::from(rand::random::<u32>()) + (1 << 40);
+        User::create(&pool, user_id).await.unwrap();
+
+        let entries: Vec<DictionaryEntry> = (0..8).map(|i| entry(&format!("語{}", i))).collect();
+        let results =
+            future::join_all(entries.iter().map(|entry| entry.create(&pool, user_id, 3))).await;
+        let created = results
+            .into_iter()
+            .filter(|result| *result.as_ref().unwrap() == Creation::Created)
+            .count();
+        assert_eq!(created, 3);
+        assert_eq!(
+            DictionaryEntry::list(&pool, user_id).await.unwrap().len(),
+            3
+        );
+
+        let existing = DictionaryEntry::list(&pool, user_id).await.unwrap();
+        assert_eq!(
+            existing[0].create(&pool, user_id, 10).await.unwrap(),
+            Creation::Exists
+        );
+    }
+
+    #[actix_rt::test]
+    async fn imports_over_the_limit_change_nothing() {
+        let pool = match database().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let user_id = i64::from(rand::random::<u32>()) + (1 << 40);
+        User::create(&pool, user_id).await.unwrap();
+        entry("既存").create(&pool, user_id, 10).await.unwrap();
+
+        let imported = vec![entry("一"), entry("二")];
+        assert!(
+            !DictionaryEntry::import(&pool, user_id, &imported, false, 2)
+                .await
+                .unwrap()
+        );
+        assert!(!DictionaryEntry::import(&pool, user_id, &imported, true, 1)
+            .await
+            .unwrap());
+        let surfaces: Vec<String> = DictionaryEntry::list(&pool, user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.surface)
+            .collect();
+        assert_eq!(surfaces, vec!["既存"]);
+
+        assert!(DictionaryEntry::import(&pool, user_id, &imported, true, 2)
+            .await
+            .unwrap());
+        assert_eq!(
+            DictionaryEntry::list(&pool, user_id).await.unwrap().len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{error::AppError, models::dictionary::is_kana};
+use chrono::{DateTime, Utc};
+use sqlx::{pool::PoolConnection, query, query_as, PgConnection, PgPool, Transaction};
+
+pub const DEFAULT_PART_OF_SPEECH: &str = "名詞,固有名詞,一般,*";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GlobalDictionaryEntry {
+    pub surface: String,
+    pub reading: String,
+    pub accent_type: i32,
+    #[serde(default = "default_part_of_speech")]
+    pub part_of_speech: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GlobalDictionaryVersion {
+    pub version: i32,
+    pub entry_count: i32,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+fn default_part_of_speech() -> String {
+    DEFAULT_PART_OF_SPEECH.to_string()
+}
+
+impl GlobalDictionaryEntry {
+    /// Checks that the entry is well-formed and can be written as one row of
+    /// the MeCab source CSV.
+    pub fn validate(&self) -> Result<(), String> {
+        let fields = [&self.surface, &self.reading, &self.part_of_speech];
+        if fields
+            .iter()
+            .any(|field| field.chars().any(char::is_control))
+        {
+            return Err(format!(
+                "Entry {:?} must not contain control characters.",
+                self.surface
+            ));
+        }
+        if self.surface.is_empty() || self.surface.contains(',') {
+            return Err(format!("Invalid surface: {:?}", self.surface));
+        }
+        if self.reading.is_empty() || !self.reading.chars().all(is_kana) {
+            return Err(format!("Reading must be written in kana: {}", self.reading));
+        }
+        if self.accent_type < 0 || self.accent_type as usize > self.mora_count() {
+            return Err(format!(
+                "Accent type of {} must be between 0 and {}.",
+                self.surface,
+                self.mora_count()
+            ));
+        }
+        if self.part_of_speech.split(',').count() != 4 {
+            return Err(format!(
+                "Part of speech must have 4 comma separated fields: {}",
+                self.part_of_speech
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the reading converted to katakana as OpenJTalk expects.
+    pub fn katakana(&self) -> String {
+        self.reading
+            .chars()
+            .map(|c| match c {
+                'ぁ'..='ゖ' => std::char::from_u32(c as u32 + 0x60).unwrap_or(c),
+                _ => c,
+            })
+            .collect()
+    }
+
+    pub fn mora_count(&self) -> usize {
+        self.katakana()
+            .chars()
+            .filter(|c| !matches!(c, 'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ'))
+            .count()
+    }
+
+    pub async fn list(pool: &PgPool, version: i32) -> Result<Vec<GlobalDictionaryEntry>, AppError> {
+        let entries = query_as!(
+            GlobalDictionaryEntry,
+            r#"
+                SELECT surface, reading, accent_type, part_of_speech FROM global_dictionary_entries
+                WHERE version = $1
+                ORDER BY surface
+            "#,
+            version
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(entries)
+    }
+}
+
+impl GlobalDictionaryVersion {
+    pub async fn create(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        entries: &[GlobalDictionaryEntry],
+    ) -> Result<GlobalDictionaryVersion, AppError> {
+        let version = query_as!(
+            GlobalDictionaryVersion,
+            r#"
+                INSERT INTO global_dictionary_versions (entry_count) VALUES ($1)
+                RETURNING version, entry_count, active, created_at
+            "#,
+            entries.len() as i32
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        for entry in entries {
+            query!(
+                r#"
+                    INSERT INTO global_dictionary_entries
+                    (version, surface, reading, accent_type, part_of_speech)
+                    VALUES ($1, $2, $3, $4, $5)
+                "#,
+                version.version,
+                entry.surface,
+                entry.reading,
+                entry.accent_type,
+                entry.part_of_speech
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(version)
+    }
+
+    pub async fn list(pool: &PgPool) -> Result<Vec<GlobalDictionaryVersion>, AppError> {
+        let versions = query_as!(
+            GlobalDictionaryVersion,
+            "SELECT * FROM global_dictionary_versions ORDER BY version DESC"
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(versions)
+    }
+
+    pub async fn get(pool: &PgPool, version: i32) -> Result<GlobalDictionaryVersion, AppError> {
+        let version = query_as!(
+            GlobalDictionaryVersion,
+            "SELECT * FROM global_dictionary_versions WHERE version = $1",
+            version
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(version)
+    }
+
+    pub async fn active(pool: &PgPool) -> Result<Option<GlobalDictionaryVersion>, AppError> {
+        let result = query_as!(
+            GlobalDictionaryVersion,
+            "SELECT * FROM global_dictionary_versions WHERE active"
+        )
+        .fetch_one(pool)
+        .await;
+        match result {
+            Ok(version) => Ok(Some(version)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn activate(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        version: i32,
+    ) -> Result<(), AppError> {
+        query!(
+            "UPDATE global_dictionary_versions SET active = (version = $1)",
+            version
+        )
+        .execute(tx)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(surface: &str, reading: &str, accent_type: i32) -> GlobalDictionaryEntry {
+        GlobalDictionaryEntry {
+            surface: surface.to_string(),
+            reading: reading.to_string(),
+            accent_type,
+            part_of_speech: DEFAULT_PART_OF_SPEECH.to_string(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_entries() {
+        assert!(entry("東京", "とうきょう", 0).validate().is_ok());
+        assert!(entry("ラズパイ", "ラズパイ", 4).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_commas_in_surface() {
+        assert!(entry("a,b", "えーびー", 0).validate().is_err());
+        assert!(entry("", "えー", 0).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        assert!(entry("a\nb,,,1", "えー", 0).validate().is_err());
+        assert!(entry("a\rb", "えー", 0).validate().is_err());
+        assert!(entry("ab", "えー\t", 0).validate().is_err());
+
+        let mut injected = entry("ab", "えーびー", 0);
+        injected.part_of_speech = "名詞,固有名詞,一般,*\nx,,,1,名詞".to_string();
+        assert!(injected.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_non_kana_readings() {
+        assert!(entry("東京", "tokyo", 0).validate().is_err());
+        assert!(entry("東京", "", 0).validate().is_err());
+    }
+
+    #[test]
+    fn bounds_accent_type_by_mora_count() {
+        // キャット has 3 morae: キャ, ッ, ト.
+        assert!(entry("cat", "キャット", 3).validate().is_ok());
+        assert!(entry("cat", "キャット", 4).validate().is_err());
+        assert!(entry("cat", "キャット", -1).validate().is_err());
+    }
+
+    #[test]
+    fn requires_four_part_of_speech_fields() {
+        let mut entry = entry("東京", "とうきょう", 0);
+        entry.part_of_speech = "名詞,固有名詞".to_string();
+        assert!(entry.validate().is_err());
+    }
+
+    #[test]
+    fn converts_readings_to_katakana() {
+        assert_eq!(entry("x", "とうきょう", 0).katakana(), "トウキョウ");
+        assert_eq!(entry("x", "とうきょう", 0).mora_count(), 4);
+    }
+}
@@ -0,0 +1,516 @@
+use crate::error::AppError;
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, PgPool};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued = 0,
+    Running = 1,
+    Done = 2,
+    Failed = 3,
+}
+
+impl From<i32> for JobStatus {
+    fn from(value: i32) -> JobStatus {
+        match value {
+            1 => JobStatus::Running,
+            2 => JobStatus::Done,
+            3 => JobStatus::Failed,
+            _ => JobStatus::Queued,
+        }
+    }
+}
+
+impl Default for JobStatus {
+    fn default() -> JobStatus {
+        JobStatus::Queued
+    }
+}
+
+/// A queued synthesis request. The rendered audio is loaded separately with
+/// [`Job::audio`].
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub users_id: i64,
+    pub status: i32,
+    pub request: String,
+    pub callback_url: Option<String>,
+    pub segments_done: i32,
+    pub segments_total: i32,
+    pub attempts: i32,
+    pub content_type: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub heartbeat_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn status(&self) -> JobStatus {
+        JobStatus::from(self.status)
+    }
+
+    pub async fn create(
+        pool: &PgPool,
+        users_id: i64,
+        request: &str,
+        callback_url: Option<&str>,
+    ) -> Result<Job, AppError> {
+        let job = query_as!(
+            Job,
+            r#"
+                INSERT INTO synthesis_jobs (users_id, request, callback_url)
+                VALUES ($1, $2, $3)
+                RETURNING id, users_id, status, request, callback_url, segments_done,
+                    segments_total, attempts, content_type, error, created_at, started_at,
+                    heartbeat_at, finished_at
+            "#,
+            users_id,
+            request,
+            callback_url
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(job)
+    }
+
+    /// Gets a job of a user. Fails with `RowNotFound` for other users' jobs.
+    pub async fn get(pool: &PgPool, id: i64, users_id: i64) -> Result<Job, AppError> {
+        let job = query_as!(
+            Job,
+            r#"
+                SELECT id, users_id, status, request, callback_url, segments_done,
+                    segments_total, attempts, content_type, error, created_at, started_at,
+                    heartbeat_at, finished_at
+                FROM synthesis_jobs
+                WHERE id = $1 AND users_id = $2
+            "#,
+            id,
+            users_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(job)
+    }
+
+    /// Marks the oldest runnable job as running and returns it. Queued jobs
+    /// are runnable, as are running jobs whose worker stopped sending
+    /// heartbeats before `stale_before` and which have attempts left. Rows
+    /// locked by other workers are skipped, so concurrent workers never
+    /// claim the same job.
+    pub async fn claim(
+        pool: &PgPool,
+        stale_before: DateTime<Utc>,
+        max_attempts: i32,
+    ) -> Result<Option<Job>, AppError> {
+        let result = query_as!(
+            Job,
+            r#"
+                UPDATE synthesis_jobs
+                SET status = 1, attempts = attempts + 1, segments_done = 0,
+                    started_at = now(), heartbeat_at = now()
+                WHERE id = (
+                    SELECT id FROM synthesis_jobs
+                    WHERE status = 0
+                        OR (status = 1 AND heartbeat_at < $1 AND attempts < $2)
+                    ORDER BY id
+                    FOR UPDATE SKIP LOCKED
+                    LIMIT 1
+                )
+                RETURNING id, users_id, status, request, callback_url, segments_done,
+                    segments_total, attempts, content_type, error, created_at, started_at,
+                    heartbeat_at, finished_at
+            "#,
+            stale_before,
+            max_attempts
+        )
+        .fetch_one(pool)
+        .await;
+        match result {
+            Ok(job) => Ok(Some(job)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Puts a running job back in the queue without counting the
+    /// interrupted attempt, so that another worker picks it up.
+    pub async fn requeue(pool: &PgPool, id: i64) -> Result<(), AppError> {
+        query!(
+            r#"
+                UPDATE synthesis_jobs
+                SET status = 0, attempts = GREATEST(attempts - 1, 0), segments_done = 0,
+                    started_at = NULL, heartbeat_at = NULL
+                WHERE id = $1 AND status = 1
+            "#,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Fails running jobs that went stale after their last attempt and
+    /// returns them.
+    pub async fn fail_stale(
+        pool: &PgPool,
+        stale_before: DateTime<Utc>,
+        max_attempts: i32,
+    ) -> Result<Vec<Job>, AppError> {
+        let jobs = query_as!(
+            Job,
+            r#"
+                UPDATE synthesis_jobs
+                SET status = 3, error = 'Worker stopped responding.', finished_at = now()
+                WHERE status = 1 AND heartbeat_at < $1 AND attempts >= $2
+                RETURNING id, users_id, status, request, callback_url, segments_done,
+                    segments_total, attempts, content_type, error, created_at, started_at,
+                    heartbeat_at, finished_at
+            "#,
+            stale_before,
+            max_attempts
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(jobs)
+    }
+
+    /// Records progress and refreshes the heartbeat of a running job.
+    pub async fn report(pool: &PgPool, id: i64, done: i32, total: i32) -> Result<(), AppError> {
+        query!(
+            r#"
+                UPDATE synthesis_jobs
+                SET segments_done = $1, segments_total = $2, heartbeat_at = now()
+                WHERE id = $3 AND status = 1
+            "#,
+            done,
+            total,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Stores the audio of a job and marks it done, if attempt `attempt` is
+    /// still running it. Returns false when the job went stale and was
+    /// failed or claimed again in the meantime.
+    pub async fn complete(
+        pool: &PgPool,
+        id: i64,
+        attempt: i32,
+        audio: &[u8],
+        content_type: &str,
+    ) -> Result<bool, AppError> {
+        let count = query!(
+            r#"
+                UPDATE synthesis_jobs
+                SET status = 2, segments_done = segments_total, audio = $1, content_type = $2,
+                    finished_at = now()
+                WHERE id = $3 AND status = 1 AND attempts = $4
+            "#,
+            audio,
+            content_type,
+            id,
+            attempt
+        )
+        .execute(pool)
+        .await?;
+        Ok(count > 0)
+    }
+
+    /// Marks a job failed, if attempt `attempt` is still running it. Returns
+    /// false like [`Job::complete`].
+    pub async fn fail(pool: &PgPool, id: i64, attempt: i32, error: &str) -> Result<bool, AppError> {
+        let count = query!(
+            r#"
+                UPDATE synthesis_jobs
+                SET status = 3, error = $1, finished_at = now()
+                WHERE id = $2 AND status = 1 AND attempts = $3
+            "#,
+            error,
+            id,
+            attempt
+        )
+        .execute(pool)
+        .await?;
+        Ok(count > 0)
+    }
+
+    /// Deletes jobs that finished before `finished_before`, with their audio
+    /// and webhook deliveries, and returns how many were deleted.
+    pub async fn purge(pool: &PgPool, finished_before: DateTime<Utc>) -> Result<u64, AppError> {
+        let count = query!(
+            "DELETE FROM synthesis_jobs WHERE status IN (2, 3) AND finished_at < $1",
+            finished_before
+        )
+        .execute(pool)
+        .await?;
+        Ok(count)
+    }
+
+    /// Loads the rendered audio of a finished job of a user.
+    pub async fn audio(
+        pool: &PgPool,
+        id: i64,
+        users_id: i64,
+    ) -> Result<Option<(Vec<u8>, String)>, AppError> {
+        let row = query!(
+            r#"
+                SELECT audio, content_type FROM synthesis_jobs
+                WHERE id = $1 AND users_id = $2 AND status = 2
+            "#,
+            id,
+            users_id
+        )
+        .fetch_one(pool)
+        .await;
+        match row {
+            Ok(row) => Ok(row.audio.zip(row.content_type)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{migrate, models::users::User};
+    use futures::lock::{Mutex, MutexGuard};
+
+    lazy_static! {
+        /// Claims see every job in the table, so tests using the database run
+        /// one at a time.
+        static ref DATABASE: Mutex<()> = Mutex::new(());
+    }
+
+    /// Connects to the scratch database at `TEST_DATABASE_URL` and deletes
+    /// its unfinished jobs. Returns `None`, skipping the test, when it is
+    /// not set.
+    async fn database() -> Option<(PgPool, MutexGuard<'static, ()>)> {
+        let url = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("TEST_DATABASE_URL is not set, skipping");
+                return None;
+            }
+        };
+        let guard = DATABASE.lock().await;
+        let pool = PgPool::new(&url).await.unwrap();
+        migrate::run(&pool).await.unwrap();
+        query!("DELETE FROM synthesis_jobs WHERE status IN (0, 1)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        Some((pool, guard))
+    }
+
+    async fn user(pool: &PgPool) -> i64 {
+        let id = i64::from(rand::random::<u32>()) + (1 << 40);
+        User::create(pool, id).await.unwrap();
+        id
+    }
+
+    /// Moves the heartbeat of a running job an hour into the past.
+    async fn stop_heartbeat(pool: &PgPool, id: i64) {
+        query!(
+            "UPDATE synthesis_jobs SET heartbeat_at = now() - interval '1 hour' WHERE id = $1",
+            id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    fn stale_before() -> DateTime<Utc> {
+        Utc::now() - chrono::Duration::minutes(1)
+    }
+
+    #[actix_rt::test]
+    async fn claims_queued_jobs_in_order_once() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let first = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        let second = Job::create(&pool, users_id, "{}", None).await.unwrap();
+
+        let claimed = Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        assert_eq!(claimed.id, first.id);
+        assert_eq!(claimed.status(), JobStatus::Running);
+        assert_eq!(claimed.attempts, 1);
+        assert!(claimed.heartbeat_at.is_some());
+        let claimed = Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        assert_eq!(claimed.id, second.id);
+        assert!(Job::claim(&pool, stale_before(), 3)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_rt::test]
+    async fn reports_refresh_the_heartbeat() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let job = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        stop_heartbeat(&pool, job.id).await;
+
+        Job::report(&pool, job.id, 2, 5).await.unwrap();
+        let job = Job::get(&pool, job.id, users_id).await.unwrap();
+        assert_eq!((job.segments_done, job.segments_total), (2, 5));
+        assert!(job.heartbeat_at.unwrap() > stale_before());
+        // A job with a fresh heartbeat is not taken over.
+        assert!(Job::claim(&pool, stale_before(), 3)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_rt::test]
+    async fn stale_jobs_are_retried_until_attempts_run_out() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let job = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        Job::claim(&pool, stale_before(), 2).await.unwrap().unwrap();
+        stop_heartbeat(&pool, job.id).await;
+        assert!(Job::fail_stale(&pool, stale_before(), 2)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let retried = Job::claim(&pool, stale_before(), 2).await.unwrap().unwrap();
+        assert_eq!(retried.id, job.id);
+        assert_eq!(retried.attempts, 2);
+        assert_eq!(retried.segments_done, 0);
+        stop_heartbeat(&pool, job.id).await;
+        assert!(Job::claim(&pool, stale_before(), 2)
+            .await
+            .unwrap()
+            .is_none());
+
+        let failed = Job::fail_stale(&pool, stale_before(), 2).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].id, job.id);
+        assert_eq!(failed[0].status(), JobStatus::Failed);
+        assert!(failed[0].finished_at.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn requeued_jobs_keep_their_attempts() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let job = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        Job::requeue(&pool, job.id).await.unwrap();
+
+        let claimed = Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        assert_eq!(claimed.id, job.id);
+        assert_eq!(claimed.attempts, 1);
+    }
+
+    #[actix_rt::test]
+    async fn only_the_current_attempt_finishes_a_job() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let job = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        let lost = Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        stop_heartbeat(&pool, job.id).await;
+        let current = Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        assert_eq!(current.id, job.id);
+
+        // The worker that went stale no longer owns the job.
+        assert!(
+            !Job::complete(&pool, job.id, lost.attempts, b"old", "audio/wav")
+                .await
+                .unwrap()
+        );
+        assert!(!Job::fail(&pool, job.id, lost.attempts, "Old failure.")
+            .await
+            .unwrap());
+        assert_eq!(
+            Job::get(&pool, job.id, users_id).await.unwrap().status(),
+            JobStatus::Running
+        );
+
+        assert!(
+            Job::complete(&pool, job.id, current.attempts, b"new", "audio/wav")
+                .await
+                .unwrap()
+        );
+        // Nor does a finished job change again.
+        assert!(!Job::fail(&pool, job.id, current.attempts, "Late failure.")
+            .await
+            .unwrap());
+        let audio = Job::audio(&pool, job.id, users_id).await.unwrap();
+        assert_eq!(audio, Some((b"new".to_vec(), "audio/wav".to_string())));
+    }
+
+    #[actix_rt::test]
+    async fn stale_jobs_that_failed_are_not_completed() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let job = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        let claimed = Job::claim(&pool, stale_before(), 1).await.unwrap().unwrap();
+        stop_heartbeat(&pool, job.id).await;
+        assert_eq!(
+            Job::fail_stale(&pool, stale_before(), 1)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert!(
+            !Job::complete(&pool, job.id, claimed.attempts, b"audio", "audio/wav")
+                .await
+                .unwrap()
+        );
+        let job = Job::get(&pool, job.id, users_id).await.unwrap();
+        assert_eq!(job.status(), JobStatus::Failed);
+        assert_eq!(job.error.as_deref(), Some("Worker stopped responding."));
+    }
+
+    #[actix_rt::test]
+    async fn purges_finished_jobs() {
+        let (pool, _guard) = match database().await {
+            Some(database) => database,
+            None => return,
+        };
+        let users_id = user(&pool).await;
+        let done = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        let queued = Job::create(&pool, users_id, "{}", None).await.unwrap();
+        let claimed = Job::claim(&pool, stale_before(), 3).await.unwrap().unwrap();
+        assert!(
+            Job::complete(&pool, done.id, claimed.attempts, b"audio", "audio/wav")
+                .await
+                .unwrap()
+        );
+
+        Job::purge(&pool, Utc::now() + chrono::Duration::minutes(1))
+            .await
+            .unwrap();
+        assert!(Job::get(&pool, done.id, users_id).await.is_err());
+        assert!(Job::get(&pool, queued.id, users_id).await.is_ok());
+    }
+}
@@ -1 +1,11 @@
+pub mod account_status;
+pub mod audio;
+pub mod audit;
+pub mod dictionary;
+pub mod global_dictionary;
+pub mod job;
+pub mod plan;
+pub mod preset;
+pub mod role;
 pub mod users;
+pub mod webhook;
@@ -0,0 +1,39 @@
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::str::FromStr;
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Plan {
+    Free = 0,
+    Standard = 1,
+    Premium = 2,
+}
+
+impl From<i32> for Plan {
+    fn from(value: i32) -> Plan {
+        match value {
+            1 => Plan::Standard,
+            2 => Plan::Premium,
+            _ => Plan::Free,
+        }
+    }
+}
+
+impl Default for Plan {
+    fn default() -> Plan {
+        Plan::Free
+    }
+}
+
+impl FromStr for Plan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Plan, String> {
+        match s {
+            "free" => Ok(Plan::Free),
+            "standard" => Ok(Plan::Standard),
+            "premium" => Ok(Plan::Premium),
+            _ => Err(format!("Unknown plan: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    audio::{format::OutputFormat, process::PostProcess},
+    backend::prosody::Prosody,
+    config::Config,
+    error::AppError,
+    tts::request::SynthesisOptions,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, PgPool};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Request defaults bundled in a preset. Unset options are left to the
+/// request.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PresetSettings {
+    pub voice: Option<String>,
+    pub prosody: Option<Prosody>,
+    pub normalize: Option<bool>,
+    pub postprocess: PostProcess,
+    pub output: OutputFormat,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: PresetSettings,
+    pub is_default: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+struct PresetRow {
+    name: String,
+    settings: String,
+    is_default: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl PresetRow {
+    fn into_preset(self) -> Result<Preset, AppError> {
+        Ok(Preset {
+            name: self.name,
+            settings: serde_json::from_str(&self.settings)?,
+            is_default: self.is_default,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Preset names must be 1 to {} letters, digits, '-' or '_': {:?}",
+            MAX_NAME_LENGTH, name
+        ))
+    }
+}
+
+impl PresetSettings {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        if let Some(voice) = &self.voice {
+            if config.voice(Some(voice)).is_none() {
+                return Err(format!("Unknown voice: {}", voice));
+            }
+        }
+        if let Some(prosody) = &self.prosody {
+            prosody.validate()?;
+        }
+        self.postprocess.validate()?;
+        self.output.validate()
+    }
+
+    /// Fills the options a request left unset. The preset's prosody is
+    /// combined with the request's.
+    pub fn apply(&self, options: &mut SynthesisOptions) {
+        if options.voice.is_none() {
+            options.voice = self.voice.clone();
+        }
+        if options.normalize.is_none() {
+            options.normalize = self.normalize;
+        }
+        if let Some(prosody) = &self.prosody {
+            options.prosody = prosody.then(&options.prosody);
+        }
+    }
+}
+
+impl Preset {
+    pub async fn list(pool: &PgPool, users_id: i64) -> Result<Vec<Preset>, AppError> {
+        let rows = query_as!(
+            PresetRow,
+            r#"
+                SELECT name, settings, is_default, created_at, updated_at FROM user_presets
+                WHERE users_id = $1
+                ORDER BY name
+            "#,
+            users_id
+        )
+        .fetch_all(pool)
+        .await?;
+        rows.into_iter().map(PresetRow::into_preset).collect()
+    }
+
+    pub async fn get(pool: &PgPool, users_id: i64, name: &str) -> Result<Option<Preset>, AppError> {
+        let result = query_as!(
+            PresetRow,
+            r#"
+                SELECT name, settings, is_default, created_at, updated_at FROM user_presets
+                WHERE users_id = $1 AND name = $2
+            "#,
+            users_id,
+            name
+        )
+        .fetch_one(pool)
+        .await;
+        match result {
+            Ok(row) => Ok(Some(row.into_preset()?)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn default(pool: &PgPool, users_id: i64) -> Result<Option<Preset>, AppError> {
+        let result = query_as!(
+            PresetRow,
+            r#"
+                SELECT name, settings, is_default, created_at, updated_at FROM user_presets
+                WHERE users_id = $1 AND is_default
+            "#,
+            users_id
+        )
+        .fetch_one(pool)
+        .await;
+        match result {
+            Ok(row) => Ok(Some(row.into_preset()?)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Creates or replaces a preset. Making it the default clears the flag
+    /// on the user's other presets.
+    pub async fn upsert(
+        pool: &PgPool,
+        users_id: i64,
+        name: &str,
+        settings: &PresetSettings,
+        is_default: bool,
+    ) -> Result<Preset, AppError> {
+        let settings = serde_json::to_string(settings)?;
+        let mut tx = pool.begin().await?;
+        if is_default {
+            query!(
+                "UPDATE user_presets SET is_default = FALSE WHERE users_id = $1 AND name <> $2",
+                users_id,
+                name
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        let row = query_as!(
+            PresetRow,
+            r#"
+                INSERT INTO user_presets (users_id, name, settings, is_default)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (users_id, name)
+                DO UPDATE SET settings = $3, is_default = $4, updated_at = now()
+                RETURNING name, settings, is_default, created_at, updated_at
+            "#,
+            users_id,
+            name,
+            settings,
+            is_default
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        row.into_preset()
+    }
+
+    pub async fn delete(pool: &PgPool, users_id: i64, name: &str) -> Result<bool, AppError> {
+        let deleted = query!(
+            "DELETE FROM user_presets WHERE users_id = $1 AND name = $2",
+            users_id,
+            name
+        )
+        .execute(pool)
+        .await?;
+        Ok(deleted > 0)
+    }
+}
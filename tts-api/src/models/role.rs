@@ -0,0 +1,36 @@
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::str::FromStr;
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Role {
+    User = 0,
+    Admin = 1,
+}
+
+impl From<i32> for Role {
+    fn from(value: i32) -> Role {
+        match value {
+            1 => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+impl Default for Role {
+    fn default() -> Role {
+        Role::User
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {}", s)),
+        }
+    }
+}
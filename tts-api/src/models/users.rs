@@ -1,5 +1,8 @@
-use crate::error::AppError;
-use sqlx::{query, query_as, PgPool};
+use crate::{
+    error::AppError,
+    models::{account_status::AccountStatus, plan::Plan, role::Role},
+};
+use sqlx::{pool::PoolConnection, query, query_as, PgConnection, PgPool, Transaction};
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct User {
@@ -7,9 +10,27 @@ pub struct User {
     pub account_status: i32,
     pub character_count: i64,
     pub character_limit: i64,
+    pub plan: i32,
+    pub role: i32,
 }
 
 impl User {
+    pub fn plan(&self) -> Plan {
+        Plan::from(self.plan)
+    }
+
+    pub fn role(&self) -> Role {
+        Role::from(self.role)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role() == Role::Admin
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        AccountStatus::from(self.account_status)
+    }
+
     pub async fn get(pool: &PgPool, id: i64) -> Result<User, AppError> {
         let user = query_as!(User, "SELECT * FROM users WHERE id = $1", id)
             .fetch_one(pool)
@@ -44,9 +65,139 @@ impl User {
     pub async fn use_capability(pool: &PgPool, id: i64, length: i64) -> Result<(), AppError> {
         let user = Self::get(pool, id).await?;
         let new_capability = user.character_count + length;
-        query!("UPDATE users SET character_count = $1", new_capability)
-            .execute(pool)
-            .await?;
+        query!(
+            "UPDATE users SET character_count = $1 WHERE id = $2",
+            new_capability,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Gives back characters charged for work that was never delivered.
+    pub async fn refund(pool: &PgPool, id: i64, length: i64) -> Result<(), AppError> {
+        query!(
+            "UPDATE users SET character_count = GREATEST(character_count - $1, 0) WHERE id = $2",
+            length,
+            id
+        )
+        .execute(pool)
+        .await?;
         Ok(())
     }
+
+    /// Lists users ordered by id. `id_prefix` matches the leading digits of
+    /// the id; unset filters match every user.
+    pub async fn search(
+        pool: &PgPool,
+        filter: &UserFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>, AppError> {
+        let plan = filter.plan.map(|plan| plan as i32);
+        let role = filter.role.map(|role| role as i32);
+        let status = filter.status.map(|status| status as i32);
+        let users = query_as!(
+            User,
+            r#"
+                SELECT * FROM users
+                WHERE ($1::TEXT IS NULL OR CAST(id AS TEXT) LIKE $1 || '%')
+                    AND ($2::INT IS NULL OR plan = $2)
+                    AND ($3::INT IS NULL OR role = $3)
+                    AND ($4::INT IS NULL OR account_status = $4)
+                    AND ($5::BOOLEAN IS NULL OR (character_count >= character_limit) = $5)
+                ORDER BY id
+                LIMIT $6 OFFSET $7
+            "#,
+            filter.id_prefix,
+            plan,
+            role,
+            status,
+            filter.over_quota,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(users)
+    }
+
+    /// Loads a user and locks the row until the transaction ends.
+    pub async fn lock(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        id: i64,
+    ) -> Result<User, AppError> {
+        let user = query_as!(User, "SELECT * FROM users WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(tx)
+            .await?;
+        Ok(user)
+    }
+
+    /// Applies the changes that are set, leaving the other columns as they
+    /// are.
+    pub async fn update(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        id: i64,
+        changes: &UserChanges,
+    ) -> Result<User, AppError> {
+        let plan = changes.plan.map(|plan| plan as i32);
+        let role = changes.role.map(|role| role as i32);
+        let status = changes.status.map(|status| status as i32);
+        let user = query_as!(
+            User,
+            r#"
+                UPDATE users SET
+                    character_limit = COALESCE($2, character_limit),
+                    plan = COALESCE($3, plan),
+                    role = COALESCE($4, role),
+                    account_status = COALESCE($5, account_status)
+                WHERE id = $1
+                RETURNING id, account_status, character_count, character_limit, plan, role
+            "#,
+            id,
+            changes.character_limit,
+            plan,
+            role,
+            status
+        )
+        .fetch_one(tx)
+        .await?;
+        Ok(user)
+    }
+
+    pub async fn reset_count(
+        tx: &mut Transaction<PoolConnection<PgConnection>>,
+        id: i64,
+    ) -> Result<User, AppError> {
+        let user = query_as!(
+            User,
+            r#"
+                UPDATE users SET character_count = 0
+                WHERE id = $1
+                RETURNING id, account_status, character_count, character_limit, plan, role
+            "#,
+            id
+        )
+        .fetch_one(tx)
+        .await?;
+        Ok(user)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct UserFilter {
+    pub id_prefix: Option<String>,
+    pub plan: Option<Plan>,
+    pub role: Option<Role>,
+    pub status: Option<AccountStatus>,
+    pub over_quota: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct UserChanges {
+    pub character_limit: Option<i64>,
+    pub plan: Option<Plan>,
+    pub role: Option<Role>,
+    pub status: Option<AccountStatus>,
 }
@@ -0,0 +1,226 @@
+use crate::{auth::token::Token, error::AppError};
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, PgPool};
+
+const SECRET_LENGTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending = 0,
+    Delivered = 1,
+    Failed = 2,
+}
+
+impl From<i32> for DeliveryStatus {
+    fn from(value: i32) -> DeliveryStatus {
+        match value {
+            1 => DeliveryStatus::Delivered,
+            2 => DeliveryStatus::Failed,
+            _ => DeliveryStatus::Pending,
+        }
+    }
+}
+
+impl Default for DeliveryStatus {
+    fn default() -> DeliveryStatus {
+        DeliveryStatus::Pending
+    }
+}
+
+/// A webhook notification of a job, retried until delivered or out of
+/// attempts.
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub jobs_id: i64,
+    pub users_id: i64,
+    pub url: String,
+    pub payload: String,
+    pub status: i32,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// One request made for a delivery.
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookAttempt {
+    pub deliveries_id: i64,
+    pub attempt: i32,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Returns the user's webhook signing secret, creating one on first use.
+pub async fn secret(pool: &PgPool, users_id: i64) -> Result<String, AppError> {
+    let result = query!(
+        "SELECT secret FROM webhook_secrets WHERE users_id = $1",
+        users_id
+    )
+    .fetch_one(pool)
+    .await;
+    match result {
+        Ok(row) => Ok(row.secret),
+        Err(sqlx::Error::RowNotFound) => rotate_secret(pool, users_id).await,
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces the user's webhook signing secret with a new random one.
+pub async fn rotate_secret(pool: &PgPool, users_id: i64) -> Result<String, AppError> {
+    let secret = Token::generate(SECRET_LENGTH).show();
+    query!(
+        r#"
+            INSERT INTO webhook_secrets (users_id, secret) VALUES ($1, $2)
+            ON CONFLICT (users_id)
+            DO UPDATE SET secret = $2, created_at = now()
+        "#,
+        users_id,
+        secret
+    )
+    .execute(pool)
+    .await?;
+    Ok(secret)
+}
+
+impl WebhookDelivery {
+    pub fn status(&self) -> DeliveryStatus {
+        DeliveryStatus::from(self.status)
+    }
+
+    pub async fn create(
+        pool: &PgPool,
+        jobs_id: i64,
+        users_id: i64,
+        url: &str,
+        payload: &str,
+    ) -> Result<WebhookDelivery, AppError> {
+        let delivery = query_as!(
+            WebhookDelivery,
+            r#"
+                INSERT INTO webhook_deliveries (jobs_id, users_id, url, payload)
+                VALUES ($1, $2, $3, $4)
+                RETURNING *
+            "#,
+            jobs_id,
+            users_id,
+            url,
+            payload
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(delivery)
+    }
+
+    pub async fn list(pool: &PgPool, jobs_id: i64) -> Result<Vec<WebhookDelivery>, AppError> {
+        let deliveries = query_as!(
+            WebhookDelivery,
+            "SELECT * FROM webhook_deliveries WHERE jobs_id = $1 ORDER BY id",
+            jobs_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(deliveries)
+    }
+
+    /// Takes the next due delivery and counts an attempt for it. The delivery
+    /// is not due again until `lease_until`, so it is retried if the process
+    /// dies before recording the result.
+    pub async fn claim(
+        pool: &PgPool,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Option<WebhookDelivery>, AppError> {
+        let result = query_as!(
+            WebhookDelivery,
+            r#"
+                UPDATE webhook_deliveries
+                SET attempts = attempts + 1, next_attempt_at = $1
+                WHERE id = (
+                    SELECT id FROM webhook_deliveries
+                    WHERE status = 0 AND next_attempt_at <= now()
+                    ORDER BY next_attempt_at
+                    FOR UPDATE SKIP LOCKED
+                    LIMIT 1
+                )
+                RETURNING *
+            "#,
+            lease_until
+        )
+        .fetch_one(pool)
+        .await;
+        match result {
+            Ok(delivery) => Ok(Some(delivery)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Logs an attempt and updates the delivery: delivered, failed, or
+    /// pending until `retry_at` when given.
+    pub async fn record(
+        &self,
+        pool: &PgPool,
+        response_status: Option<i32>,
+        error: Option<&str>,
+        duration_ms: i32,
+        status: DeliveryStatus,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), AppError> {
+        let mut tx = pool.begin().await?;
+        query!(
+            r#"
+                INSERT INTO webhook_attempts
+                (deliveries_id, attempt, response_status, error, duration_ms)
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            self.id,
+            self.attempts,
+            response_status,
+            error,
+            duration_ms
+        )
+        .execute(&mut tx)
+        .await?;
+        query!(
+            r#"
+                UPDATE webhook_deliveries
+                SET status = $1, response_status = $2, error = $3,
+                    next_attempt_at = COALESCE($4, next_attempt_at),
+                    finished_at = CASE WHEN $1 = 0 THEN NULL ELSE now() END
+                WHERE id = $5
+            "#,
+            status as i32,
+            response_status,
+            error,
+            retry_at,
+            self.id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+impl WebhookAttempt {
+    pub async fn list(pool: &PgPool, deliveries_id: i64) -> Result<Vec<WebhookAttempt>, AppError> {
+        let attempts = query_as!(
+            WebhookAttempt,
+            r#"
+                SELECT * FROM webhook_attempts
+                WHERE deliveries_id = $1
+                ORDER BY attempt
+            "#,
+            deliveries_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(attempts)
+    }
+}
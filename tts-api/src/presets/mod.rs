@@ -0,0 +1,36 @@
+pub mod routes;
+
+pub use self::routes::init;
+
+use crate::{
+    models::preset::{Preset, PresetSettings},
+    trace,
+};
+use actix_web::HttpResponse;
+use sqlx::PgPool;
+
+/// Loads the settings of the preset named in a request, or of the user's
+/// default preset. Without either, no settings apply.
+pub async fn load(
+    pool: &PgPool,
+    users_id: i64,
+    name: Option<&str>,
+) -> Result<PresetSettings, HttpResponse> {
+    let _stage = trace::stage("preset");
+    let result = match name {
+        Some(name) => match Preset::get(pool, users_id, name).await {
+            Ok(None) => {
+                return Err(HttpResponse::NotFound().body(format!("Preset not found: {}", name)))
+            }
+            result => result,
+        },
+        None => Preset::default(pool, users_id).await,
+    };
+    match result {
+        Ok(preset) => Ok(preset.map(|preset| preset.settings).unwrap_or_default()),
+        Err(e) => {
+            error!("{:?}", e);
+            Err(HttpResponse::InternalServerError().body("Unexpected Error"))
+        }
+    }
+}
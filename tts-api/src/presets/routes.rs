@@ -0,0 +1,149 @@
+use crate::{
+    auth::{authenticate, Credentials},
+    config::Config,
+    error::AppError,
+    models::preset::{validate_name, Preset, PresetSettings},
+};
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use sqlx::PgPool;
+
+const MAX_PRESETS: usize = 50;
+
+#[derive(Deserialize, Debug)]
+struct CreatePresetBody {
+    name: String,
+    #[serde(default)]
+    default: bool,
+    #[serde(flatten)]
+    settings: PresetSettings,
+}
+
+#[derive(Deserialize, Debug)]
+struct UpdatePresetBody {
+    #[serde(default)]
+    default: bool,
+    #[serde(flatten)]
+    settings: PresetSettings,
+}
+
+fn preset_not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("Preset not found.")
+}
+
+#[get("/presets")]
+async fn list_presets(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let presets = Preset::list(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(presets))
+}
+
+#[post("/presets")]
+async fn create_preset(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    query: web::Query<Credentials>,
+    body: web::Json<CreatePresetBody>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let body = body.into_inner();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    if let Err(message) = validate_name(&body.name).and_then(|_| body.settings.validate(&config)) {
+        return Ok(HttpResponse::BadRequest().body(message));
+    }
+
+    let presets = Preset::list(pool, user.id).await?;
+    if presets.iter().any(|preset| preset.name == body.name) {
+        return Ok(HttpResponse::Conflict().body("Preset already exists."));
+    }
+    if presets.len() >= MAX_PRESETS {
+        return Ok(HttpResponse::Forbidden()
+            .body(format!("A user can have at most {} presets.", MAX_PRESETS)));
+    }
+
+    let preset = Preset::upsert(pool, user.id, &body.name, &body.settings, body.default).await?;
+    Ok(HttpResponse::Created().json(preset))
+}
+
+#[get("/presets/{name}")]
+async fn get_preset(
+    pool: web::Data<PgPool>,
+    name: web::Path<String>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    match Preset::get(pool, user.id, &name).await? {
+        Some(preset) => Ok(HttpResponse::Ok().json(preset)),
+        None => Ok(preset_not_found()),
+    }
+}
+
+/// Replaces the settings of a preset.
+#[put("/presets/{name}")]
+async fn update_preset(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    name: web::Path<String>,
+    query: web::Query<Credentials>,
+    body: web::Json<UpdatePresetBody>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let body = body.into_inner();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    if let Err(message) = body.settings.validate(&config) {
+        return Ok(HttpResponse::BadRequest().body(message));
+    }
+    if Preset::get(pool, user.id, &name).await?.is_none() {
+        return Ok(preset_not_found());
+    }
+
+    let preset = Preset::upsert(pool, user.id, &name, &body.settings, body.default).await?;
+    Ok(HttpResponse::Ok().json(preset))
+}
+
+#[delete("/presets/{name}")]
+async fn delete_preset(
+    pool: web::Data<PgPool>,
+    name: web::Path<String>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    if Preset::delete(pool, user.id, &name).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(preset_not_found())
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_presets);
+    cfg.service(create_preset);
+    cfg.service(get_preset);
+    cfg.service(update_preset);
+    cfg.service(delete_preset);
+}
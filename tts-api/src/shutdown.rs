@@ -0,0 +1,330 @@
+//! Graceful shutdown.
+//!
+//! On SIGTERM or SIGINT the process stops claiming jobs and the server
+//! stops accepting connections. In-flight requests and jobs get until
+//! `[shutdown] deadline_secs` to finish; after that, leftover `open_jtalk`
+//! and dictionary compiler processes are killed, interrupted jobs go back to
+//! the queue and characters charged to requests that never finished are
+//! refunded.
+
+use crate::{
+    models::{job::Job, users::User},
+    trace,
+};
+use actix_service::Service;
+use actix_web::{
+    dev::{Server, ServiceRequest, ServiceResponse},
+    rt::{
+        self,
+        signal::{
+            self,
+            unix::{signal, SignalKind},
+        },
+        time::delay_for,
+    },
+    Error,
+};
+use futures::future::{self, Future};
+use sqlx::PgPool;
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    io,
+    pin::Pin,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct State {
+    draining: AtomicBool,
+    deadline: Mutex<Option<Instant>>,
+    /// Process ids of running children started with [`output`].
+    children: Mutex<HashSet<u32>>,
+    /// Running jobs by id.
+    jobs: Mutex<HashSet<i64>>,
+    /// Characters charged by each request in flight, by request key.
+    reservations: Mutex<HashMap<u64, Reservation>>,
+    next_key: AtomicU64,
+}
+
+/// Characters charged by a request, as `(user id, characters)`, with its
+/// request id for logging.
+struct Reservation {
+    request_id: Option<String>,
+    charges: Vec<(i64, i64)>,
+}
+
+lazy_static! {
+    static ref STATE: State = State::default();
+}
+
+thread_local! {
+    /// The key of the request being handled on this thread. Keys are
+    /// assigned here rather than taken from `X-Request-Id`, which clients
+    /// may reuse across concurrent requests.
+    static REQUEST_KEY: Cell<Option<u64>> = Cell::new(None);
+}
+
+pub fn draining() -> bool {
+    STATE.draining.load(Ordering::SeqCst)
+}
+
+/// Starts draining, with `deadline` from now. Later calls keep the first
+/// deadline.
+pub fn begin(deadline: Duration) {
+    let mut current = STATE.deadline.lock().unwrap();
+    if current.is_none() {
+        *current = Some(Instant::now() + deadline);
+        STATE.draining.store(true, Ordering::SeqCst);
+        info!(
+            "Shutting down, waiting up to {}s for in-flight work",
+            deadline.as_secs()
+        );
+    }
+}
+
+/// Starts draining and stops `server` gracefully when the process receives
+/// SIGTERM or SIGINT. The server's own signal handling must be disabled, as
+/// it stops without waiting for in-flight requests on SIGINT.
+pub fn listen(server: Server, deadline: Duration) {
+    rt::spawn(async move {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {:?}", e);
+                return;
+            }
+        };
+        let terminate = Box::pin(terminate.recv());
+        let interrupt = Box::pin(signal::ctrl_c());
+        future::select(terminate, interrupt).await;
+        begin(deadline);
+        server.stop(true).await;
+    });
+}
+
+/// Runs a command to completion like [`Command::output`], registered so
+/// that it can be killed at shutdown.
+pub fn output(command: &mut Command) -> io::Result<Output> {
+    struct Registered(u32);
+
+    impl Drop for Registered {
+        fn drop(&mut self) {
+            STATE.children.lock().unwrap().remove(&self.0);
+        }
+    }
+
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    STATE.children.lock().unwrap().insert(child.id());
+    let _registered = Registered(child.id());
+    child.wait_with_output()
+}
+
+/// Marks a job as running until the guard is dropped.
+pub struct JobGuard(i64);
+
+pub fn track_job(id: i64) -> JobGuard {
+    STATE.jobs.lock().unwrap().insert(id);
+    JobGuard(id)
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        STATE.jobs.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Records characters charged to `user_id` by the current request, to be
+/// refunded if the request is cut off by shutdown.
+pub fn reserve(user_id: i64, length: i64) {
+    if let Some(key) = REQUEST_KEY.with(Cell::get) {
+        STATE
+            .reservations
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Reservation {
+                request_id: trace::current(),
+                charges: Vec::new(),
+            })
+            .charges
+            .push((user_id, length));
+    }
+}
+
+/// A request's future, polled with its key set. Its reservations are
+/// released once it finishes; a request dropped unfinished while draining
+/// keeps them for [`finish`] to refund.
+struct Request<F> {
+    key: u64,
+    finished: bool,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Request<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        let previous = REQUEST_KEY.with(|key| key.replace(Some(this.key)));
+        let poll = this.inner.as_mut().poll(cx);
+        REQUEST_KEY.with(|key| key.set(previous));
+        if poll.is_ready() {
+            this.finished = true;
+        }
+        poll
+    }
+}
+
+impl<F> Drop for Request<F> {
+    fn drop(&mut self) {
+        if self.finished || !draining() {
+            STATE.reservations.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+/// Tracks the reservations of a request. Must run inside
+/// [`trace::middleware`], so that reservations carry the request id.
+pub fn middleware<S, B>(
+    request: ServiceRequest,
+    service: &mut S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    track(service.call(request))
+}
+
+fn track<F: Future>(inner: F) -> Request<F> {
+    Request {
+        key: STATE.next_key.fetch_add(1, Ordering::Relaxed),
+        finished: false,
+        inner: Box::pin(inner),
+    }
+}
+
+/// Refunds every reservation left by unfinished requests. A failed refund
+/// is logged and does not stop the others.
+async fn refund(pool: &PgPool) {
+    let reservations: Vec<Reservation> = STATE
+        .reservations
+        .lock()
+        .unwrap()
+        .drain()
+        .map(|(_, reservation)| reservation)
+        .collect();
+    for reservation in reservations {
+        let request_id = reservation.request_id.as_deref().unwrap_or("-");
+        for (user_id, length) in reservation.charges {
+            match User::refund(pool, user_id, length).await {
+                Ok(()) => info!(
+                    "Refunded {} characters to user {} for unfinished request {}",
+                    length, user_id, request_id
+                ),
+                Err(e) => error!(
+                    "Failed to refund {} characters to user {} for request {}: {:?}",
+                    length, user_id, request_id, e
+                ),
+            }
+        }
+    }
+}
+
+/// Waits for running jobs and subprocesses until the deadline, then
+/// cleans up whatever is left. Call once the server has stopped.
+pub async fn finish(pool: &PgPool, deadline: Duration) {
+    begin(deadline);
+    let deadline = STATE.deadline.lock().unwrap().unwrap_or_else(Instant::now);
+    loop {
+        let busy =
+            !STATE.jobs.lock().unwrap().is_empty() || !STATE.children.lock().unwrap().is_empty();
+        if !busy || Instant::now() >= deadline {
+            break;
+        }
+        delay_for(POLL_INTERVAL).await;
+    }
+
+    let children: Vec<u32> = STATE.children.lock().unwrap().drain().collect();
+    for pid in children {
+        warn!("Killing child process {}", pid);
+        // SAFETY: kill only sends a signal. Pids are unregistered as soon
+        // as their child is reaped, so the pid still names our child.
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    let jobs: Vec<i64> = STATE.jobs.lock().unwrap().drain().collect();
+    for id in jobs {
+        match Job::requeue(pool, id).await {
+            Ok(()) => warn!("Requeued job {} interrupted by shutdown", id),
+            Err(e) => error!("Failed to requeue job {}: {:?}", id, e),
+        }
+    }
+
+    refund(pool).await;
+    info!("Shutdown complete");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{channel::oneshot, poll};
+
+    fn charges(key: u64) -> Option<Vec<(i64, i64)>> {
+        STATE
+            .reservations
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|reservation| reservation.charges.clone())
+    }
+
+    #[actix_rt::test]
+    async fn reservations_are_kept_per_request() {
+        let (release, released) = oneshot::channel::<()>();
+        let mut first = track(async {
+            reserve(1, 10);
+            released.await.ok();
+            reserve(1, 2);
+        });
+        assert!(poll!(&mut first).is_pending());
+        assert_eq!(charges(first.key), Some(vec![(1, 10)]));
+
+        let second = track(async { reserve(2, 5) });
+        let second_key = second.key;
+        second.await;
+        assert_eq!(charges(second_key), None);
+        assert_eq!(charges(first.key), Some(vec![(1, 10)]));
+
+        release.send(()).unwrap();
+        let first_key = first.key;
+        first.await;
+        assert_eq!(charges(first_key), None);
+
+        // Charges made outside a request are not tracked.
+        reserve(3, 1);
+        assert!(STATE.reservations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn children_are_unregistered_once_reaped() {
+        let output = output(Command::new("sh").args(&["-c", "echo out; exit 3"])).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert!(STATE.children.lock().unwrap().is_empty());
+    }
+}
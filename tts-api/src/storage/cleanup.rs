@@ -0,0 +1,45 @@
+use crate::{error::AppError, models::audio::StoredAudio, storage::Storage};
+use actix_web::rt::{self, time::delay_for};
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// Number of expired clips deleted per query.
+const BATCH_SIZE: i64 = 100;
+
+/// Starts deleting expired clips every `interval` on the current arbiter.
+pub fn spawn(pool: PgPool, storage: Storage, interval: Duration) {
+    rt::spawn(async move {
+        loop {
+            match cleanup(&pool, &storage).await {
+                Ok(0) => {}
+                Ok(count) => info!("Deleted {} expired audio clips", count),
+                Err(e) => error!("Audio cleanup failed: {:?}", e),
+            }
+            delay_for(interval).await;
+        }
+    });
+}
+
+/// Deletes expired clips and returns how many were deleted. Clips whose
+/// object fails to delete are left for the next pass.
+pub async fn cleanup(pool: &PgPool, storage: &Storage) -> Result<usize, AppError> {
+    let mut count = 0;
+    loop {
+        let expired = StoredAudio::expired(pool, BATCH_SIZE).await?;
+        let mut deleted = 0;
+        for audio in &expired {
+            // The row is kept so that the next pass retries the object.
+            if let Err(e) = storage.delete(&audio.storage_key).await {
+                warn!("Failed to delete expired audio {}: {:?}", audio.id, e);
+                continue;
+            }
+            StoredAudio::delete(pool, audio.id).await?;
+            deleted += 1;
+        }
+        count += deleted;
+        // Stop at the end, or when only objects failing to delete are left.
+        if (expired.len() as i64) < BATCH_SIZE || deleted == 0 {
+            return Ok(count);
+        }
+    }
+}
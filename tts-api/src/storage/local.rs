@@ -0,0 +1,51 @@
+use crate::{error::AppError, trace};
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+/// Stores objects as files under a root directory.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> LocalStorage {
+        LocalStorage { root }
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), AppError> {
+        let path = self.root.join(key);
+        trace::block(move || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Written aside and renamed, so readers never see partial files.
+            let partial = path.with_extension("part");
+            fs::write(&partial, &data)?;
+            fs::rename(&partial, &path)?;
+            Ok::<_, AppError>(())
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, AppError> {
+        let path = self.root.join(key);
+        let data = trace::block(move || match fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(AppError::from(e)),
+        })
+        .await?;
+        Ok(data)
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), AppError> {
+        let path = self.root.join(key);
+        trace::block(move || match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(AppError::from(e)),
+            _ => Ok(()),
+        })
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+pub mod cleanup;
+pub mod local;
+pub mod routes;
+pub mod s3;
+
+pub use self::routes::init;
+
+use self::{local::LocalStorage, s3::S3Storage};
+use crate::{
+    auth::token::Token,
+    config::{Config, StorageBackend, StorageConfig},
+    crypto,
+    error::AppError,
+    metrics,
+    models::{audio::StoredAudio, users::User},
+    tts::request::AudioFormat,
+};
+use actix_web::HttpResponse;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use std::env;
+
+const KEY_LENGTH: usize = 32;
+
+/// The configured audio storage backend.
+#[derive(Debug, Clone)]
+pub enum Storage {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl Storage {
+    pub fn from_config(config: &StorageConfig) -> Result<Storage, AppError> {
+        Ok(match config.backend {
+            StorageBackend::Local => Storage::Local(LocalStorage::new(config.path.clone())),
+            StorageBackend::S3 => Storage::S3(S3Storage::new(&config.s3)?),
+        })
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), AppError> {
+        match self {
+            Storage::Local(storage) => storage.put(key, data).await,
+            Storage::S3(storage) => storage.put(key, data, content_type).await,
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, AppError> {
+        match self {
+            Storage::Local(storage) => storage.get(key).await,
+            Storage::S3(storage) => storage.get(key).await,
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match self {
+            Storage::Local(storage) => storage.delete(key).await,
+            Storage::S3(storage) => storage.delete(key).await,
+        }
+    }
+}
+
+pub fn quota_exceeded() -> HttpResponse {
+    metrics::QUOTA_REJECTIONS.inc(&["storage"]);
+    HttpResponse::Forbidden().body("Storage quota exceeded.")
+}
+
+/// Returns the storage bytes the user has left under their plan. Only a hint
+/// for rejecting requests early; [`store`] enforces the quota.
+pub async fn remaining(pool: &PgPool, user: &User, config: &Config) -> Result<i64, AppError> {
+    let limit = config.plans.limits(user.plan()).max_storage_bytes;
+    let usage = StoredAudio::usage(pool, user.id).await?;
+    Ok(limit - usage)
+}
+
+/// Saves a clip for the user, to be deleted after the retention period.
+/// Returns `None`, keeping nothing, when the clip does not fit in the user's
+/// storage quota.
+pub async fn store(
+    pool: &PgPool,
+    storage: &Storage,
+    config: &Config,
+    user: &User,
+    data: Vec<u8>,
+    format: AudioFormat,
+    duration: f64,
+) -> Result<Option<StoredAudio>, AppError> {
+    let key = format!(
+        "{}/{}.{}",
+        user.id,
+        Token::generate(KEY_LENGTH).show().to_lowercase(),
+        format.extension()
+    );
+    let size = data.len() as i64;
+    storage.put(&key, data, format.content_type()).await?;
+    let expires_at = match config.storage.retention_days {
+        days if days > 0 => Some(Utc::now() + Duration::days(days)),
+        _ => None,
+    };
+    let result = StoredAudio::create(
+        pool,
+        user.id,
+        &key,
+        format.content_type(),
+        size,
+        duration,
+        expires_at,
+        config.plans.limits(user.plan()).max_storage_bytes,
+    )
+    .await;
+    if !matches!(result, Ok(Some(_))) {
+        // Don't leave an object no row refers to.
+        if let Err(e) = storage.delete(&key).await {
+            warn!("Failed to delete orphaned object {}: {:?}", key, e);
+        }
+    }
+    result
+}
+
+/// Returns the key share URLs are signed with, if sharing is configured.
+pub fn signing_key(config: &StorageConfig) -> Option<String> {
+    if !config.signing_key.is_empty() {
+        return Some(config.signing_key.clone());
+    }
+    env::var("AUDIO_SIGNING_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+}
+
+/// Signs access to clip `id` until the unix time `expires`.
+pub fn share_signature(key: &str, id: i64, expires: i64) -> String {
+    let message = format!("{}:{}", id, expires);
+    crypto::hex(&crypto::hmac_sha256(key.as_bytes(), message.as_bytes()))
+}
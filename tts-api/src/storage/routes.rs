@@ -0,0 +1,189 @@
+use crate::{
+    auth::{authenticate, Credentials},
+    config::Config,
+    crypto,
+    error::AppError,
+    models::audio::StoredAudio,
+    storage::{share_signature, signing_key, Storage},
+};
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::PgPool;
+
+#[derive(Deserialize, Debug)]
+struct AudioQuery {
+    id: Option<i64>,
+    token: Option<String>,
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ShareQuery {
+    id: i64,
+    token: String,
+    expires_in: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct AudioListResponse {
+    usage: i64,
+    limit: i64,
+    items: Vec<StoredAudio>,
+}
+
+#[derive(Serialize, Debug)]
+struct ShareResponse {
+    url: String,
+    expires_at: DateTime<Utc>,
+}
+
+fn audio_not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("Audio not found.")
+}
+
+#[get("/audio")]
+async fn list_audio(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    Ok(HttpResponse::Ok().json(AudioListResponse {
+        usage: StoredAudio::usage(pool, user.id).await?,
+        limit: config.plans.limits(user.plan()).max_storage_bytes,
+        items: StoredAudio::list(pool, user.id).await?,
+    }))
+}
+
+/// Serves a stored clip to its owner, or to anyone with a valid share
+/// signature.
+#[get("/audio/{audio_id}")]
+async fn get_audio(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    storage: web::Data<Storage>,
+    audio_id: web::Path<i64>,
+    query: web::Query<AudioQuery>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let audio_id = *audio_id;
+
+    let audio = if let (Some(expires), Some(signature)) = (query.expires, &query.signature) {
+        let key = match signing_key(&config.storage) {
+            Some(key) => key,
+            None => return Ok(HttpResponse::Forbidden().body("Sharing is disabled.")),
+        };
+        let expected = share_signature(&key, audio_id, expires);
+        if !crypto::constant_time_eq(signature, &expected) {
+            return Ok(HttpResponse::Forbidden().body("Invalid signature."));
+        }
+        if expires < Utc::now().timestamp() {
+            return Ok(HttpResponse::Gone().body("Link has expired."));
+        }
+        StoredAudio::find(pool, audio_id).await
+    } else {
+        let (id, token) = match (query.id, &query.token) {
+            (Some(id), Some(token)) => (id, token),
+            _ => return Ok(HttpResponse::Unauthorized().body("Credentials required.")),
+        };
+        let user = match authenticate(pool, id, token).await {
+            Ok(user) => user,
+            Err(e) => return Ok(e),
+        };
+        StoredAudio::get(pool, audio_id, user.id).await
+    };
+    let audio = match audio {
+        Ok(audio) => audio,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => return Ok(audio_not_found()),
+        Err(e) => return Err(e),
+    };
+
+    match storage.get(&audio.storage_key).await? {
+        Some(data) => Ok(HttpResponse::Ok()
+            .content_type(audio.content_type.as_str())
+            .body(data)),
+        None => Ok(audio_not_found()),
+    }
+}
+
+/// Creates an expiring URL serving a clip without credentials.
+#[post("/audio/{audio_id}/share")]
+async fn share_audio(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    audio_id: web::Path<i64>,
+    query: web::Query<ShareQuery>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+    let key = match signing_key(&config.storage) {
+        Some(key) => key,
+        None => return Ok(HttpResponse::Forbidden().body("Sharing is disabled.")),
+    };
+    let max_share_secs = config.storage.max_share_secs;
+    let expires_in = query.expires_in.unwrap_or(max_share_secs);
+    if expires_in <= 0 || expires_in > max_share_secs {
+        return Ok(HttpResponse::BadRequest().body(format!(
+            "expires_in must be between 1 and {} seconds.",
+            max_share_secs
+        )));
+    }
+
+    let audio = match StoredAudio::get(pool, *audio_id, user.id).await {
+        Ok(audio) => audio,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => return Ok(audio_not_found()),
+        Err(e) => return Err(e),
+    };
+    // Links never outlive the clip.
+    let mut expires = Utc::now().timestamp() + expires_in;
+    if let Some(expires_at) = audio.expires_at {
+        expires = expires.min(expires_at.timestamp());
+    }
+    let signature = share_signature(&key, audio.id, expires);
+    Ok(HttpResponse::Ok().json(ShareResponse {
+        url: config.server.url(&format!(
+            "/audio/{}?expires={}&signature={}",
+            audio.id, expires, signature
+        )),
+        expires_at: Utc.timestamp(expires, 0),
+    }))
+}
+
+#[delete("/audio/{audio_id}")]
+async fn delete_audio(
+    pool: web::Data<PgPool>,
+    storage: web::Data<Storage>,
+    audio_id: web::Path<i64>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let audio = match StoredAudio::get(pool, *audio_id, user.id).await {
+        Ok(audio) => audio,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => return Ok(audio_not_found()),
+        Err(e) => return Err(e),
+    };
+    storage.delete(&audio.storage_key).await?;
+    StoredAudio::delete(pool, audio.id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_audio);
+    cfg.service(get_audio);
+    cfg.service(share_audio);
+    cfg.service(delete_audio);
+}
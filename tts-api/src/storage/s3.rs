@@ -0,0 +1,345 @@
+//! A client for the S3 object API, signing requests with AWS Signature
+//! Version 4. Works with S3-compatible servers such as MinIO.
+
+use crate::{config::S3Config, crypto, error::AppError};
+use actix_web::{
+    client::Client,
+    http::{Method, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use std::{env, time::Duration};
+use url::Url;
+
+/// Objects larger than this are not read back.
+const MAX_OBJECT_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    config: S3Config,
+    host: String,
+    access_key: String,
+    secret_key: String,
+}
+
+/// Percent-encodes a key as S3 expects in the canonical URI, keeping `/`.
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> Result<S3Storage, AppError> {
+        let endpoint = Url::parse(&config.endpoint).map_err(|e| {
+            AppError::StorageError(format!("Invalid S3 endpoint {}: {}", config.endpoint, e))
+        })?;
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                return Err(AppError::StorageError(format!(
+                    "S3 endpoint has no host: {}",
+                    config.endpoint
+                )))
+            }
+        };
+        let credential = |value: &str, name: &str| {
+            if value.is_empty() {
+                env::var(name).unwrap_or_default()
+            } else {
+                value.to_string()
+            }
+        };
+        Ok(S3Storage {
+            config: config.clone(),
+            host,
+            access_key: credential(&config.access_key, "S3_ACCESS_KEY"),
+            secret_key: credential(&config.secret_key, "S3_SECRET_KEY"),
+        })
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!("/{}/{}", self.config.bucket, encode_key(key))
+    }
+
+    /// Returns the signed headers for a request without query parameters,
+    /// made at `now`.
+    fn sign(
+        &self,
+        method: &Method,
+        path: &str,
+        payload: &[u8],
+        now: DateTime<Utc>,
+    ) -> Vec<(&'static str, String)> {
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = crypto::hex(&crypto::sha256(payload));
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method.as_str(),
+            path,
+            self.host,
+            payload_hash,
+            timestamp,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            crypto::hex(&crypto::sha256(canonical_request.as_bytes()))
+        );
+
+        let key = format!("AWS4{}", self.secret_key);
+        let key = crypto::hmac_sha256(key.as_bytes(), date.as_bytes());
+        let key = crypto::hmac_sha256(&key, self.config.region.as_bytes());
+        let key = crypto::hmac_sha256(&key, b"s3");
+        let key = crypto::hmac_sha256(&key, b"aws4_request");
+        let signature = crypto::hex(&crypto::hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        vec![
+            ("Host", self.host.clone()),
+            ("x-amz-content-sha256", payload_hash),
+            ("x-amz-date", timestamp),
+            (
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key, scope, signed_headers, signature
+                ),
+            ),
+        ]
+    }
+
+    /// Sends a signed request and returns the response status and body.
+    async fn request(
+        &self,
+        method: Method,
+        key: &str,
+        payload: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<(StatusCode, Vec<u8>), AppError> {
+        let path = self.path(key);
+        let url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), path);
+        let client = Client::builder()
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .finish();
+        let mut request = client.request(method.clone(), url);
+        for (name, value) in self.sign(&method, &path, &payload, Utc::now()) {
+            request = request.header(name, value);
+        }
+        if let Some(content_type) = content_type {
+            request = request.header("Content-Type", content_type);
+        }
+
+        let mut response = request
+            .send_body(payload)
+            .await
+            .map_err(|e| AppError::StorageError(format!("S3 request failed: {}", e)))?;
+        let body =
+            response.body().limit(MAX_OBJECT_SIZE).await.map_err(|e| {
+                AppError::StorageError(format!("Failed to read S3 response: {}", e))
+            })?;
+        Ok((response.status(), body.to_vec()))
+    }
+
+    fn unexpected(method: &Method, key: &str, status: StatusCode, body: &[u8]) -> AppError {
+        AppError::StorageError(format!(
+            "S3 {} {} responded with {}: {}",
+            method,
+            key,
+            status,
+            String::from_utf8_lossy(body)
+        ))
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), AppError> {
+        let (status, body) = self
+            .request(Method::PUT, key, data, Some(content_type))
+            .await?;
+        if !status.is_success() {
+            return Err(Self::unexpected(&Method::PUT, key, status, &body));
+        }
+        Ok(())
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, AppError> {
+        let (status, body) = self.request(Method::GET, key, Vec::new(), None).await?;
+        match status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(body)),
+            status => Err(Self::unexpected(&Method::GET, key, status, &body)),
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), AppError> {
+        let (status, body) = self.request(Method::DELETE, key, Vec::new(), None).await?;
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            return Err(Self::unexpected(&Method::DELETE, key, status, &body));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use chrono::TimeZone;
+    use std::{collections::HashMap, sync::Mutex};
+
+    /// An in-memory stand-in for an S3 bucket, keyed by request path.
+    #[derive(Default)]
+    struct Bucket {
+        objects: Mutex<HashMap<String, (String, Vec<u8>)>>,
+        authorizations: Mutex<Vec<String>>,
+    }
+
+    async fn object(
+        request: HttpRequest,
+        body: web::Bytes,
+        bucket: web::Data<Bucket>,
+    ) -> HttpResponse {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        if header("x-amz-content-sha256") != crypto::hex(&crypto::sha256(&body)) {
+            return HttpResponse::BadRequest().body("XAmzContentSHA256Mismatch");
+        }
+        bucket
+            .authorizations
+            .lock()
+            .unwrap()
+            .push(header("authorization"));
+        let path = request.uri().path().to_string();
+        if path.contains("/fail") {
+            return HttpResponse::InternalServerError().body("InternalError");
+        }
+        let mut objects = bucket.objects.lock().unwrap();
+        match *request.method() {
+            Method::PUT => {
+                objects.insert(path, (header("content-type"), body.to_vec()));
+                HttpResponse::Ok().finish()
+            }
+            Method::GET => match objects.get(&path) {
+                Some((_, data)) => HttpResponse::Ok().body(data.clone()),
+                None => HttpResponse::NotFound().body("NoSuchKey"),
+            },
+            Method::DELETE => {
+                objects.remove(&path);
+                HttpResponse::NoContent().finish()
+            }
+            _ => HttpResponse::MethodNotAllowed().finish(),
+        }
+    }
+
+    fn config(endpoint: &str) -> S3Config {
+        S3Config {
+            endpoint: endpoint.to_string(),
+            access_key: "access".to_string(),
+            secret_key: "secret".to_string(),
+            ..S3Config::default()
+        }
+    }
+
+    #[test]
+    fn keys_are_percent_encoded() {
+        assert_eq!(encode_key("1/a b+é.wav"), "1/a%20b%2B%C3%A9.wav");
+        assert_eq!(encode_key("1/abc-_.~.ogg"), "1/abc-_.~.ogg");
+    }
+
+    #[test]
+    fn signs_with_signature_version_4() {
+        let storage = S3Storage::new(&config("http://127.0.0.1:9000")).unwrap();
+        let now = Utc.ymd(2021, 3, 17).and_hms(12, 0, 0);
+        let headers = storage.sign(&Method::PUT, "/tts-audio/1/a%20b.wav", b"RIFF", now);
+        let payload_hash = "a40ff3d5900fb7698b8c865041347cb49eccedc8f93945f89629ad104aaecce4";
+        assert_eq!(
+            headers,
+            vec![
+                ("Host", "127.0.0.1:9000".to_string()),
+                ("x-amz-content-sha256", payload_hash.to_string()),
+                ("x-amz-date", "20210317T120000Z".to_string()),
+                (
+                    "Authorization",
+                    "AWS4-HMAC-SHA256 \
+                     Credential=access/20210317/us-east-1/s3/aws4_request, \
+                     SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+                     Signature=94765a75473af5aa70eaa9d4ddef0ffe76f843c0c8d75886e6e1b28822eb9ef7"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn stores_reads_and_deletes_objects() {
+        let bucket = web::Data::new(Bucket::default());
+        let server = test::start({
+            let bucket = bucket.clone();
+            move || {
+                App::new()
+                    .app_data(bucket.clone())
+                    .default_service(web::route().to(object))
+            }
+        });
+        let storage = S3Storage::new(&config(&format!("http://{}", server.addr()))).unwrap();
+
+        storage
+            .put("1/a b.wav", b"RIFF".to_vec(), "audio/wav")
+            .await
+            .unwrap();
+        assert_eq!(
+            bucket.objects.lock().unwrap().get("/tts-audio/1/a%20b.wav"),
+            Some(&("audio/wav".to_string(), b"RIFF".to_vec()))
+        );
+        assert_eq!(
+            storage.get("1/a b.wav").await.unwrap(),
+            Some(b"RIFF".to_vec())
+        );
+        storage.delete("1/a b.wav").await.unwrap();
+        assert_eq!(storage.get("1/a b.wav").await.unwrap(), None);
+
+        let authorizations = bucket.authorizations.lock().unwrap();
+        assert_eq!(authorizations.len(), 4);
+        assert!(authorizations
+            .iter()
+            .all(|value| value.starts_with("AWS4-HMAC-SHA256 Credential=access/")));
+    }
+
+    #[actix_rt::test]
+    async fn reports_server_errors() {
+        let bucket = web::Data::new(Bucket::default());
+        let server = test::start({
+            let bucket = bucket.clone();
+            move || {
+                App::new()
+                    .app_data(bucket.clone())
+                    .default_service(web::route().to(object))
+            }
+        });
+        let storage = S3Storage::new(&config(&format!("http://{}", server.addr()))).unwrap();
+
+        let error = storage
+            .put("1/fail.wav", Vec::new(), "audio/wav")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("InternalError"));
+        assert!(storage.get("1/fail.wav").await.is_err());
+        assert!(storage.delete("1/fail.wav").await.is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::models::dictionary::DictionaryEntry;
+
+/// Replaces dictionary surfaces with their readings, preferring the longest
+/// surface that matches at each position.
+pub struct Dictionary {
+    entries: Vec<(Vec<char>, String)>,
+}
+
+impl Dictionary {
+    pub fn new(entries: Vec<DictionaryEntry>) -> Dictionary {
+        let mut entries: Vec<(Vec<char>, String)> = entries
+            .into_iter()
+            .filter(|entry| !entry.surface.is_empty())
+            .map(|entry| (entry.surface.chars().collect(), entry.reading))
+            .collect();
+        entries.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Dictionary { entries }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        if self.entries.is_empty() {
+            return text.to_string();
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        let mut output = String::with_capacity(text.len());
+        let mut i = 0;
+
+        while i < chars.len() {
+            let rest = &chars[i..];
+            match self
+                .entries
+                .iter()
+                .find(|(surface, _)| rest.starts_with(surface))
+            {
+                Some((surface, reading)) => {
+                    output.push_str(reading);
+                    i += surface.len();
+                }
+                None => {
+                    output.push(chars[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary(entries: &[(&str, &str)]) -> Dictionary {
+        Dictionary::new(
+            entries
+                .iter()
+                .map(|(surface, reading)| DictionaryEntry {
+                    surface: surface.to_string(),
+                    reading: reading.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn longest_surface_wins() {
+        // Insertion order must not matter.
+        for entries in &[
+            [("東京", "トウキョウ"), ("東京都", "トーキョート")],
+            [("東京都", "トーキョート"), ("東京", "トウキョウ")],
+        ] {
+            let dictionary = dictionary(entries);
+            assert_eq!(dictionary.apply("東京都に住む"), "トーキョートに住む");
+            assert_eq!(dictionary.apply("東京に住む"), "トウキョウに住む");
+        }
+    }
+
+    #[test]
+    fn matches_at_the_end_of_the_input() {
+        let dictionary = dictionary(&[("東京", "トウキョウ")]);
+        assert_eq!(dictionary.apply("行き先は東京"), "行き先はトウキョウ");
+        assert_eq!(dictionary.apply("東京"), "トウキョウ");
+        // A surface longer than the rest of the input does not match.
+        assert_eq!(dictionary.apply("東"), "東");
+    }
+
+    #[test]
+    fn matches_whole_characters() {
+        let dictionary = dictionary(&[("🦀", "カニ"), ("é", "エー"), ("ab", "エービー")]);
+        assert_eq!(dictionary.apply("🦀とéとab"), "カニとエーとエービー");
+        // Surfaces are compared by character, never inside a character.
+        assert_eq!(dictionary.apply("😀"), "😀");
+    }
+
+    #[test]
+    fn replacements_are_not_rescanned() {
+        let dictionary = dictionary(&[("あ", "い"), ("い", "う")]);
+        assert_eq!(dictionary.apply("あい"), "いう");
+    }
+
+    #[test]
+    fn empty_surfaces_are_ignored() {
+        let dictionary = dictionary(&[("", "カラ"), ("東京", "トウキョウ")]);
+        assert_eq!(dictionary.apply("東京"), "トウキョウ");
+        assert_eq!(dictionary(&[]).apply("そのまま"), "そのまま");
+    }
+}
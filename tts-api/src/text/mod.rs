@@ -0,0 +1,49 @@
+pub mod dictionary;
+pub mod normalize;
+pub mod segment;
+pub mod ssml;
+
+use self::{dictionary::Dictionary, normalize::Normalizer};
+use crate::{error::AppError, models::dictionary::DictionaryEntry, trace};
+use sqlx::PgPool;
+
+/// Applies a user's pronunciation dictionary and, unless disabled, the
+/// normalization rules to text before synthesis.
+pub struct Preprocessor<'a> {
+    dictionary: Dictionary,
+    normalizer: Option<&'a Normalizer>,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub async fn load(
+        pool: &PgPool,
+        user_id: i64,
+        normalizer: &'a Normalizer,
+        normalize: bool,
+    ) -> Result<Preprocessor<'a>, AppError> {
+        let entries = {
+            let _stage = trace::stage("dictionary");
+            DictionaryEntry::list(pool, user_id).await?
+        };
+        Ok(Preprocessor {
+            dictionary: Dictionary::new(entries),
+            normalizer: if normalize { Some(normalizer) } else { None },
+        })
+    }
+
+    /// A preprocessor without a user dictionary.
+    pub fn new(normalizer: &'a Normalizer, normalize: bool) -> Preprocessor<'a> {
+        Preprocessor {
+            dictionary: Dictionary::new(Vec::new()),
+            normalizer: if normalize { Some(normalizer) } else { None },
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let text = self.dictionary.apply(text);
+        match self.normalizer {
+            Some(normalizer) => normalizer.normalize(&text),
+            None => text,
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::config::NormalizeConfig;
+use regex::Regex;
+use std::{collections::HashMap, sync::Arc};
+use unicode_normalization::UnicodeNormalization;
+
+const DEFAULT_EMOJI_NAMES: &[(&str, &str)] = &[
+    ("😀", "にっこり"),
+    ("😂", "うれし泣き"),
+    ("🤣", "大笑い"),
+    ("😊", "笑顔"),
+    ("😍", "ハートの目"),
+    ("😭", "大泣き"),
+    ("😢", "泣き顔"),
+    ("😡", "怒り"),
+    ("😱", "絶叫"),
+    ("🤔", "考え中"),
+    ("🙏", "お願い"),
+    ("👍", "いいね"),
+    ("👎", "よくないね"),
+    ("👏", "拍手"),
+    ("🎉", "クラッカー"),
+    ("🔥", "炎"),
+    ("✨", "キラキラ"),
+    ("💦", "汗"),
+    ("💯", "百点"),
+    ("❤", "ハート"),
+    ("⭐", "星"),
+    ("✅", "チェック"),
+    ("❌", "バツ"),
+    ("⚠", "注意"),
+];
+
+pub trait Rule {
+    fn apply(&self, text: &str) -> String;
+}
+
+/// Unifies full-width alphanumerics and half-width katakana via NFKC.
+pub struct WidthRule;
+
+impl Rule for WidthRule {
+    fn apply(&self, text: &str) -> String {
+        text.nfkc().collect()
+    }
+}
+
+pub struct UrlRule {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl UrlRule {
+    pub fn new(replacement: &str) -> UrlRule {
+        UrlRule {
+            pattern: Regex::new(r"https?://[^\s<>]+").unwrap(),
+            replacement: replacement.to_string(),
+        }
+    }
+}
+
+impl Rule for UrlRule {
+    fn apply(&self, text: &str) -> String {
+        self.pattern
+            .replace_all(text, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+/// Reads Discord custom emoji by name and replaces user, role and channel
+/// mentions with fixed words.
+pub struct DiscordRule {
+    custom_emoji: Regex,
+    mention: Regex,
+    role: Regex,
+    channel: Regex,
+    mention_replacement: String,
+    role_replacement: String,
+    channel_replacement: String,
+}
+
+impl DiscordRule {
+    pub fn new(mention: &str, role: &str, channel: &str) -> DiscordRule {
+        DiscordRule {
+            custom_emoji: Regex::new(r"<a?:(\w+):\d+>").unwrap(),
+            mention: Regex::new(r"<@!?\d+>").unwrap(),
+            role: Regex::new(r"<@&\d+>").unwrap(),
+            channel: Regex::new(r"<#\d+>").unwrap(),
+            mention_replacement: mention.to_string(),
+            role_replacement: role.to_string(),
+            channel_replacement: channel.to_string(),
+        }
+    }
+}
+
+impl Rule for DiscordRule {
+    fn apply(&self, text: &str) -> String {
+        let text = self.custom_emoji.replace_all(text, "$1");
+        let text = self.role.replace_all(&text, self.role_replacement.as_str());
+        let text = self
+            .mention
+            .replace_all(&text, self.mention_replacement.as_str());
+        self.channel
+            .replace_all(&text, self.channel_replacement.as_str())
+            .into_owned()
+    }
+}
+
+/// Replaces emoji with their names. Emoji without a known name are dropped.
+pub struct EmojiRule {
+    names: HashMap<String, String>,
+}
+
+impl EmojiRule {
+    pub fn new(names: &HashMap<String, String>) -> EmojiRule {
+        let mut table: HashMap<String, String> = DEFAULT_EMOJI_NAMES
+            .iter()
+            .map(|(emoji, name)| (emoji.to_string(), name.to_string()))
+            .collect();
+        table.extend(names.clone());
+        EmojiRule { names: table }
+    }
+
+    fn is_emoji(c: char) -> bool {
+        matches!(c as u32,
+            0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x2300..=0x23FF)
+    }
+
+    fn is_modifier(c: char) -> bool {
+        matches!(c as u32, 0xFE0E | 0xFE0F | 0x200D | 0x20E3 | 0x1F3FB..=0x1F3FF)
+    }
+}
+
+impl Rule for EmojiRule {
+    fn apply(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if !Self::is_emoji(c) {
+                output.push(c);
+                continue;
+            }
+            let mut cluster = c.to_string();
+            while let Some(&next) = chars.peek() {
+                let joined = cluster.ends_with('\u{200D}') && Self::is_emoji(next);
+                if !Self::is_modifier(next) && !joined {
+                    break;
+                }
+                cluster.push(next);
+                chars.next();
+            }
+            let name = self
+                .names
+                .get(&cluster)
+                .or_else(|| self.names.get(&c.to_string()));
+            if let Some(name) = name {
+                output.push_str(name);
+            }
+        }
+
+        output
+    }
+}
+
+/// Caps runs of the same character at `max_repeat`, reading runs of `w` as
+/// laughter ("わら").
+pub struct RepeatRule {
+    max_repeat: usize,
+}
+
+impl RepeatRule {
+    pub fn new(max_repeat: usize) -> RepeatRule {
+        RepeatRule {
+            max_repeat: max_repeat.max(1),
+        }
+    }
+}
+
+impl Rule for RepeatRule {
+    fn apply(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut output = String::with_capacity(text.len());
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] == c {
+                end += 1;
+            }
+            let count = end - i;
+
+            let isolated = (i == 0 || !chars[i - 1].is_ascii_alphabetic())
+                && (end == chars.len() || !chars[end].is_ascii_alphabetic());
+            if (c == 'w' || c == 'W') && isolated {
+                output.push_str(&"わら".repeat(count.min(self.max_repeat)));
+            } else if c.is_ascii_digit() {
+                output.extend(&chars[i..end]);
+            } else {
+                output.extend(std::iter::repeat(c).take(count.min(self.max_repeat)));
+            }
+            i = end;
+        }
+
+        output
+    }
+}
+
+#[derive(Clone)]
+pub struct Normalizer {
+    rules: Vec<Arc<dyn Rule + Send + Sync>>,
+}
+
+impl Normalizer {
+    pub fn from_config(config: &NormalizeConfig) -> Normalizer {
+        let mut rules: Vec<Arc<dyn Rule + Send + Sync>> = Vec::new();
+        if config.width {
+            rules.push(Arc::new(WidthRule));
+        }
+        if config.url {
+            rules.push(Arc::new(UrlRule::new(&config.url_replacement)));
+        }
+        if config.discord {
+            rules.push(Arc::new(DiscordRule::new(
+                &config.mention_replacement,
+                &config.role_replacement,
+                &config.channel_replacement,
+            )));
+        }
+        if config.emoji {
+            rules.push(Arc::new(EmojiRule::new(&config.emoji_names)));
+        }
+        if config.repeat {
+            rules.push(Arc::new(RepeatRule::new(config.max_repeat)));
+        }
+        Normalizer { rules }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |text, rule| rule.apply(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn width_unifies_full_and_half_width() {
+        assert_eq!(WidthRule.apply("ＡＢＣ１２３ｱｲｳ"), "ABC123アイウ");
+    }
+
+    #[test]
+    fn url_is_replaced() {
+        let rule = UrlRule::new("URL省略");
+        assert_eq!(
+            rule.apply("見て https://example.com/a?b=1 です"),
+            "見て URL省略 です"
+        );
+        assert_eq!(rule.apply("<http://example.com>"), "<URL省略>");
+    }
+
+    #[test]
+    fn discord_mentions_and_emoji_are_replaced() {
+        let rule = DiscordRule::new("メンション", "ロール", "チャンネル");
+        assert_eq!(
+            rule.apply("<@123> <@!456> <@&789> <#10> <:smile:42> <a:wave:43>"),
+            "メンション メンション ロール チャンネル smile wave"
+        );
+    }
+
+    #[test]
+    fn emoji_are_read_by_name() {
+        let rule = EmojiRule::new(&HashMap::new());
+        assert_eq!(rule.apply("いいね👍"), "いいねいいね");
+        // Modifiers fall back to the base emoji.
+        assert_eq!(rule.apply("👍🏽"), "いいね");
+        assert_eq!(rule.apply("❤\u{FE0F}"), "ハート");
+        // Unknown emoji are dropped.
+        assert_eq!(rule.apply("カニ🦀"), "カニ");
+    }
+
+    #[test]
+    fn emoji_names_can_be_configured() {
+        let mut names = HashMap::new();
+        names.insert("🦀".to_string(), "カニ".to_string());
+        names.insert("👍".to_string(), "グッド".to_string());
+        let rule = EmojiRule::new(&names);
+        assert_eq!(rule.apply("🦀👍"), "カニグッド");
+    }
+
+    #[test]
+    fn repeats_are_capped() {
+        let rule = RepeatRule::new(2);
+        assert_eq!(rule.apply("すごーーーーい"), "すごーーい");
+        assert_eq!(rule.apply("10000円"), "10000円");
+    }
+
+    #[test]
+    fn laughter_is_read() {
+        let rule = RepeatRule::new(2);
+        assert_eq!(rule.apply("草wwwww"), "草わらわら");
+        assert_eq!(rule.apply("w"), "わら");
+        // A `w` inside a word is not laughter.
+        assert_eq!(rule.apply("wow"), "wow");
+    }
+
+    #[test]
+    fn rules_run_in_order() {
+        let normalizer = Normalizer::from_config(&NormalizeConfig::default());
+        // Width unification turns full-width `ｗ` into laughter.
+        assert_eq!(normalizer.normalize("ｗｗｗ"), "わらわら");
+    }
+
+    #[test]
+    fn rules_can_be_disabled() {
+        let config = NormalizeConfig {
+            width: false,
+            url: false,
+            discord: false,
+            emoji: false,
+            repeat: false,
+            ..NormalizeConfig::default()
+        };
+        let normalizer = Normalizer::from_config(&config);
+        let text = "ｗｗｗ https://example.com <@1> 👍";
+        assert_eq!(normalizer.normalize(text), text);
+    }
+}
@@ -0,0 +1,211 @@
+const SENTENCE_DELIMITERS: &[char] = &['。', '．', '！', '？', '!', '?', '\n'];
+const CLAUSE_DELIMITERS: &[char] = &['、', '，', ',', '；', ';', '：', ':'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    Sentence,
+    Clause,
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub text: String,
+    pub boundary: Boundary,
+}
+
+/// Splits `text` into segments of at most `max_length` characters.
+///
+/// Sentence boundaries always end a segment. Sentences longer than `max_length`
+/// are packed clause by clause, and clauses that still do not fit are cut at
+/// `max_length` characters. Each segment records the delimiter it ends with.
+pub fn split(text: &str, max_length: usize) -> Vec<Segment> {
+    let max_length = max_length.max(1);
+    let mut segments = Vec::new();
+
+    for sentence in split_inclusive(text, SENTENCE_DELIMITERS) {
+        let clauses = split_inclusive(sentence, CLAUSE_DELIMITERS);
+        for (text, boundary) in pack(&clauses, max_length) {
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            segments.push(Segment {
+                text: text.to_string(),
+                boundary,
+            });
+        }
+    }
+
+    segments
+}
+
+/// Splits `text` after each run of `delimiters`, keeping the delimiters.
+fn split_inclusive<'a>(text: &'a str, delimiters: &[char]) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        if !delimiters.contains(&c) {
+            continue;
+        }
+        while let Some(&(_, next)) = chars.peek() {
+            if delimiters.contains(&next) {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or_else(|| text.len());
+        pieces.push(&text[start..end]);
+        start = end;
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+
+    pieces
+}
+
+fn pack(clauses: &[&str], max_length: usize) -> Vec<(String, Boundary)> {
+    let mut packed = Vec::new();
+    let mut current = String::new();
+    let mut current_length = 0;
+
+    for clause in clauses {
+        let length = clause.chars().count();
+        if current_length + length <= max_length {
+            current.push_str(clause);
+            current_length += length;
+            continue;
+        }
+        if !current.is_empty() {
+            let boundary = trailing_boundary(&current);
+            packed.push((std::mem::take(&mut current), boundary));
+        }
+
+        let chars: Vec<char> = clause.chars().collect();
+        let mut chunks = chars.chunks(max_length).peekable();
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_some() {
+                packed.push((chunk.iter().collect(), Boundary::None));
+            } else {
+                current = chunk.iter().collect();
+                current_length = chunk.len();
+            }
+        }
+    }
+    if !current.is_empty() {
+        let boundary = trailing_boundary(&current);
+        packed.push((current, boundary));
+    }
+
+    packed
+}
+
+fn trailing_boundary(text: &str) -> Boundary {
+    if text.ends_with('\n') {
+        return Boundary::Sentence;
+    }
+    match text.trim_end().chars().last() {
+        Some(c) if SENTENCE_DELIMITERS.contains(&c) => Boundary::Sentence,
+        Some(c) if CLAUSE_DELIMITERS.contains(&c) => Boundary::Clause,
+        _ => Boundary::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(text: &str, max_length: usize) -> Vec<(String, Boundary)> {
+        split(text, max_length)
+            .into_iter()
+            .map(|segment| (segment.text, segment.boundary))
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_sentences() {
+        assert_eq!(
+            segments("こんにちは。元気ですか？", 100),
+            vec![
+                ("こんにちは。".to_string(), Boundary::Sentence),
+                ("元気ですか？".to_string(), Boundary::Sentence),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_runs_of_delimiters_together() {
+        assert_eq!(
+            segments("本当！？はい", 100),
+            vec![
+                ("本当！？".to_string(), Boundary::Sentence),
+                ("はい".to_string(), Boundary::None),
+            ]
+        );
+    }
+
+    #[test]
+    fn packs_long_sentences_by_clause() {
+        assert_eq!(
+            segments("あいう、えおか、きくけ。", 8),
+            vec![
+                ("あいう、えおか、".to_string(), Boundary::Clause),
+                ("きくけ。".to_string(), Boundary::Sentence),
+            ]
+        );
+    }
+
+    #[test]
+    fn cuts_clauses_longer_than_max_length() {
+        assert_eq!(
+            segments("あいうえおかきく", 3),
+            vec![
+                ("あいう".to_string(), Boundary::None),
+                ("えおか".to_string(), Boundary::None),
+                ("きく".to_string(), Boundary::None),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_text_has_no_boundary() {
+        // SSML splits text at tags, so a run may stop mid-sentence and must
+        // not get the pause of a sentence end.
+        assert_eq!(
+            segments("あいう、えお", 4),
+            vec![
+                ("あいう、".to_string(), Boundary::Clause),
+                ("えお".to_string(), Boundary::None),
+            ]
+        );
+        assert_eq!(
+            segments("声を変えて", 100),
+            vec![("声を変えて".to_string(), Boundary::None)]
+        );
+    }
+
+    #[test]
+    fn drops_blank_segments() {
+        assert_eq!(
+            segments("あ。 \nい", 100),
+            vec![
+                ("あ。".to_string(), Boundary::Sentence),
+                ("い".to_string(), Boundary::None),
+            ]
+        );
+    }
+
+    #[test]
+    fn never_exceeds_max_length() {
+        let text = "吾輩は猫である。名前はまだ無い、どこで生れたかとんと見当がつかぬ。";
+        for max_length in 1..20 {
+            for segment in split(text, max_length) {
+                assert!(segment.text.chars().count() <= max_length);
+            }
+        }
+    }
+}
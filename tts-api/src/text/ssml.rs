@@ -0,0 +1,523 @@
+use crate::backend::prosody::{Prosody, PITCH_RANGE, RATE_RANGE, VOLUME_RANGE};
+use roxmltree::{Document, Node};
+
+pub const MAX_BREAK_MILLIS: u32 = 10_000;
+/// Breaks are not charged, so their total per request is bounded too.
+pub const MAX_TOTAL_BREAK_MILLIS: u32 = 60_000;
+/// Characters of markup and text accepted before parsing.
+pub const MAX_INPUT_LENGTH: usize = 20_000;
+
+const DIGIT_READINGS: [&str; 10] = [
+    "ゼロ", "イチ", "ニ", "サン", "ヨン", "ゴ", "ロク", "ナナ", "ハチ", "キュー",
+];
+const LETTER_READINGS: [&str; 26] = [
+    "エー", "ビー", "シー", "ディー", "イー", "エフ", "ジー", "エイチ", "アイ", "ジェー", "ケー",
+    "エル", "エム", "エヌ", "オー", "ピー", "キュー", "アール", "エス", "ティー", "ユー", "ブイ",
+    "ダブリュー", "エックス", "ワイ", "ゼット",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SsmlPart {
+    Text {
+        text: String,
+        voice: Option<String>,
+        prosody: Prosody,
+    },
+    Break {
+        millis: u32,
+    },
+}
+
+#[derive(Clone, Default)]
+struct Context {
+    voice: Option<String>,
+    prosody: Prosody,
+}
+
+impl SsmlPart {
+    /// Number of characters that will actually be spoken.
+    pub fn spoken_length(&self) -> usize {
+        match self {
+            SsmlPart::Text { text, .. } => text.chars().count(),
+            SsmlPart::Break { .. } => 0,
+        }
+    }
+}
+
+/// Parses a document in the supported SSML subset into a flat list of text
+/// runs and pauses. Input without a `<speak>` root is wrapped in one.
+pub fn parse(input: &str) -> Result<Vec<SsmlPart>, String> {
+    if input.chars().count() > MAX_INPUT_LENGTH {
+        return Err(format!(
+            "SSML must be at most {} characters.",
+            MAX_INPUT_LENGTH
+        ));
+    }
+    let wrapped;
+    let input = if input.trim_start().starts_with("<speak") {
+        input
+    } else {
+        wrapped = format!("<speak>{}</speak>", input);
+        &wrapped
+    };
+
+    let document = Document::parse(input).map_err(|e| format!("Invalid SSML: {}", e))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "speak" {
+        return Err(format!(
+            "SSML root element must be <speak>, found <{}>.",
+            root.tag_name().name()
+        ));
+    }
+
+    let mut parts = Vec::new();
+    walk_children(root, &Context::default(), &mut parts)?;
+    let break_millis: u32 = parts
+        .iter()
+        .map(|part| match part {
+            SsmlPart::Break { millis } => *millis,
+            SsmlPart::Text { .. } => 0,
+        })
+        .sum();
+    if break_millis > MAX_TOTAL_BREAK_MILLIS {
+        return Err(format!(
+            "Breaks must add up to at most {}ms.",
+            MAX_TOTAL_BREAK_MILLIS
+        ));
+    }
+    Ok(merge(parts))
+}
+
+fn walk_children(node: Node, context: &Context, parts: &mut Vec<SsmlPart>) -> Result<(), String> {
+    for child in node.children() {
+        if child.is_text() {
+            push_text(child.text().unwrap_or_default(), context, parts);
+        } else if child.is_element() {
+            walk_element(child, context, parts)?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_element(node: Node, context: &Context, parts: &mut Vec<SsmlPart>) -> Result<(), String> {
+    let name = node.tag_name().name();
+    match name {
+        "break" => {
+            check_attributes(node, &["time", "strength"])?;
+            parts.push(SsmlPart::Break {
+                millis: parse_break(node)?,
+            });
+        }
+        "prosody" => {
+            check_attributes(node, &["rate", "pitch", "volume"])?;
+            let mut prosody = Prosody::default();
+            if let Some(rate) = node.attribute("rate") {
+                prosody.rate = parse_rate(rate)?;
+            }
+            if let Some(pitch) = node.attribute("pitch") {
+                prosody.pitch = parse_pitch(pitch)?;
+            }
+            if let Some(volume) = node.attribute("volume") {
+                prosody.volume = parse_volume(volume)?;
+            }
+            let context = Context {
+                voice: context.voice.clone(),
+                prosody: context.prosody.then(&prosody),
+            };
+            walk_children(node, &context, parts)?;
+        }
+        "sub" => {
+            check_attributes(node, &["alias"])?;
+            let alias = node
+                .attribute("alias")
+                .ok_or_else(|| "<sub> requires an alias attribute.".to_string())?;
+            push_text(alias, context, parts);
+        }
+        "say-as" => {
+            check_attributes(node, &["interpret-as", "format"])?;
+            let text = inner_text(node)?;
+            let text = match node.attribute("interpret-as") {
+                Some("characters") => say_characters(&text),
+                Some("cardinal") => say_cardinal(&text)?,
+                Some("date") => say_date(&text, node.attribute("format").unwrap_or("ymd"))?,
+                Some(other) => return Err(format!("Unsupported interpret-as value: {}", other)),
+                None => return Err("<say-as> requires an interpret-as attribute.".to_string()),
+            };
+            push_text(&text, context, parts);
+        }
+        "voice" => {
+            check_attributes(node, &["name"])?;
+            let voice = node
+                .attribute("name")
+                .ok_or_else(|| "<voice> requires a name attribute.".to_string())?;
+            let context = Context {
+                voice: Some(voice.to_string()),
+                prosody: context.prosody,
+            };
+            walk_children(node, &context, parts)?;
+        }
+        _ => return Err(format!("Unsupported SSML tag: <{}>", name)),
+    }
+    Ok(())
+}
+
+fn check_attributes(node: Node, allowed: &[&str]) -> Result<(), String> {
+    for attribute in node.attributes() {
+        if !allowed.contains(&attribute.name()) {
+            return Err(format!(
+                "Unsupported attribute {} on <{}>.",
+                attribute.name(),
+                node.tag_name().name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn inner_text(node: Node) -> Result<String, String> {
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_element() {
+            return Err(format!(
+                "<{}> may only contain text.",
+                node.tag_name().name()
+            ));
+        }
+        text.push_str(child.text().unwrap_or_default());
+    }
+    Ok(text.trim().to_string())
+}
+
+fn push_text(text: &str, context: &Context, parts: &mut Vec<SsmlPart>) {
+    if text.trim().is_empty() {
+        return;
+    }
+    parts.push(SsmlPart::Text {
+        text: text.to_string(),
+        voice: context.voice.clone(),
+        prosody: context.prosody,
+    });
+}
+
+/// Joins adjacent text runs that share a voice and prosody.
+fn merge(parts: Vec<SsmlPart>) -> Vec<SsmlPart> {
+    let mut merged: Vec<SsmlPart> = Vec::new();
+    for part in parts {
+        if let (
+            Some(SsmlPart::Text {
+                text,
+                voice,
+                prosody,
+            }),
+            SsmlPart::Text {
+                text: next,
+                voice: next_voice,
+                prosody: next_prosody,
+            },
+        ) = (merged.last_mut(), &part)
+        {
+            if voice == next_voice && prosody == next_prosody {
+                text.push_str(next);
+                continue;
+            }
+        }
+        merged.push(part);
+    }
+    merged
+}
+
+fn parse_number(value: &str, suffix: &str) -> Option<f64> {
+    value.strip_suffix(suffix)?.trim().parse().ok()
+}
+
+fn parse_break(node: Node) -> Result<u32, String> {
+    let millis = match (node.attribute("time"), node.attribute("strength")) {
+        (Some(time), _) => parse_number(time, "ms")
+            .or_else(|| parse_number(time, "s").map(|s| s * 1000.0))
+            .filter(|millis| *millis >= 0.0)
+            .ok_or_else(|| format!("Invalid break time: {}", time))? as u32,
+        (None, Some(strength)) => match strength {
+            "none" => 0,
+            "x-weak" => 100,
+            "weak" => 200,
+            "medium" => 400,
+            "strong" => 700,
+            "x-strong" => 1000,
+            _ => return Err(format!("Invalid break strength: {}", strength)),
+        },
+        (None, None) => 400,
+    };
+    if millis > MAX_BREAK_MILLIS {
+        return Err(format!("Break time must be at most {}ms.", MAX_BREAK_MILLIS));
+    }
+    Ok(millis)
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let rate = match value {
+        "x-slow" => 0.5,
+        "slow" => 0.75,
+        "medium" | "default" => 1.0,
+        "fast" => 1.25,
+        "x-fast" => 1.5,
+        _ => parse_number(value, "%")
+            .map(|percent| percent / 100.0)
+            .or_else(|| value.parse().ok())
+            .ok_or_else(|| format!("Invalid prosody rate: {}", value))?,
+    };
+    if !RATE_RANGE.contains(&rate) {
+        return Err(format!("Prosody rate out of range: {}", value));
+    }
+    Ok(rate)
+}
+
+fn parse_pitch(value: &str) -> Result<f64, String> {
+    let semitones = match value {
+        "x-low" => -6.0,
+        "low" => -3.0,
+        "medium" | "default" => 0.0,
+        "high" => 3.0,
+        "x-high" => 6.0,
+        _ => parse_number(value, "st")
+            .or_else(|| parse_number(value, "%").map(|p| 12.0 * (1.0 + p / 100.0).log2()))
+            .filter(|semitones| semitones.is_finite())
+            .ok_or_else(|| format!("Invalid prosody pitch: {}", value))?,
+    };
+    if !PITCH_RANGE.contains(&semitones) {
+        return Err(format!("Prosody pitch out of range: {}", value));
+    }
+    Ok(semitones)
+}
+
+fn parse_volume(value: &str) -> Result<f64, String> {
+    let decibels = match value {
+        "x-soft" => -12.0,
+        "soft" => -6.0,
+        "medium" | "default" => 0.0,
+        "loud" => 6.0,
+        "x-loud" => 12.0,
+        _ => parse_number(value, "dB")
+            .ok_or_else(|| format!("Invalid prosody volume: {}", value))?,
+    };
+    if !VOLUME_RANGE.contains(&decibels) {
+        return Err(format!("Prosody volume out of range: {}", value));
+    }
+    Ok(decibels)
+}
+
+fn say_characters(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '0'..='9' => DIGIT_READINGS[c as usize - '0' as usize].to_string(),
+            'a'..='z' => LETTER_READINGS[c as usize - 'a' as usize].to_string(),
+            'A'..='Z' => LETTER_READINGS[c as usize - 'A' as usize].to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+fn say_cardinal(text: &str) -> Result<String, String> {
+    let digits: String = text.chars().filter(|c| *c != ',' && *c != '_').collect();
+    let unsigned = digits.strip_prefix('-').unwrap_or(&digits);
+    if unsigned.is_empty() || !unsigned.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid cardinal number: {}", text));
+    }
+    if digits.starts_with('-') {
+        Ok(format!("マイナス{}", unsigned))
+    } else {
+        Ok(digits)
+    }
+}
+
+fn say_date(text: &str, format: &str) -> Result<String, String> {
+    let fields: Vec<&str> = text.split(|c| c == '-' || c == '/' || c == '.').collect();
+    let invalid = || format!("Invalid date for format {}: {}", format, text);
+    if fields.len() != format.len() || fields.iter().any(|f| f.parse::<u32>().is_err()) {
+        return Err(invalid());
+    }
+
+    let mut date = String::new();
+    for (unit, field) in format.chars().zip(fields) {
+        let value: u32 = field.parse().map_err(|_| invalid())?;
+        let suffix = match unit {
+            'y' => "年",
+            'm' if (1..=12).contains(&value) => "月",
+            'd' if (1..=31).contains(&value) => "日",
+            _ => return Err(invalid()),
+        };
+        date.push_str(&format!("{}{}", value, suffix));
+    }
+    Ok(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str, voice: Option<&str>, prosody: Prosody) -> SsmlPart {
+        SsmlPart::Text {
+            text: text.to_string(),
+            voice: voice.map(str::to_string),
+            prosody,
+        }
+    }
+
+    fn prosody(rate: f64, pitch: f64, volume: f64) -> Prosody {
+        Prosody {
+            rate,
+            pitch,
+            volume,
+        }
+    }
+
+    #[test]
+    fn wraps_plain_text() {
+        assert_eq!(
+            parse("こんにちは").unwrap(),
+            vec![text("こんにちは", None, Prosody::default())]
+        );
+    }
+
+    #[test]
+    fn nested_elements_combine() {
+        let parts = parse(
+            r#"<speak>あ<prosody rate="2"><voice name="mei">い<prosody pitch="2st">う</prosody></voice></prosody>え</speak>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                text("あ", None, Prosody::default()),
+                text("い", Some("mei"), prosody(2.0, 0.0, 0.0)),
+                text("う", Some("mei"), prosody(2.0, 2.0, 0.0)),
+                text("え", None, Prosody::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_runs_are_merged() {
+        assert_eq!(
+            parse(r#"あ<sub alias="びー">B</sub>し"#).unwrap(),
+            vec![text("あびーし", None, Prosody::default())]
+        );
+    }
+
+    #[test]
+    fn breaks() {
+        assert_eq!(
+            parse(r#"あ<break time="500ms"/>い"#).unwrap(),
+            vec![
+                text("あ", None, Prosody::default()),
+                SsmlPart::Break { millis: 500 },
+                text("い", None, Prosody::default()),
+            ]
+        );
+        let millis = |input: &str| match parse(input).unwrap().as_slice() {
+            [SsmlPart::Break { millis }] => *millis,
+            parts => panic!("unexpected parts: {:?}", parts),
+        };
+        assert_eq!(millis(r#"<break time="1.5s"/>"#), 1500);
+        assert_eq!(millis(r#"<break strength="strong"/>"#), 700);
+        assert_eq!(millis("<break/>"), 400);
+    }
+
+    #[test]
+    fn breaks_are_bounded() {
+        assert!(parse(r#"<break time="10s"/>"#).is_ok());
+        assert!(parse(r#"<break time="11s"/>"#).is_err());
+        assert!(parse(r#"<break time="-1ms"/>"#).is_err());
+        assert!(parse(r#"<break time="soon"/>"#).is_err());
+        assert!(parse(r#"<break strength="huge"/>"#).is_err());
+    }
+
+    #[test]
+    fn total_break_time_is_bounded() {
+        let breaks = |count: usize| format!("あ{}", r#"<break time="10s"/>"#.repeat(count));
+        assert!(parse(&breaks(6)).is_ok());
+        assert!(parse(&breaks(7)).is_err());
+        assert!(parse(&breaks(1000)).is_err());
+    }
+
+    #[test]
+    fn input_length_is_bounded_before_parsing() {
+        assert!(parse(&"あ".repeat(MAX_INPUT_LENGTH)).is_ok());
+        assert_eq!(
+            parse(&"あ".repeat(MAX_INPUT_LENGTH + 1)),
+            Err(format!(
+                "SSML must be at most {} characters.",
+                MAX_INPUT_LENGTH
+            ))
+        );
+        // Too long to be parsed at all, even though it is not valid SSML.
+        assert_eq!(
+            parse(&"<".repeat(MAX_INPUT_LENGTH + 1)),
+            parse(&"あ".repeat(MAX_INPUT_LENGTH + 1))
+        );
+    }
+
+    #[test]
+    fn prosody_values() {
+        let parsed = |attributes: &str| {
+            let parts = parse(&format!("<prosody {}>あ</prosody>", attributes)).unwrap();
+            match parts.as_slice() {
+                [SsmlPart::Text { prosody, .. }] => *prosody,
+                parts => panic!("unexpected parts: {:?}", parts),
+            }
+        };
+        assert_eq!(parsed(r#"rate="50%""#), prosody(0.5, 0.0, 0.0));
+        assert_eq!(parsed(r#"rate="fast""#), prosody(1.25, 0.0, 0.0));
+        assert_eq!(parsed(r#"pitch="-3st""#), prosody(1.0, -3.0, 0.0));
+        assert_eq!(parsed(r#"volume="loud""#), prosody(1.0, 0.0, 6.0));
+        assert_eq!(parsed(r#"volume="-6dB""#), prosody(1.0, 0.0, -6.0));
+    }
+
+    #[test]
+    fn prosody_is_bounded() {
+        assert!(parse(r#"<prosody rate="10">あ</prosody>"#).is_err());
+        assert!(parse(r#"<prosody rate="0.1">あ</prosody>"#).is_err());
+        assert!(parse(r#"<prosody pitch="30st">あ</prosody>"#).is_err());
+        assert!(parse(r#"<prosody volume="30dB">あ</prosody>"#).is_err());
+        assert!(parse(r#"<prosody rate="quick">あ</prosody>"#).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_markup() {
+        let error = parse(r#"<audio src="a.wav"/>"#).unwrap_err();
+        assert!(error.contains("Unsupported SSML tag"), "{}", error);
+        let error = parse(r#"<break foo="1"/>"#).unwrap_err();
+        assert!(error.contains("Unsupported attribute"), "{}", error);
+        assert!(parse("<p>あ</p>").is_err());
+        assert!(parse(r#"<say-as interpret-as="telephone">1</say-as>"#).is_err());
+        assert!(parse(r#"<say-as interpret-as="characters"><break/></say-as>"#).is_err());
+        assert!(parse("<speak>あ").is_err());
+    }
+
+    #[test]
+    fn say_as() {
+        let spoken = |input: &str| match parse(input).unwrap().as_slice() {
+            [SsmlPart::Text { text, .. }] => text.clone(),
+            parts => panic!("unexpected parts: {:?}", parts),
+        };
+        assert_eq!(
+            spoken(r#"<say-as interpret-as="characters">AB1</say-as>"#),
+            "エービーイチ"
+        );
+        assert_eq!(
+            spoken(r#"<say-as interpret-as="cardinal">-1,000</say-as>"#),
+            "マイナス1000"
+        );
+        assert_eq!(
+            spoken(r#"<say-as interpret-as="date">2021-03-05</say-as>"#),
+            "2021年3月5日"
+        );
+        assert!(parse(r#"<say-as interpret-as="date">2021-13-05</say-as>"#).is_err());
+    }
+
+    #[test]
+    fn spoken_length_skips_markup() {
+        let parts = parse(r#"あい<break/><sub alias="う">X</sub>"#).unwrap();
+        let length: usize = parts.iter().map(SsmlPart::spoken_length).sum();
+        assert_eq!(length, 3);
+    }
+}
@@ -0,0 +1,353 @@
+//! Request correlation ids and logging.
+//!
+//! Each request runs with an id taken from `X-Request-Id` or generated. The
+//! id is kept in a thread local while the request's future is polled, and
+//! carried into blocking tasks by [`block`], so every log line written on
+//! behalf of a request can include it.
+
+use crate::config::{LogFormat, LoggingConfig};
+use actix_service::Service;
+use actix_web::{
+    dev::{Body, ResponseBody, ServiceRequest, ServiceResponse},
+    error::BlockingError,
+    http::header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    web, Error,
+};
+use chrono::{SecondsFormat, Utc};
+use futures::future::{Future, FutureExt};
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    io::Write,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Returns the id of the request being handled on this thread.
+pub fn current() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Runs `f` with `id` as the current request id.
+pub fn with<T>(id: Option<String>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<String>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            REQUEST_ID.with(|id| *id.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(REQUEST_ID.with(|current| current.replace(id)));
+    f()
+}
+
+/// A future polled with a request id set.
+pub struct Scoped<F> {
+    id: Option<String>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        with(this.id.clone(), || inner.as_mut().poll(cx))
+    }
+}
+
+pub fn scope<F: Future>(id: Option<String>, future: F) -> Scoped<F> {
+    Scoped {
+        id,
+        inner: Box::pin(future),
+    }
+}
+
+/// Like [`web::block`], keeping the current request id.
+pub async fn block<F, T, E>(f: F) -> Result<T, BlockingError<E>>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + std::fmt::Debug + 'static,
+{
+    let id = current();
+    web::block(move || with(id, f)).await
+}
+
+/// Logs how long a pipeline stage took when dropped.
+pub struct Stage {
+    name: &'static str,
+    started: Instant,
+}
+
+pub fn stage(name: &'static str) -> Stage {
+    Stage {
+        name,
+        started: Instant::now(),
+    }
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        debug!(
+            target: "tts_api::stage",
+            "{} took {:.1} ms",
+            self.name,
+            self.started.elapsed().as_secs_f64() * 1000.0
+        );
+    }
+}
+
+fn generate() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Accepts ids of visible ASCII characters, so they can be echoed in a
+/// header.
+fn valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Adds the request id to an error body. Text bodies become
+/// `{"error": ..., "request_id": ...}`; JSON objects get a `request_id`
+/// field.
+fn error_body(response: ServiceResponse<Body>, request_id: &str) -> ServiceResponse<Body> {
+    response.map_body(|head, body| {
+        let bytes = match &body {
+            ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => {
+                bytes.clone()
+            }
+            ResponseBody::Body(Body::Empty) | ResponseBody::Other(Body::Empty) => {
+                Default::default()
+            }
+            _ => return body,
+        };
+        let is_json = head.headers().get(CONTENT_TYPE).map_or(false, |value| {
+            value.as_bytes().starts_with(b"application/json")
+        });
+        let value = if is_json {
+            match serde_json::from_slice(&bytes) {
+                Ok(Value::Object(mut object)) => {
+                    object.insert("request_id".to_string(), json!(request_id));
+                    Value::Object(object)
+                }
+                _ => return body,
+            }
+        } else {
+            let message = if bytes.is_empty() {
+                head.status
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .to_string()
+            } else {
+                String::from_utf8_lossy(&bytes).into_owned()
+            };
+            json!({ "error": message, "request_id": request_id })
+        };
+        head.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        ResponseBody::Other(Body::from(value.to_string()))
+    })
+}
+
+/// Runs a request with its id, returning the id in `X-Request-Id` and in
+/// error bodies.
+pub fn middleware<S>(
+    request: ServiceRequest,
+    service: &mut S,
+) -> impl Future<Output = Result<ServiceResponse<Body>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+{
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+    scope(Some(request_id.clone()), service.call(request)).map(move |response| {
+        let mut response = response?;
+        if response.status().is_client_error() || response.status().is_server_error() {
+            response = error_body(response, &request_id);
+        }
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    })
+}
+
+/// Installs the logger, filtered by `RUST_LOG` as before. Lines carry the
+/// current request id, if any.
+pub fn init_logging(config: &LoggingConfig) {
+    let format = config.format;
+    env_logger::Builder::from_default_env()
+        .format(move |buf, record| {
+            let request_id = current();
+            match format {
+                LogFormat::Text => {
+                    let request_id = request_id.map(|id| format!(" {}", id)).unwrap_or_default();
+                    writeln!(
+                        buf,
+                        "[{} {:<5} {}{}] {}",
+                        buf.timestamp(),
+                        record.level(),
+                        record.target(),
+                        request_id,
+                        record.args()
+                    )
+                }
+                LogFormat::Json => {
+                    let line = json!({
+                        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                        "level": record.level().to_string(),
+                        "target": record.target(),
+                        "request_id": request_id,
+                        "message": record.args().to_string(),
+                    });
+                    writeln!(buf, "{}", line)
+                }
+            }
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App, HttpResponse};
+
+    fn with_request_id(response: HttpResponse, request_id: &str) -> ServiceResponse<Body> {
+        error_body(
+            test::TestRequest::default().to_srv_response(response),
+            request_id,
+        )
+    }
+
+    async fn json_body(response: ServiceResponse<Body>) -> Value {
+        serde_json::from_slice(&test::read_body(response).await).unwrap()
+    }
+
+    #[test]
+    fn request_ids_must_be_visible_ascii() {
+        assert!(valid("0123456789abcdef"));
+        assert!(valid("req-1/2:3"));
+        assert!(!valid(""));
+        assert!(!valid("has space"));
+        assert!(!valid("line\nbreak"));
+        assert!(!valid("リクエスト"));
+        assert!(valid(&"a".repeat(MAX_REQUEST_ID_LENGTH)));
+        assert!(!valid(&"a".repeat(MAX_REQUEST_ID_LENGTH + 1)));
+    }
+
+    #[actix_rt::test]
+    async fn text_errors_are_wrapped() {
+        let response = with_request_id(HttpResponse::NotFound().body("User not found"), "abc");
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "User not found", "request_id": "abc" })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn empty_errors_use_the_status_reason() {
+        let response = with_request_id(HttpResponse::TooManyRequests().finish(), "abc");
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Too Many Requests", "request_id": "abc" })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn json_errors_get_a_request_id_field() {
+        let response = with_request_id(
+            HttpResponse::BadRequest().json(json!({ "error": "Bad", "field": "text" })),
+            "abc",
+        );
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Bad", "field": "text", "request_id": "abc" })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn json_errors_other_than_objects_are_kept() {
+        let response = with_request_id(HttpResponse::BadRequest().json(json!(["a"])), "abc");
+        assert_eq!(json_body(response).await, json!(["a"]));
+    }
+
+    #[actix_rt::test]
+    async fn block_keeps_the_request_id() {
+        let id = scope(Some("abc".to_string()), async {
+            block(|| Ok::<_, ()>(current())).await.unwrap()
+        })
+        .await;
+        assert_eq!(id.as_deref(), Some("abc"));
+        assert_eq!(current(), None);
+    }
+
+    #[actix_rt::test]
+    async fn middleware_propagates_the_request_id() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap_fn(middleware)
+                .route(
+                    "/id",
+                    web::get().to(|| async {
+                        let id = block(|| Ok::<_, ()>(current())).await.unwrap();
+                        HttpResponse::Ok().body(id.unwrap_or_default())
+                    }),
+                )
+                .route(
+                    "/fail",
+                    web::get().to(|| async { HttpResponse::BadRequest().body("Bad") }),
+                ),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/id")
+            .header(REQUEST_ID_HEADER, "client-id")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(
+            response.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "client-id"
+        );
+        assert_eq!(test::read_body(response).await, "client-id");
+
+        // Invalid ids are replaced with a generated one.
+        let request = test::TestRequest::get()
+            .uri("/fail")
+            .header(REQUEST_ID_HEADER, "not valid")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        let request_id = response.headers().get(REQUEST_ID_HEADER).unwrap();
+        let request_id = request_id.to_str().unwrap().to_string();
+        assert_eq!(request_id.len(), 16);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Bad", "request_id": request_id })
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{
+    archive::ArchiveFormat,
+    audio::{
+        format::{self, OutputFormat},
+        process::PostProcess,
+    },
+    auth::{authenticate, Credentials},
+    backend::prosody::Prosody,
+    config::Config,
+    dictionary::ActiveDictionary,
+    error::AppError,
+    metrics,
+    models::preset::PresetSettings,
+    presets,
+    text::{normalize::Normalizer, ssml::SsmlPart, Preprocessor},
+    tts::{
+        request::{self, AudioFormat, SynthesisOptions},
+        routes::{charge, check_length},
+        synthesis,
+    },
+};
+use actix_web::{web, HttpResponse};
+use futures::{stream, StreamExt};
+use sqlx::PgPool;
+use std::{collections::HashSet, time::Instant};
+
+const MAX_ID_LENGTH: usize = 64;
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Deserialize, Debug)]
+struct BatchItem {
+    id: String,
+    text: String,
+    voice: Option<String>,
+    format: Option<AudioFormat>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchBody {
+    items: Vec<BatchItem>,
+    #[serde(default)]
+    archive: ArchiveFormat,
+    normalize: Option<bool>,
+    metadata: Option<bool>,
+    #[serde(default)]
+    postprocess: PostProcess,
+    #[serde(default)]
+    output: OutputFormat,
+    preset: Option<String>,
+    #[serde(skip)]
+    prosody: Prosody,
+}
+
+#[derive(Serialize, Debug)]
+struct ManifestItem {
+    id: String,
+    file: Option<String>,
+    format: AudioFormat,
+    duration: Option<f64>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Manifest {
+    succeeded: usize,
+    failed: usize,
+    items: Vec<ManifestItem>,
+}
+
+impl BatchItem {
+    fn format(&self) -> AudioFormat {
+        self.format.unwrap_or(AudioFormat::Wav)
+    }
+
+    fn input(&self, prosody: Prosody) -> Vec<SsmlPart> {
+        vec![SsmlPart::Text {
+            text: self.text.clone(),
+            voice: self.voice.clone(),
+            prosody,
+        }]
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}.{}", self.id, self.format().extension())
+    }
+}
+
+/// Item ids become file names in the archive, so they are restricted to a
+/// portable set of characters.
+fn validate_id(id: &str) -> Result<(), String> {
+    let valid = !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && !id.starts_with('.')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Item ids must be 1 to {} characters of A-Z, a-z, 0-9, '-', '_' and '.', not starting with '.': {:?}",
+            MAX_ID_LENGTH, id
+        ))
+    }
+}
+
+impl BatchBody {
+    /// Fills options the request left unset from a preset.
+    fn apply(&mut self, preset: &PresetSettings) {
+        for item in &mut self.items {
+            if item.voice.is_none() {
+                item.voice = preset.voice.clone();
+            }
+        }
+        self.prosody = preset.prosody.unwrap_or_default();
+        self.normalize = self.normalize.or(preset.normalize);
+        self.postprocess = self.postprocess.or(&preset.postprocess);
+        self.output = self.output.or(&preset.output);
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), String> {
+        if self.items.is_empty() {
+            return Err("Batch must have at least one item.".to_string());
+        }
+        if self.items.len() > config.batch.max_items {
+            return Err(format!(
+                "Batch must have at most {} items.",
+                config.batch.max_items
+            ));
+        }
+        self.postprocess.validate()?;
+
+        let sampling_rate = config.openjtalk.sampling_rate();
+        let mut ids = HashSet::new();
+        for item in &self.items {
+            validate_id(&item.id)?;
+            if !ids.insert(item.id.as_str()) {
+                return Err(format!("Duplicate item id: {}", item.id));
+            }
+            if item.text.trim().is_empty() {
+                return Err(format!("Item {} has no text.", item.id));
+            }
+            if let Some(voice) = &item.voice {
+                if config.voice(Some(voice)).is_none() {
+                    return Err(format!("Unknown voice: {}", voice));
+                }
+            }
+            item.format()
+                .resolve(&self.output, sampling_rate)
+                .map_err(|e| format!("Item {}: {}", item.id, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders and encodes one item, returning the file and its duration.
+async fn render_item(
+    item: &BatchItem,
+    body: &BatchBody,
+    config: &Config,
+    preprocessor: &Preprocessor<'_>,
+    dictionary: &ActiveDictionary,
+) -> Result<(Vec<u8>, f64), AppError> {
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let audio_format = item.format();
+    let format = audio_format
+        .resolve(&body.output, sampling_rate)
+        .map_err(AppError::InvalidRequest)?;
+    let input = item.input(body.prosody);
+    let options = SynthesisOptions {
+        text: item.text.clone(),
+        metadata: body.metadata,
+        ..SynthesisOptions::default()
+    };
+    let metadata = options.describe(&input, config);
+    let parts = request::parts(input, preprocessor, config, dictionary)?;
+
+    let started = Instant::now();
+    let mut rendered = synthesis::render(&config.synthesis, parts, false).await?;
+    metrics::observe_synthesis(
+        "batch",
+        item.voice.as_deref(),
+        audio_format.extension(),
+        started,
+    );
+    rendered.post_process(
+        sampling_rate,
+        &config.post_process(item.voice.as_deref(), &body.postprocess),
+    );
+    let duration = rendered.samples.len() as f64 / sampling_rate as f64;
+    let samples = format::convert(&rendered.samples, sampling_rate, 1, &format);
+    let file = audio_format.encode(&samples, &format, metadata.as_ref())?;
+    Ok((file, duration))
+}
+
+/// Synthesizes a list of items into an archive of files named by item id,
+/// with a manifest of durations and errors. The whole batch is charged up
+/// front; items that fail are reported in the manifest.
+async fn generate_batch(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    normalizer: web::Data<Normalizer>,
+    dictionary: web::Data<ActiveDictionary>,
+    query: web::Query<Credentials>,
+    body: web::Json<BatchBody>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let config = config.get_ref();
+    let mut body = body.into_inner();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+    match presets::load(pool, user.id, body.preset.as_deref()).await {
+        Ok(preset) => body.apply(&preset),
+        Err(e) => return Ok(e),
+    }
+
+    if let Err(e) = body.validate(config) {
+        return Ok(HttpResponse::BadRequest().body(e));
+    }
+    let mut length = 0;
+    for item in &body.items {
+        let item_length = item.text.chars().count();
+        if let Err(e) = check_length(item_length, &user, config) {
+            return Ok(e);
+        }
+        length += item_length;
+    }
+    let user = match charge(pool, &user, length).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let preprocessor =
+        Preprocessor::load(pool, user.id, &normalizer, body.normalize.unwrap_or(true)).await?;
+    let results = stream::iter(
+        body.items
+            .iter()
+            .map(|item| render_item(item, &body, config, &preprocessor, &dictionary)),
+    )
+    .buffered(config.batch.concurrency.max(1))
+    .collect::<Vec<_>>()
+    .await;
+
+    let mut files = Vec::new();
+    let mut items = Vec::with_capacity(body.items.len());
+    for (item, result) in body.items.iter().zip(results) {
+        let (file, duration, error) = match result {
+            Ok((data, duration)) => {
+                files.push((item.file_name(), data));
+                (Some(item.file_name()), Some(duration), None)
+            }
+            Err(e) => {
+                error!("Batch item {} failed: {:?}", item.id, e);
+                let message = match e {
+                    AppError::InvalidRequest(_) | AppError::UnknownVoice(_) => e.to_string(),
+                    _ => "Synthesis failed.".to_string(),
+                };
+                (None, None, Some(message))
+            }
+        };
+        items.push(ManifestItem {
+            id: item.id.clone(),
+            file,
+            format: item.format(),
+            duration,
+            error,
+        });
+    }
+    let succeeded = files.len();
+    let manifest = Manifest {
+        succeeded,
+        failed: items.len() - succeeded,
+        items,
+    };
+    files.push((
+        MANIFEST_NAME.to_string(),
+        serde_json::to_vec_pretty(&manifest)?,
+    ));
+
+    let archive = body.archive;
+    Ok(HttpResponse::Ok()
+        .content_type(archive.content_type())
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"batch.{}\"", archive.extension()),
+        )
+        .body(archive.pack(&files)))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig, config: &Config) {
+    let items = config.batch.max_items;
+    cfg.service(
+        web::resource("/tts/batch")
+            .app_data(web::JsonConfig::default().limit(config.json_limit(items, items)))
+            .route(web::post().to(generate_batch)),
+    );
+}
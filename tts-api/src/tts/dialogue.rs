@@ -0,0 +1,351 @@
+use crate::{
+    audio::{
+        self,
+        format::{self, OutputFormat},
+        metadata::Metadata,
+        process::PostProcess,
+    },
+    auth::{authenticate, Credentials},
+    backend::prosody::Prosody,
+    config::Config,
+    dictionary::ActiveDictionary,
+    error::AppError,
+    metrics,
+    models::preset::PresetSettings,
+    presets,
+    text::{
+        normalize::Normalizer,
+        ssml::{MAX_BREAK_MILLIS, MAX_TOTAL_BREAK_MILLIS},
+        Preprocessor,
+    },
+    tts::{
+        routes::{charge, check_length},
+        synthesis::{self, Part},
+    },
+};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use std::{collections::HashMap, time::Instant};
+
+const MAX_LINES: usize = 200;
+/// Speakers are spread evenly across this much of the stereo field.
+const MAX_AUTO_PAN: f32 = 0.6;
+
+#[derive(Deserialize, Debug)]
+struct DialogueLine {
+    voice: Option<String>,
+    text: String,
+    #[serde(default)]
+    prosody: Prosody,
+    pause_after_ms: Option<u32>,
+    pan: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DialogueBody {
+    lines: Vec<DialogueLine>,
+    stereo: Option<bool>,
+    normalize: Option<bool>,
+    #[serde(default)]
+    postprocess: PostProcess,
+    #[serde(default)]
+    format: OutputFormat,
+    metadata: Option<bool>,
+    preset: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct LineTiming {
+    index: usize,
+    voice: Option<String>,
+    start: f64,
+    end: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct DialogueResponse {
+    sampling_rate: u32,
+    channels: u16,
+    duration: f64,
+    audio: String,
+    lines: Vec<LineTiming>,
+}
+
+impl DialogueBody {
+    /// Fills options the request left unset from a preset.
+    fn apply(&mut self, preset: &PresetSettings) {
+        for line in &mut self.lines {
+            if line.voice.is_none() {
+                line.voice = preset.voice.clone();
+            }
+            if let Some(prosody) = &preset.prosody {
+                line.prosody = prosody.then(&line.prosody);
+            }
+        }
+        self.normalize = self.normalize.or(preset.normalize);
+        self.postprocess = self.postprocess.or(&preset.postprocess);
+        self.format = self.format.or(&preset.output);
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), String> {
+        if self.lines.is_empty() {
+            return Err("Dialogue must have at least one line.".to_string());
+        }
+        if self.lines.len() > MAX_LINES {
+            return Err(format!("Dialogue must have at most {} lines.", MAX_LINES));
+        }
+        for (index, line) in self.lines.iter().enumerate() {
+            if line.text.trim().is_empty() {
+                return Err(format!("Line {} has no text.", index));
+            }
+            if let Some(voice) = &line.voice {
+                if config.voice(Some(voice)).is_none() {
+                    return Err(format!("Unknown voice: {}", voice));
+                }
+            }
+            line.prosody
+                .validate()
+                .map_err(|e| format!("Line {}: {}", index, e))?;
+            if line.pause_after_ms.unwrap_or(0) > MAX_BREAK_MILLIS {
+                return Err(format!(
+                    "Line {}: pause must be at most {}ms.",
+                    index, MAX_BREAK_MILLIS
+                ));
+            }
+            if let Some(pan) = line.pan {
+                if !(-1.0..=1.0).contains(&pan) {
+                    return Err(format!("Line {}: pan must be between -1 and 1.", index));
+                }
+            }
+        }
+        let pause_millis: u32 = self
+            .lines
+            .iter()
+            .map(|line| line.pause_after_ms.unwrap_or(0))
+            .sum();
+        if pause_millis > MAX_TOTAL_BREAK_MILLIS {
+            return Err(format!(
+                "Pauses must add up to at most {}ms.",
+                MAX_TOTAL_BREAK_MILLIS
+            ));
+        }
+        self.postprocess.validate()?;
+        self.format.validate()
+    }
+
+    /// Describes the dialogue for tagging the encoded file, unless the user
+    /// opted out.
+    fn describe(&self, config: &Config) -> Option<Metadata> {
+        if !self.metadata.unwrap_or(true) {
+            return None;
+        }
+        let mut voices = Vec::new();
+        for line in &self.lines {
+            let name = line.voice.clone().unwrap_or_else(|| "default".to_string());
+            if !voices.contains(&name) {
+                voices.push(name);
+            }
+        }
+        let text = self
+            .lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let first = &self.lines[0];
+        let parameters = config
+            .voice(first.voice.as_deref())
+            .map(|voice| first.prosody.apply(&voice).parameters())
+            .unwrap_or_default();
+        Some(Metadata::new(&text, voices, parameters))
+    }
+
+    /// Resolves the stereo position of each line. Lines without an explicit
+    /// `pan` are placed by speaker, in order of first appearance.
+    fn pans(&self) -> Vec<f32> {
+        let mut speakers = Vec::new();
+        for line in &self.lines {
+            if !speakers.contains(&&line.voice) {
+                speakers.push(&line.voice);
+            }
+        }
+        let positions = speakers
+            .iter()
+            .enumerate()
+            .map(|(index, voice)| {
+                let position = if speakers.len() < 2 {
+                    0.0
+                } else {
+                    index as f32 / (speakers.len() - 1) as f32 * 2.0 - 1.0
+                };
+                (*voice, position * MAX_AUTO_PAN)
+            })
+            .collect::<HashMap<_, _>>();
+
+        self.lines
+            .iter()
+            .map(|line| line.pan.unwrap_or(positions[&line.voice]))
+            .collect()
+    }
+}
+
+async fn generate_dialogue(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    normalizer: web::Data<Normalizer>,
+    dictionary: web::Data<ActiveDictionary>,
+    query: web::Query<Credentials>,
+    body: web::Json<DialogueBody>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let config = config.get_ref();
+    let mut body = body.into_inner();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+    match presets::load(pool, user.id, body.preset.as_deref()).await {
+        Ok(preset) => body.apply(&preset),
+        Err(e) => return Ok(e),
+    }
+
+    if let Err(e) = body.validate(config) {
+        return Ok(HttpResponse::BadRequest().body(e));
+    }
+    let length = body.lines.iter().map(|line| line.text.chars().count()).sum();
+    if let Err(e) = check_length(length, &user, config) {
+        return Ok(e);
+    }
+    let user = match charge(pool, &user, length).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let preprocessor = Preprocessor::load(
+        pool,
+        user.id,
+        &normalizer,
+        body.normalize.unwrap_or(true),
+    )
+    .await?;
+    let mut parts = Vec::new();
+    let mut line_parts = Vec::with_capacity(body.lines.len());
+    for line in &body.lines {
+        let voice = config
+            .voice(line.voice.as_deref())
+            .ok_or_else(|| AppError::UnknownVoice(line.voice.clone().unwrap_or_default()))?;
+        line_parts.push(parts.len());
+        parts.push(Part::Speech {
+            text: preprocessor.apply(&line.text),
+            jtalk_config: dictionary.resolve(&line.prosody.apply(&voice)),
+            loudness: config
+                .loudness(line.voice.as_deref())
+                .map(|loudness| loudness.shifted(line.prosody.volume)),
+        });
+        if let Some(millis) = line.pause_after_ms.filter(|millis| *millis > 0) {
+            parts.push(Part::Silence { millis });
+        }
+    }
+
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let started = Instant::now();
+    let mut rendered = synthesis::render(&config.synthesis, parts, false).await?;
+    // One render covers every line, so it is counted under the voice the
+    // lines share, or `mixed` when they differ.
+    let first = body.lines[0].voice.as_deref();
+    let voice = if body.lines.iter().all(|line| line.voice.as_deref() == first) {
+        first
+    } else {
+        Some("mixed")
+    };
+    metrics::observe_synthesis("dialogue", voice, "wav", started);
+    // Lines mix voices, so per-voice track settings do not apply.
+    rendered.post_process(sampling_rate, &config.post_process(None, &body.postprocess));
+    let seconds = |samples: usize| samples as f64 / sampling_rate as f64;
+
+    let stereo = body.stereo.unwrap_or(false);
+    let (samples, channels) = if stereo {
+        let mut mixed = vec![0i16; rendered.samples.len() * 2];
+        for (part, pan) in line_parts.iter().zip(body.pans()) {
+            let span = rendered.spans[*part].clone();
+            let panned = audio::pan(&rendered.samples[span.clone()], pan);
+            mixed[span.start * 2..span.end * 2].copy_from_slice(&panned);
+        }
+        (mixed, 2)
+    } else {
+        (rendered.samples, 1)
+    };
+
+    let lines = body
+        .lines
+        .iter()
+        .zip(line_parts)
+        .enumerate()
+        .map(|(index, (line, part))| LineTiming {
+            index,
+            voice: line.voice.clone(),
+            start: seconds(rendered.spans[part].start),
+            end: seconds(rendered.spans[part].end),
+        })
+        .collect();
+
+    let format = body.format.resolve(sampling_rate, channels);
+    let duration = seconds(samples.len() / channels as usize);
+    let samples = format::convert(&samples, sampling_rate, channels, &format);
+    let wav = audio::wav::encode(&samples, &format, body.describe(config).as_ref());
+    Ok(HttpResponse::Ok().json(DialogueResponse {
+        sampling_rate: format.sample_rate,
+        channels: format.channels,
+        duration,
+        audio: base64::encode(&wav),
+        lines,
+    }))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig, config: &Config) {
+    cfg.service(
+        web::resource("/tts/dialogue")
+            .app_data(web::JsonConfig::default().limit(config.json_limit(1, MAX_LINES)))
+            .route(web::post().to(generate_dialogue)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dialogue(pauses: &[u32]) -> DialogueBody {
+        DialogueBody {
+            lines: pauses
+                .iter()
+                .map(|pause| DialogueLine {
+                    voice: None,
+                    text: "あ".to_string(),
+                    prosody: Prosody::default(),
+                    pause_after_ms: Some(*pause),
+                    pan: None,
+                })
+                .collect(),
+            stereo: None,
+            normalize: None,
+            postprocess: PostProcess::default(),
+            format: OutputFormat::default(),
+            metadata: None,
+            preset: None,
+        }
+    }
+
+    #[test]
+    fn total_pause_time_is_bounded() {
+        let config = Config::default();
+        assert!(dialogue(&[MAX_BREAK_MILLIS; 6]).validate(&config).is_ok());
+        assert!(dialogue(&[MAX_BREAK_MILLIS + 1]).validate(&config).is_err());
+        assert_eq!(
+            dialogue(&[MAX_BREAK_MILLIS; 7]).validate(&config),
+            Err(format!(
+                "Pauses must add up to at most {}ms.",
+                MAX_TOTAL_BREAK_MILLIS
+            ))
+        );
+    }
+}
@@ -1,3 +1,14 @@
+pub mod batch;
+pub mod dialogue;
+pub mod request;
 pub mod routes;
+pub mod synthesis;
 
-pub use self::routes::init;
+use crate::config::Config;
+use actix_web::web;
+
+pub fn init(cfg: &mut web::ServiceConfig, config: &Config) {
+    routes::init(cfg);
+    dialogue::init(cfg, config);
+    batch::init(cfg, config);
+}
@@ -0,0 +1,224 @@
+use crate::{
+    audio::{
+        self,
+        format::{Encoding, OutputFormat, PcmFormat},
+        g711::Law,
+        metadata::Metadata,
+    },
+    backend::prosody::Prosody,
+    config::Config,
+    dictionary::ActiveDictionary,
+    error::AppError,
+    text::{
+        normalize::Normalizer,
+        ssml::{self, SsmlPart},
+        Preprocessor,
+    },
+    trace,
+    tts::synthesis::Part,
+};
+use sqlx::PgPool;
+
+/// What to synthesize, independent of how the request arrived.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SynthesisOptions {
+    pub text: String,
+    pub normalize: Option<bool>,
+    pub ssml: Option<bool>,
+    pub metadata: Option<bool>,
+    /// Voice of text not in an SSML `<voice>`, or the default voice.
+    pub voice: Option<String>,
+    /// Prosody applied under any SSML `<prosody>`.
+    #[serde(default)]
+    pub prosody: Prosody,
+}
+
+/// Encoded file formats.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Wav,
+    Ogg,
+    Flac,
+    Ulaw,
+    Alaw,
+}
+
+impl SynthesisOptions {
+    /// Parses the request text, treating plain text as a single run, and
+    /// applies the request's voice and prosody to it.
+    pub fn input(&self, config: &Config) -> Result<Vec<SsmlPart>, String> {
+        if let Some(voice) = &self.voice {
+            if config.voice(Some(voice)).is_none() {
+                return Err(format!("Unknown voice: {}", voice));
+            }
+        }
+        self.prosody.validate()?;
+        if !self.ssml.unwrap_or(false) {
+            return Ok(vec![SsmlPart::Text {
+                text: self.text.clone(),
+                voice: self.voice.clone(),
+                prosody: self.prosody,
+            }]);
+        }
+
+        let mut parts = ssml::parse(&self.text)?;
+        for part in &mut parts {
+            if let SsmlPart::Text { voice, prosody, .. } = part {
+                if let Some(name) = voice.as_deref() {
+                    if config.voice(Some(name)).is_none() {
+                        return Err(format!("Unknown voice: {}", name));
+                    }
+                }
+                if voice.is_none() {
+                    *voice = self.voice.clone();
+                }
+                *prosody = self.prosody.then(prosody);
+            }
+        }
+        Ok(parts)
+    }
+
+    /// Describes the request for tagging the encoded file, unless the user
+    /// opted out.
+    pub fn describe(&self, input: &[SsmlPart], config: &Config) -> Option<Metadata> {
+        if !self.metadata.unwrap_or(true) {
+            return None;
+        }
+        let mut text = String::new();
+        let mut voices = Vec::new();
+        let mut parameters = None;
+        for part in input {
+            if let SsmlPart::Text {
+                text: part_text,
+                voice,
+                prosody,
+            } = part
+            {
+                text.push_str(part_text);
+                let name = voice.clone().unwrap_or_else(|| "default".to_string());
+                if !voices.contains(&name) {
+                    voices.push(name);
+                }
+                if parameters.is_none() {
+                    parameters = config
+                        .voice(voice.as_deref())
+                        .map(|voice| prosody.apply(&voice).parameters());
+                }
+            }
+        }
+        Some(Metadata::new(&text, voices, parameters.unwrap_or_default()))
+    }
+
+    /// Applies the user's dictionary and normalization to each run and
+    /// resolves its engine config and loudness.
+    pub async fn prepare(
+        &self,
+        input: Vec<SsmlPart>,
+        user_id: i64,
+        pool: &PgPool,
+        config: &Config,
+        normalizer: &Normalizer,
+        active_dictionary: &ActiveDictionary,
+    ) -> Result<Vec<Part>, AppError> {
+        let preprocessor =
+            Preprocessor::load(pool, user_id, normalizer, self.normalize.unwrap_or(true)).await?;
+        parts(input, &preprocessor, config, active_dictionary)
+    }
+}
+
+/// Applies `preprocessor` to each run of `input` and resolves its engine
+/// config and loudness.
+pub fn parts(
+    input: Vec<SsmlPart>,
+    preprocessor: &Preprocessor,
+    config: &Config,
+    active_dictionary: &ActiveDictionary,
+) -> Result<Vec<Part>, AppError> {
+    let _stage = trace::stage("preprocess");
+    let mut parts = Vec::with_capacity(input.len());
+    for part in input {
+        match part {
+            SsmlPart::Text {
+                text,
+                voice,
+                prosody,
+            } => {
+                let text = preprocessor.apply(&text);
+                let loudness = config
+                    .loudness(voice.as_deref())
+                    .map(|loudness| loudness.shifted(prosody.volume));
+                let voice = config
+                    .voice(voice.as_deref())
+                    .ok_or_else(|| AppError::UnknownVoice(voice.unwrap_or_default()))?;
+                parts.push(Part::Speech {
+                    text,
+                    jtalk_config: active_dictionary.resolve(&prosody.apply(&voice)),
+                    loudness,
+                });
+            }
+            SsmlPart::Break { millis } => parts.push(Part::Silence { millis }),
+        }
+    }
+    Ok(parts)
+}
+
+impl AudioFormat {
+    /// Resolves the PCM layout of mono audio rendered at `sampling_rate`.
+    pub fn resolve(self, output: &OutputFormat, sampling_rate: u32) -> Result<PcmFormat, String> {
+        output.validate()?;
+        match self {
+            AudioFormat::Wav => Ok(output.resolve(sampling_rate, 1)),
+            AudioFormat::Ogg => audio::opus::resolve(output, 1),
+            AudioFormat::Flac => audio::flac::resolve(output, sampling_rate, 1),
+            AudioFormat::Ulaw | AudioFormat::Alaw => {
+                let output = OutputFormat {
+                    encoding: Some(match self {
+                        AudioFormat::Ulaw => Encoding::Mulaw,
+                        _ => Encoding::Alaw,
+                    }),
+                    ..output.clone()
+                };
+                output.validate()?;
+                Ok(output.resolve(sampling_rate, 1))
+            }
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Ogg => "audio/ogg",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::Ulaw => Law::Mulaw.content_type(),
+            AudioFormat::Alaw => Law::Alaw.content_type(),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Ogg => "ogg",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Ulaw => "ulaw",
+            AudioFormat::Alaw => "alaw",
+        }
+    }
+
+    /// Encodes samples converted to `format` by [`AudioFormat::resolve`].
+    pub fn encode(
+        self,
+        samples: &[f32],
+        format: &PcmFormat,
+        metadata: Option<&Metadata>,
+    ) -> Result<Vec<u8>, AppError> {
+        let _stage = trace::stage("encode");
+        Ok(match self {
+            AudioFormat::Wav => audio::wav::encode(samples, format, metadata),
+            AudioFormat::Ogg => audio::opus::encode_ogg(samples, format, metadata)?,
+            AudioFormat::Flac => audio::flac::encode(samples, format, metadata),
+            AudioFormat::Ulaw => audio::g711::encode(samples, Law::Mulaw),
+            AudioFormat::Alaw => audio::g711::encode(samples, Law::Alaw),
+        })
+    }
+}
@@ -1,22 +1,71 @@
 use crate::{
-    auth::token::Token,
-    backend::{openjtalk::OpenJTalk, TtsEngine},
+    audio::{
+        self,
+        format::{self, OutputFormat},
+        process::PostProcess,
+    },
+    auth::authenticate,
+    backend::{
+        analysis::AccentPhrase,
+        label::{self, Mora, PhonemeLabel},
+    },
     config::Config,
+    dictionary::ActiveDictionary,
     error::AppError,
+    metrics,
     models::users::User,
+    presets, shutdown,
+    storage::{self, Storage},
+    text::{normalize::Normalizer, ssml::SsmlPart},
+    trace,
+    tts::{
+        request::{AudioFormat, SynthesisOptions},
+        synthesis::{self, Part},
+    },
 };
 use actix_web::{get, web, HttpResponse, Result};
 use sqlx::PgPool;
-
-const OPUS_SAMPLING_RATE: usize = 48000;
-const OPUS_MILLIS_PER_FRAME: usize = 20;
-const OPUS_FRAME_SIZE: usize = OPUS_SAMPLING_RATE * OPUS_MILLIS_PER_FRAME / 1000;
+use std::time::Instant;
 
 #[derive(Debug, Deserialize, Default)]
 struct TtsGenerateQuery {
     text: String,
     token: String,
     id: i64,
+    normalize: Option<bool>,
+    ssml: Option<bool>,
+    metadata: Option<bool>,
+    store: Option<bool>,
+    voice: Option<String>,
+    preset: Option<String>,
+}
+
+impl TtsGenerateQuery {
+    fn options(&self) -> SynthesisOptions {
+        SynthesisOptions {
+            text: self.text.clone(),
+            normalize: self.normalize,
+            ssml: self.ssml,
+            metadata: self.metadata,
+            voice: self.voice.clone(),
+            ..SynthesisOptions::default()
+        }
+    }
+}
+
+/// A generate request with the user's preset applied.
+struct ResolvedRequest {
+    user: User,
+    options: SynthesisOptions,
+    input: Vec<SsmlPart>,
+    length: usize,
+    post: PostProcess,
+    output: OutputFormat,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatPath {
+    format: AudioFormat,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -25,59 +74,78 @@ struct UserQuery {
     id: i64,
 }
 
-impl From<UserQuery> for TtsGenerateQuery {
-    fn from(query: UserQuery) -> TtsGenerateQuery {
-        TtsGenerateQuery {
-            text: String::from(""),
-            token: query.token,
-            id: query.id,
-        }
-    }
-}
-
 #[derive(Serialize, Debug)]
 struct OpusDataResponse {
     data: Vec<Vec<u8>>,
 }
 
-async fn tts_validate(query: &TtsGenerateQuery, pool: &PgPool) -> Result<User, HttpResponse> {
-    if query.text.chars().count() > 200 {
-        return Err(
-            HttpResponse::BadRequest().body("Text length must be less than 100 characters.")
-        );
-    }
+#[derive(Serialize, Debug)]
+struct AnalyzeResponse {
+    text: String,
+    accent_phrases: Vec<AccentPhrase>,
+}
 
-    let token = Token::new(&query.token);
-    let id = query.id;
-
-    match token.verify(pool, id).await {
-        Ok(true) => {}
-        Ok(false) => return Err(HttpResponse::Unauthorized().body("Invalid token.")),
-        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => {
-            return Err(HttpResponse::NotFound().body("User not found"))
-        },
-        Err(e) => {
-            error!("{:?}", e);
-            return Err(HttpResponse::InternalServerError().body("Unexpected Error"));
-        }
+#[derive(Serialize, Debug)]
+struct TimedAudioResponse {
+    sampling_rate: u32,
+    channels: u16,
+    duration: f64,
+    audio: String,
+    phonemes: Vec<PhonemeLabel>,
+    moras: Vec<Mora>,
+}
+
+pub fn spoken_length(input: &[SsmlPart]) -> usize {
+    input.iter().map(SsmlPart::spoken_length).sum()
+}
+
+pub fn check_length(length: usize, user: &User, config: &Config) -> Result<(), HttpResponse> {
+    let max_length = config.plans.limits(user.plan()).max_text_length;
+
+    if length > max_length {
+        return Err(HttpResponse::BadRequest().body(format!(
+            "Text length must be at most {} characters.",
+            max_length
+        )));
     }
 
-    let user = match User::get_or_create(pool, id).await {
-        Ok(user) => user,
-        Err(_) => return Err(HttpResponse::InternalServerError().body("Unexpected Error")),
-    };
+    Ok(())
+}
+
+/// Authenticates the user and charges `length` characters to their quota.
+pub async fn tts_validate(
+    id: i64,
+    token: &str,
+    length: usize,
+    pool: &PgPool,
+    config: &Config,
+) -> Result<User, HttpResponse> {
+    let user = authenticate(pool, id, token).await?;
+
+    check_length(length, &user, config)?;
+    charge(pool, &user, length).await
+}
 
-    let length = query.text.chars().count() as i64;
+/// Charges `length` characters to the user's quota, failing if it would be
+/// exceeded. Returns the updated user.
+pub async fn charge(pool: &PgPool, user: &User, length: usize) -> Result<User, HttpResponse> {
+    let _stage = trace::stage("charge");
+    let length = length as i64;
 
     if user.character_count + length > user.character_limit {
+        info!("User {} is over their character quota", user.id);
+        metrics::QUOTA_REJECTIONS.inc(&["characters"]);
         return Err(HttpResponse::TooManyRequests().body("Account quota exceeded."));
     }
 
-    if User::use_capability(pool, id, length).await.is_err() {
+    if let Err(e) = User::use_capability(pool, user.id, length).await {
+        error!("Failed to charge user {}: {:?}", user.id, e);
         return Err(HttpResponse::InternalServerError().body("Unexpected Error"));
     }
+    shutdown::reserve(user.id, length);
+    metrics::CHARACTERS.inc_by(&[], length as f64);
 
-    let user = match User::get_or_create(pool, id).await {
+    let user = match User::get_or_create(pool, user.id).await {
         Ok(user) => user,
         Err(_) => return Err(HttpResponse::InternalServerError().body("Unexpected Error")),
     };
@@ -85,85 +153,331 @@ async fn tts_validate(query: &TtsGenerateQuery, pool: &PgPool) -> Result<User, H
     Ok(user)
 }
 
+/// Authenticates the user, applies the requested or default preset and
+/// validates the request. Characters are charged separately with [`charge`].
+async fn resolve(
+    pool: &PgPool,
+    config: &Config,
+    query: &TtsGenerateQuery,
+    post: &PostProcess,
+    output: &OutputFormat,
+) -> Result<ResolvedRequest, HttpResponse> {
+    let _stage = trace::stage("validate");
+    let user = authenticate(pool, query.id, &query.token).await?;
+    let preset = presets::load(pool, user.id, query.preset.as_deref()).await?;
+
+    let mut options = query.options();
+    preset.apply(&mut options);
+    let post = post.or(&preset.postprocess);
+    let output = output.or(&preset.output);
+    if let Err(e) = post.validate().and_then(|_| output.validate()) {
+        info!("Rejected output options: {}", e);
+        return Err(HttpResponse::BadRequest().body(e));
+    }
+    let input = options.input(config).map_err(|e| {
+        info!("Rejected input: {}", e);
+        HttpResponse::BadRequest().body(e)
+    })?;
+    let length = spoken_length(&input);
+    check_length(length, &user, config)?;
+
+    Ok(ResolvedRequest {
+        user,
+        options,
+        input,
+        length,
+        post,
+        output,
+    })
+}
+
 #[get("/user")]
-async fn get_user(pool: web::Data<PgPool>, query: web::Query<UserQuery>) -> Result<HttpResponse, HttpResponse> {
-    let query = query.into_inner().into();
+async fn get_user(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    query: web::Query<UserQuery>,
+) -> Result<HttpResponse, HttpResponse> {
+    let query = query.into_inner();
     let pool = pool.get_ref();
 
-    match tts_validate(&query, pool).await {
+    match tts_validate(query.id, &query.token, 0, pool, config.get_ref()).await {
         Ok(user) => Ok(HttpResponse::Ok().json(user)),
         Err(err) => Ok(err),
     }
 }
 
-#[get("/tts/generate.wav")]
-async fn generate_wav(
+#[get("/tts/generate.opus")]
+async fn generate_opus(
     pool: web::Data<PgPool>,
     config: web::Data<Config>,
+    normalizer: web::Data<Normalizer>,
+    dictionary: web::Data<ActiveDictionary>,
     query: web::Query<TtsGenerateQuery>,
+    post: web::Query<PostProcess>,
+    output: web::Query<OutputFormat>,
 ) -> Result<HttpResponse, AppError> {
-    let query = query.into_inner();
     let pool = pool.get_ref();
+    let config = config.get_ref();
+    let request = match resolve(pool, config, &query, &post, &output).await {
+        Ok(request) => request,
+        Err(e) => return Ok(e),
+    };
+    let format = match audio::opus::resolve(&request.output, 1) {
+        Ok(format) => format,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+    };
+    let user = match charge(pool, &request.user, request.length).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
 
-    if let Err(e) = tts_validate(&query, pool).await {
-        return Ok(e);
+    let parts = request
+        .options
+        .prepare(
+            request.input,
+            user.id,
+            pool,
+            config,
+            &normalizer,
+            &dictionary,
+        )
+        .await?;
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let started = Instant::now();
+    let mut rendered = synthesis::render(&config.synthesis, parts, false).await?;
+    metrics::observe_synthesis("speech", request.options.voice.as_deref(), "opus", started);
+    rendered.post_process(
+        sampling_rate,
+        &config.post_process(request.options.voice.as_deref(), &request.post),
+    );
+    let samples = format::convert(&rendered.samples, sampling_rate, 1, &format);
+    let chunks = audio::opus::encode(&samples, &format)?;
+
+    Ok(HttpResponse::Ok().json::<OpusDataResponse>(OpusDataResponse { data: chunks }))
+}
+
+/// Synthesizes an encoded file. With `store`, the file is also kept in audio
+/// storage and its id returned in `X-Audio-Id`. Requests from users whose
+/// storage is full are rejected before synthesis. A file that turns out not
+/// to fit, or fails to store, is still returned, as its characters are
+/// charged, with `X-Audio-Stored: false`.
+#[get("/tts/generate.{format:(wav|ogg|flac|ulaw|alaw)}")]
+#[allow(clippy::too_many_arguments)]
+async fn generate_file(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    normalizer: web::Data<Normalizer>,
+    dictionary: web::Data<ActiveDictionary>,
+    storage: web::Data<Storage>,
+    path: web::Path<FormatPath>,
+    query: web::Query<TtsGenerateQuery>,
+    post: web::Query<PostProcess>,
+    output: web::Query<OutputFormat>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let config = config.get_ref();
+    let audio_format = path.format;
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let store = query.store.unwrap_or(false);
+    let request = match resolve(pool, config, &query, &post, &output).await {
+        Ok(request) => request,
+        Err(e) => return Ok(e),
+    };
+    let format = match audio_format.resolve(&request.output, sampling_rate) {
+        Ok(format) => format,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+    };
+    if store && storage::remaining(pool, &request.user, config).await? <= 0 {
+        return Ok(storage::quota_exceeded());
+    }
+    let user = match charge(pool, &request.user, request.length).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
     };
 
-    let jtalk_config = config.get_ref().openjtalk.clone();
-    let engine = OpenJTalk::from_config(jtalk_config)?;
-    let buffer = web::block(move || engine.generate(&query.text)).await;
-    let buffer = match buffer {
-        Ok(buffer) => buffer,
+    let metadata = request.options.describe(&request.input, config);
+    let parts = request
+        .options
+        .prepare(
+            request.input,
+            user.id,
+            pool,
+            config,
+            &normalizer,
+            &dictionary,
+        )
+        .await?;
+    let started = Instant::now();
+    let mut rendered = match synthesis::render(&config.synthesis, parts, false).await {
+        Ok(rendered) => rendered,
         Err(err) => {
             error!("{:#?}", err);
             return Ok(HttpResponse::InternalServerError().body("Internal server error"));
         }
     };
-    Ok(HttpResponse::Ok().body(buffer))
+    metrics::observe_synthesis(
+        "speech",
+        request.options.voice.as_deref(),
+        audio_format.extension(),
+        started,
+    );
+    rendered.post_process(
+        sampling_rate,
+        &config.post_process(request.options.voice.as_deref(), &request.post),
+    );
+    let duration = rendered.samples.len() as f64 / sampling_rate as f64;
+    let samples = format::convert(&rendered.samples, sampling_rate, 1, &format);
+    let buffer = audio_format.encode(&samples, &format, metadata.as_ref())?;
+
+    let mut response = HttpResponse::Ok();
+    response.content_type(audio_format.content_type());
+    if store {
+        let stored = storage::store(
+            pool,
+            &storage,
+            config,
+            &user,
+            buffer.clone(),
+            audio_format,
+            duration,
+        )
+        .await;
+        match stored {
+            Ok(Some(audio)) => {
+                response
+                    .header("X-Audio-Id", audio.id.to_string())
+                    .header("Content-Location", format!("/audio/{}", audio.id));
+            }
+            Ok(None) => {
+                metrics::QUOTA_REJECTIONS.inc(&["storage"]);
+                response.header("X-Audio-Stored", "false");
+            }
+            Err(e) => {
+                error!("Failed to store audio: {:?}", e);
+                response.header("X-Audio-Stored", "false");
+            }
+        }
+    }
+    Ok(response.body(buffer))
 }
 
-#[get("/tts/generate.opus")]
-async fn generate_opus(
+#[get("/tts/generate.json")]
+async fn generate_json(
     pool: web::Data<PgPool>,
     config: web::Data<Config>,
+    normalizer: web::Data<Normalizer>,
+    dictionary: web::Data<ActiveDictionary>,
     query: web::Query<TtsGenerateQuery>,
+    post: web::Query<PostProcess>,
+    output: web::Query<OutputFormat>,
 ) -> Result<HttpResponse, AppError> {
-    let query = query.into_inner();
     let pool = pool.get_ref();
-    if let Err(e) = tts_validate(&query, pool).await {
-        return Ok(e);
+    let config = config.get_ref();
+    let request = match resolve(pool, config, &query, &post, &output).await {
+        Ok(request) => request,
+        Err(e) => return Ok(e),
+    };
+    let user = match charge(pool, &request.user, request.length).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
     };
 
-    let jtalk_config = config.get_ref().openjtalk.clone();
-    let engine = OpenJTalk::from_config(jtalk_config)?;
-    let buffer = web::block(move || engine.generate_i16(&query.text))
-        .await
-        .map_err(|_| AppError::SubprocessError())?;
-
-    let mut encoder = opus::Encoder::new(
-        OPUS_SAMPLING_RATE as u32,
-        opus::Channels::Mono,
-        opus::Application::Audio,
-    )?;
-
-    let chunks = buffer
-        .chunks(OPUS_FRAME_SIZE)
-        .map(|chunk| {
-            let v = Vec::from(chunk);
-            let mut buf = vec![0u8; 256];
-            let len = match encoder.encode(&v, &mut buf) {
-                Ok(len) => len,
-                Err(e) => return Err(e),
-            };
-            Ok(Vec::from(&buf[..len]))
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+    let metadata = request.options.describe(&request.input, config);
+    let parts = request
+        .options
+        .prepare(
+            request.input,
+            user.id,
+            pool,
+            config,
+            &normalizer,
+            &dictionary,
+        )
+        .await?;
+    let sampling_rate = config.openjtalk.sampling_rate();
+    let started = Instant::now();
+    let mut rendered = synthesis::render(&config.synthesis, parts, true).await?;
+    metrics::observe_synthesis("speech", request.options.voice.as_deref(), "wav", started);
+    rendered.post_process(
+        sampling_rate,
+        &config.post_process(request.options.voice.as_deref(), &request.post),
+    );
 
-    Ok(HttpResponse::Ok().json::<OpusDataResponse>(OpusDataResponse { data: chunks }))
+    let format = request.output.resolve(sampling_rate, 1);
+    let samples = format::convert(&rendered.samples, sampling_rate, 1, &format);
+    let wav = audio::wav::encode(&samples, &format, metadata.as_ref());
+
+    Ok(HttpResponse::Ok().json(TimedAudioResponse {
+        sampling_rate: format.sample_rate,
+        channels: format.channels,
+        duration: rendered.samples.len() as f64 / sampling_rate as f64,
+        audio: base64::encode(&wav),
+        moras: label::group_moras(&rendered.labels),
+        phonemes: rendered.labels,
+    }))
+}
+
+/// Returns the readings and accent phrases OpenJTalk assigns to the input,
+/// without consuming synthesis quota.
+#[get("/tts/analyze")]
+async fn analyze(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    normalizer: web::Data<Normalizer>,
+    dictionary: web::Data<ActiveDictionary>,
+    query: web::Query<TtsGenerateQuery>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let config = config.get_ref();
+    let request = match resolve(
+        pool,
+        config,
+        &query,
+        &PostProcess::default(),
+        &OutputFormat::default(),
+    )
+    .await
+    {
+        Ok(request) => request,
+        Err(e) => return Ok(e),
+    };
+
+    let parts = request
+        .options
+        .prepare(
+            request.input,
+            request.user.id,
+            pool,
+            config,
+            &normalizer,
+            &dictionary,
+        )
+        .await?;
+    let mut text = String::new();
+    let mut accent_phrases = Vec::new();
+    for part in parts {
+        if let Part::Speech {
+            text: part_text,
+            jtalk_config,
+            ..
+        } = part
+        {
+            accent_phrases
+                .extend(synthesis::analyze(&config.synthesis, &jtalk_config, &part_text).await?);
+            text.push_str(&part_text);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(AnalyzeResponse {
+        text,
+        accent_phrases,
+    }))
 }
 
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(get_user);
-    cfg.service(generate_wav);
     cfg.service(generate_opus);
+    cfg.service(generate_file);
+    cfg.service(generate_json);
+    cfg.service(analyze);
 }
@@ -0,0 +1,389 @@
+use crate::{
+    audio::{
+        self,
+        process::{self, Loudness, PostProcess},
+    },
+    backend::{
+        analysis::{self, AccentPhrase},
+        label::PhonemeLabel,
+        openjtalk::OpenJTalk,
+        TtsEngine,
+    },
+    config::{OpenJTalkConfig, SynthesisConfig},
+    error::AppError,
+    metrics,
+    text::segment::{self, Boundary},
+    trace,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A unit of a synthesis request: text spoken with a resolved engine config
+/// and loudness normalization, or an explicit pause.
+#[derive(Debug, Clone)]
+pub enum Part {
+    Speech {
+        text: String,
+        jtalk_config: OpenJTalkConfig,
+        loudness: Option<Loudness>,
+    },
+    Silence {
+        millis: u32,
+    },
+}
+
+/// A rendered track. `spans` holds the sample range of each input part,
+/// excluding the silence inserted after it.
+#[derive(Debug, Default)]
+pub struct Rendered {
+    pub samples: Vec<i16>,
+    pub labels: Vec<PhonemeLabel>,
+    pub spans: Vec<Range<usize>>,
+}
+
+/// Counts rendered items (speech segments and silences) of a running
+/// [`render_with_progress`].
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+}
+
+impl Progress {
+    /// Returns the number of rendered items and the total.
+    pub fn get(&self) -> (usize, usize) {
+        (
+            self.done.load(Ordering::Relaxed),
+            self.total.load(Ordering::Relaxed),
+        )
+    }
+}
+
+enum Item {
+    Segment {
+        text: String,
+        boundary: Boundary,
+        jtalk_config: OpenJTalkConfig,
+    },
+    Silence {
+        millis: u32,
+    },
+}
+
+/// Runs only the text analysis of `text`, split the same way as for synthesis.
+pub async fn analyze(
+    settings: &SynthesisConfig,
+    jtalk_config: &OpenJTalkConfig,
+    text: &str,
+) -> Result<Vec<AccentPhrase>, AppError> {
+    let segments = segment::split(text, settings.max_segment_length);
+    let engine = OpenJTalk::from_config(jtalk_config.clone())?;
+    let words = trace::block(move || {
+        let mut words = Vec::new();
+        for segment in segments {
+            words.extend(engine.analyze(&segment.text)?);
+        }
+        Ok::<_, AppError>(words)
+    })
+    .await?;
+
+    Ok(analysis::group_accent_phrases(words))
+}
+
+/// Renders `parts` in order and joins them into one track.
+///
+/// Speech is split into segments which are synthesized up to
+/// `settings.concurrency` at a time. Segments are separated by the configured
+/// sentence or clause silence, and explicit silence is inserted as is. When
+/// `with_labels` is set, phoneme labels are returned timed against the joined
+/// track, with inserted silence labeled as `pau`. Each speech part is
+/// normalized to its own loudness target. All parts must share a sampling
+/// rate.
+pub async fn render(
+    settings: &SynthesisConfig,
+    parts: Vec<Part>,
+    with_labels: bool,
+) -> Result<Rendered, AppError> {
+    render_with_progress(settings, parts, with_labels, &Progress::default()).await
+}
+
+/// Same as [`render`], reporting the number of rendered items to `progress`.
+pub async fn render_with_progress(
+    settings: &SynthesisConfig,
+    parts: Vec<Part>,
+    with_labels: bool,
+    progress: &Progress,
+) -> Result<Rendered, AppError> {
+    let _in_flight = metrics::InFlight::start();
+    let _stage = trace::stage("synthesis");
+    let sampling_rate = parts
+        .iter()
+        .find_map(|part| match part {
+            Part::Speech { jtalk_config, .. } => Some(jtalk_config.sampling_rate()),
+            Part::Silence { .. } => None,
+        })
+        .unwrap_or_else(|| OpenJTalkConfig::default().sampling_rate());
+
+    let part_count = parts.len();
+    let mut items = Vec::new();
+    let mut loudness = vec![None; part_count];
+    for (index, part) in parts.into_iter().enumerate() {
+        match part {
+            Part::Speech {
+                text,
+                jtalk_config,
+                loudness: part_loudness,
+            } => {
+                loudness[index] = part_loudness;
+                for segment in segment::split(&text, settings.max_segment_length) {
+                    let item = Item::Segment {
+                        text: segment.text,
+                        boundary: segment.boundary,
+                        jtalk_config: jtalk_config.clone(),
+                    };
+                    items.push((index, item));
+                }
+            }
+            Part::Silence { millis } => items.push((index, Item::Silence { millis })),
+        }
+    }
+
+    progress.done.store(0, Ordering::Relaxed);
+    progress.total.store(items.len(), Ordering::Relaxed);
+    let rendered = stream::iter(items.into_iter().map(|(index, item)| async move {
+        let (samples, labels, boundary) = match item {
+            Item::Segment {
+                text,
+                boundary,
+                jtalk_config,
+            } => {
+                let engine = OpenJTalk::from_config(jtalk_config)?;
+                let (samples, labels) = trace::block(move || {
+                    if with_labels {
+                        engine.generate_labeled(&text)
+                    } else {
+                        engine.generate_i16(&text).map(|samples| (samples, Vec::new()))
+                    }
+                })
+                .await?;
+                (samples, labels, Some(boundary))
+            }
+            Item::Silence { millis } => (audio::silence(sampling_rate, millis), Vec::new(), None),
+        };
+        progress.done.fetch_add(1, Ordering::Relaxed);
+        Ok::<_, AppError>((index, samples, labels, boundary))
+    }))
+    .buffered(settings.concurrency.max(1))
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let seconds = |samples: usize| samples as f64 / sampling_rate as f64;
+    let mut buffer = Vec::new();
+    let mut timeline = Vec::new();
+    let mut spans: Vec<Option<Range<usize>>> = vec![None; part_count];
+    let mut rendered = rendered.into_iter().peekable();
+    while let Some((index, samples, labels, boundary)) = rendered.next() {
+        let offset = seconds(buffer.len());
+        timeline.extend(labels.into_iter().map(|label| PhonemeLabel {
+            start: label.start + offset,
+            end: label.end + offset,
+            ..label
+        }));
+        if with_labels && boundary.is_none() && !samples.is_empty() {
+            timeline.push(PhonemeLabel {
+                phoneme: "pau".to_string(),
+                start: offset,
+                end: seconds(buffer.len() + samples.len()),
+            });
+        }
+        let start = spans[index].as_ref().map_or(buffer.len(), |span| span.start);
+        buffer.extend(samples);
+        spans[index] = Some(start..buffer.len());
+
+        // Boundary silence only goes between two consecutive segments.
+        if !matches!(rendered.peek(), Some((_, _, _, Some(_)))) {
+            continue;
+        }
+        let millis = match boundary {
+            Some(Boundary::Sentence) => settings.sentence_silence_ms,
+            Some(Boundary::Clause) => settings.clause_silence_ms,
+            Some(Boundary::None) | None => 0,
+        };
+        if millis == 0 {
+            continue;
+        }
+        let start = seconds(buffer.len());
+        buffer.extend(audio::silence(sampling_rate, millis));
+        if with_labels {
+            timeline.push(PhonemeLabel {
+                phoneme: "pau".to_string(),
+                start,
+                end: seconds(buffer.len()),
+            });
+        }
+    }
+
+    let spans = spans
+        .into_iter()
+        .map(|span| span.unwrap_or_default())
+        .collect::<Vec<_>>();
+    for (span, loudness) in spans.iter().zip(loudness) {
+        if let Some(loudness) = loudness {
+            loudness.apply(&mut buffer[span.clone()], sampling_rate);
+        }
+    }
+
+    Ok(Rendered {
+        samples: buffer,
+        labels: timeline,
+        spans,
+    })
+}
+
+impl Rendered {
+    /// Applies track-level processing: trimming, loudness normalization,
+    /// fades and padding, in that order. Labels and spans are kept in sync.
+    pub fn post_process(&mut self, sampling_rate: u32, settings: &PostProcess) {
+        let _stage = trace::stage("postprocess");
+        if let Some(threshold) = settings.trim_threshold() {
+            let range = process::trim_range(&self.samples, sampling_rate, threshold);
+            self.crop(range, sampling_rate);
+        }
+        if let Some(loudness) = settings.loudness() {
+            loudness.apply(&mut self.samples, sampling_rate);
+        }
+        process::fade(
+            &mut self.samples,
+            sampling_rate,
+            settings.fade_in_ms.unwrap_or(0),
+            settings.fade_out_ms.unwrap_or(0),
+        );
+        self.pad(
+            sampling_rate,
+            settings.pad_start_ms.unwrap_or(0),
+            settings.pad_end_ms.unwrap_or(0),
+        );
+    }
+
+    fn crop(&mut self, range: Range<usize>, sampling_rate: u32) {
+        let seconds = |samples: usize| samples as f64 / sampling_rate as f64;
+        let (start, end) = (seconds(range.start), seconds(range.end));
+        self.labels.retain(|label| label.end > start && label.start < end);
+        for label in self.labels.iter_mut() {
+            label.start = label.start.max(start) - start;
+            label.end = label.end.min(end) - start;
+        }
+        let clamp = |index: usize| index.max(range.start).min(range.end) - range.start;
+        for span in self.spans.iter_mut() {
+            *span = clamp(span.start)..clamp(span.end);
+        }
+        self.samples.truncate(range.end);
+        self.samples.drain(..range.start);
+    }
+
+    fn pad(&mut self, sampling_rate: u32, start_ms: u32, end_ms: u32) {
+        let seconds = |samples: usize| samples as f64 / sampling_rate as f64;
+        let leading = audio::silence(sampling_rate, start_ms);
+        let trailing = audio::silence(sampling_rate, end_ms);
+        let offset = leading.len();
+
+        if !self.labels.is_empty() {
+            for label in self.labels.iter_mut() {
+                label.start += seconds(offset);
+                label.end += seconds(offset);
+            }
+            if offset > 0 {
+                self.labels.insert(
+                    0,
+                    PhonemeLabel {
+                        phoneme: "pau".to_string(),
+                        start: 0.0,
+                        end: seconds(offset),
+                    },
+                );
+            }
+            if !trailing.is_empty() {
+                let length = offset + self.samples.len();
+                self.labels.push(PhonemeLabel {
+                    phoneme: "pau".to_string(),
+                    start: seconds(length),
+                    end: seconds(length + trailing.len()),
+                });
+            }
+        }
+        for span in self.spans.iter_mut() {
+            *span = span.start + offset..span.end + offset;
+        }
+        self.samples.splice(0..0, leading);
+        self.samples.extend(trailing);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(phoneme: &str, start: f64, end: f64) -> PhonemeLabel {
+        PhonemeLabel {
+            phoneme: phoneme.to_string(),
+            start,
+            end,
+        }
+    }
+
+    fn padding(start_ms: u32, end_ms: u32) -> PostProcess {
+        PostProcess {
+            pad_start_ms: Some(start_ms),
+            pad_end_ms: Some(end_ms),
+            ..PostProcess::default()
+        }
+    }
+
+    #[test]
+    fn padding_adds_silence_at_both_ends() {
+        let mut rendered = Rendered {
+            samples: vec![1; 100],
+            labels: Vec::new(),
+            spans: vec![0..60, 60..100],
+        };
+        rendered.post_process(1000, &padding(10, 20));
+        assert_eq!(rendered.samples.len(), 130);
+        assert!(rendered.samples[..10].iter().all(|sample| *sample == 0));
+        assert!(rendered.samples[10..110].iter().all(|sample| *sample == 1));
+        assert!(rendered.samples[110..].iter().all(|sample| *sample == 0));
+        assert_eq!(rendered.spans, vec![10..70, 70..110]);
+    }
+
+    #[test]
+    fn padding_follows_the_sampling_rate() {
+        for &(sampling_rate, millis, expected) in
+            &[(48_000, 250, 12_000), (44_100, 10, 441), (22_050, 1, 22)]
+        {
+            let mut rendered = Rendered::default();
+            rendered.post_process(sampling_rate, &padding(millis, millis));
+            assert_eq!(rendered.samples.len(), expected * 2);
+        }
+    }
+
+    #[test]
+    fn padding_shifts_labels_and_adds_pauses() {
+        let mut rendered = Rendered {
+            samples: vec![1; 500],
+            labels: vec![label("a", 0.0, 0.5)],
+            spans: vec![0..500],
+        };
+        rendered.post_process(1000, &padding(125, 250));
+        let labels: Vec<(&str, f64, f64)> = rendered
+            .labels
+            .iter()
+            .map(|label| (label.phoneme.as_str(), label.start, label.end))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![("pau", 0.0, 0.125), ("a", 0.125, 0.625), ("pau", 0.625, 0.875)]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::{
+    config::{Config, WebhooksConfig},
+    error::AppError,
+    models::webhook::{self, DeliveryStatus, WebhookDelivery},
+    webhooks::{
+        backoff, check_url, resolve, sign, DELIVERY_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+    },
+};
+use actix_connect::{Connect, ConnectError, TcpConnector};
+use actix_service::{fn_service, pipeline};
+use actix_web::{
+    client::{Client, Connector},
+    http::{header::CONTENT_TYPE, Uri},
+    rt::{self, time::delay_for},
+};
+use chrono::{DateTime, Utc};
+use futures::future;
+use sqlx::PgPool;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Starts delivering queued webhooks on the current arbiter.
+pub fn spawn(pool: PgPool, config: Config) {
+    rt::spawn(run(pool, config));
+}
+
+async fn run(pool: PgPool, config: Config) {
+    let interval = Duration::from_millis(config.webhooks.poll_interval_ms);
+    loop {
+        match poll(&pool, &config).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => error!("Webhook dispatcher error: {:?}", e),
+        }
+        delay_for(interval).await;
+    }
+}
+
+/// Makes one attempt of the next due delivery. Returns whether there was a
+/// delivery to attempt.
+async fn poll(pool: &PgPool, config: &Config) -> Result<bool, AppError> {
+    let settings = &config.webhooks;
+    // Long enough for the request to time out before anyone retries it.
+    let lease_until = Utc::now() + chrono::Duration::seconds(settings.timeout_secs as i64 * 2);
+    let delivery = match WebhookDelivery::claim(pool, lease_until).await? {
+        Some(delivery) => delivery,
+        None => return Ok(false),
+    };
+
+    let secret = webhook::secret(pool, delivery.users_id).await?;
+    let attempt = attempt(settings, &delivery, &secret, resolve).await;
+    let (status, retry_at) = outcome(settings, delivery.attempts, attempt.error.is_none());
+    if let Some(error) = &attempt.error {
+        warn!(
+            "Webhook delivery {} attempt {} failed: {}",
+            delivery.id, delivery.attempts, error
+        );
+    }
+
+    delivery
+        .record(
+            pool,
+            attempt.response_status,
+            attempt.error.as_deref(),
+            attempt.duration_ms,
+            status,
+            retry_at,
+        )
+        .await?;
+    Ok(true)
+}
+
+/// The result of one request made for a delivery.
+struct Attempt {
+    response_status: Option<i32>,
+    error: Option<String>,
+    duration_ms: i32,
+}
+
+/// Builds a client that connects to `addresses` whatever the host of a
+/// request, so that a delivery goes to the addresses its URL was checked
+/// against instead of looking the host up again. Requests still name the
+/// host of their URL in the `Host` header.
+fn client(settings: &WebhooksConfig, addresses: Vec<SocketAddr>) -> Client {
+    let pinned = pipeline(fn_service(move |connect: Connect<Uri>| {
+        future::ok::<_, ConnectError>(connect.set_addrs(addresses.clone()))
+    }))
+    .and_then(TcpConnector::new());
+    Client::builder()
+        .connector(Connector::new().connector(pinned).finish())
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .finish()
+}
+
+/// Posts the payload of a delivery signed with `secret`. The URL is checked
+/// again first, with host names looked up by `lookup`, and not contacted if
+/// it now resolves to a private address.
+async fn attempt<F, R>(
+    settings: &WebhooksConfig,
+    delivery: &WebhookDelivery,
+    secret: &str,
+    lookup: F,
+) -> Attempt
+where
+    F: FnOnce(String, u16) -> R,
+    R: Future<Output = Result<Vec<SocketAddr>, String>>,
+{
+    let started = Instant::now();
+    let addresses = match check_url(settings, &delivery.url, lookup).await {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            return Attempt {
+                response_status: None,
+                error: Some(e),
+                duration_ms: started.elapsed().as_millis() as i32,
+            }
+        }
+    };
+    let timestamp = Utc::now().timestamp();
+    let result = client(settings, addresses)
+        .post(&delivery.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(secret, timestamp, &delivery.payload))
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .send_body(delivery.payload.clone())
+        .await;
+    let duration_ms = started.elapsed().as_millis() as i32;
+
+    let (response_status, error) = match result {
+        Ok(response) if response.status().is_success() => {
+            (Some(response.status().as_u16() as i32), None)
+        }
+        Ok(response) => (
+            Some(response.status().as_u16() as i32),
+            Some(format!("Receiver responded with {}", response.status())),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    Attempt {
+        response_status,
+        error,
+        duration_ms,
+    }
+}
+
+/// Decides what becomes of a delivery after its `attempts`th attempt:
+/// delivered, retried after a backoff, or failed once out of attempts.
+fn outcome(
+    settings: &WebhooksConfig,
+    attempts: i32,
+    delivered: bool,
+) -> (DeliveryStatus, Option<DateTime<Utc>>) {
+    if delivered {
+        (DeliveryStatus::Delivered, None)
+    } else if attempts >= settings.max_attempts {
+        (DeliveryStatus::Failed, None)
+    } else {
+        let retry_at = Utc::now() + backoff(settings, attempts);
+        (DeliveryStatus::Pending, Some(retry_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+
+    /// Accepts one request on a local port, answers it with `status` and
+    /// sends back the raw request.
+    fn listen(status: u16) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                let complete = match text.find("\r\n\r\n") {
+                    Some(end) => {
+                        let length = header(&text, "content-length")
+                            .map_or(0, |length| length.parse().unwrap());
+                        request.len() >= end + 4 + length
+                    }
+                    None => false,
+                };
+                if complete || read == 0 {
+                    break;
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            // The test may be done with the request already.
+            sender.send(String::from_utf8(request).unwrap()).ok();
+        });
+        (url, receiver)
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+        request.lines().find_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next()?;
+            let value = parts.next()?;
+            if key.eq_ignore_ascii_case(name) {
+                Some(value.trim())
+            } else {
+                None
+            }
+        })
+    }
+
+    fn delivery(url: &str) -> WebhookDelivery {
+        WebhookDelivery {
+            id: 7,
+            jobs_id: 1,
+            users_id: 1,
+            url: url.to_string(),
+            payload: r#"{"event":"job.done","job_id":1}"#.to_string(),
+            status: DeliveryStatus::Pending as i32,
+            attempts: 1,
+            next_attempt_at: Utc::now(),
+            response_status: None,
+            error: None,
+            created_at: Utc::now(),
+            finished_at: None,
+        }
+    }
+
+    /// Lets deliveries reach the local listener.
+    fn settings() -> WebhooksConfig {
+        WebhooksConfig {
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+            ..WebhooksConfig::default()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn delivers_signed_payloads() {
+        let (url, requests) = listen(204);
+        let delivery = delivery(&url);
+        let result = attempt(&settings(), &delivery, "secret", resolve).await;
+        assert_eq!(result.response_status, Some(204));
+        assert_eq!(result.error, None);
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /hook "));
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        assert_eq!(body, delivery.payload);
+        let timestamp: i64 = header(&request, TIMESTAMP_HEADER).unwrap().parse().unwrap();
+        assert_eq!(
+            header(&request, SIGNATURE_HEADER),
+            Some(sign("secret", timestamp, body).as_str())
+        );
+        assert_eq!(header(&request, DELIVERY_HEADER), Some("7"));
+        assert_eq!(header(&request, "content-type"), Some("application/json"));
+    }
+
+    #[actix_rt::test]
+    async fn records_receiver_errors() {
+        let (url, _requests) = listen(503);
+        let result = attempt(&settings(), &delivery(&url), "secret", resolve).await;
+        assert_eq!(result.response_status, Some(503));
+        assert!(result.error.unwrap().contains("503"));
+    }
+
+    #[actix_rt::test]
+    async fn does_not_contact_private_addresses() {
+        let (url, requests) = listen(204);
+        let settings = WebhooksConfig::default();
+        let result = attempt(&settings, &delivery(&url), "secret", resolve).await;
+        assert_eq!(result.response_status, None);
+        assert!(result.error.unwrap().contains("private address"));
+        assert!(requests
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+    }
+
+    fn address(url: &str) -> SocketAddr {
+        url::Url::parse(url).unwrap().socket_addrs(|| None).unwrap()[0]
+    }
+
+    #[actix_rt::test]
+    async fn connects_to_the_checked_address() {
+        let (url, requests) = listen(204);
+        let address = address(&url);
+        let settings = WebhooksConfig {
+            allowed_hosts: vec!["hooks.invalid".to_string()],
+            ..WebhooksConfig::default()
+        };
+        // `.invalid` names never resolve, so the request only arrives if it
+        // goes to the address the check saw rather than a second lookup.
+        let host = format!("hooks.invalid:{}", address.port());
+        let delivery = delivery(&format!("http://{}/hook", host));
+        let result = attempt(&settings, &delivery, "secret", |_, _| {
+            future::ok(vec![address])
+        })
+        .await;
+        assert_eq!(result.error, None);
+        assert_eq!(result.response_status, Some(204));
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /hook "));
+        assert_eq!(header(&request, "host"), Some(host.as_str()));
+    }
+
+    #[actix_rt::test]
+    async fn does_not_contact_hosts_that_resolve_to_private_addresses() {
+        let (url, requests) = listen(204);
+        let address = address(&url);
+        let delivery = delivery(&format!("http://hooks.invalid:{}/hook", address.port()));
+        let result = attempt(&WebhooksConfig::default(), &delivery, "secret", |_, _| {
+            future::ok(vec![address])
+        })
+        .await;
+        assert_eq!(result.response_status, None);
+        assert!(result.error.unwrap().contains("private address"));
+        assert!(requests
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+    }
+
+    #[test]
+    fn retries_with_backoff_until_attempts_run_out() {
+        let settings = WebhooksConfig {
+            max_attempts: 3,
+            base_delay_secs: 10,
+            ..WebhooksConfig::default()
+        };
+        assert_eq!(
+            outcome(&settings, 1, true),
+            (DeliveryStatus::Delivered, None)
+        );
+
+        let before = Utc::now();
+        let (status, retry_at) = outcome(&settings, 2, false);
+        assert_eq!(status, DeliveryStatus::Pending);
+        let delay = retry_at.unwrap() - before;
+        assert!(delay >= chrono::Duration::seconds(20));
+        assert!(delay < chrono::Duration::seconds(21));
+
+        assert_eq!(outcome(&settings, 3, false), (DeliveryStatus::Failed, None));
+    }
+}
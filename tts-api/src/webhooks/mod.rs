@@ -0,0 +1,327 @@
+pub mod dispatcher;
+pub mod routes;
+
+pub use self::routes::init;
+
+use crate::{
+    config::{Config, WebhooksConfig},
+    error::AppError,
+    models::{
+        job::{Job, JobStatus},
+        webhook::WebhookDelivery,
+    },
+    trace,
+};
+use actix_web::error::BlockingError;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+};
+use url::{Host, Url};
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// The payload posted when a job finishes.
+#[derive(Serialize, Debug)]
+struct JobEvent<'a> {
+    event: &'static str,
+    job_id: i64,
+    status: JobStatus,
+    result_url: Option<String>,
+    error: Option<&'a str>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// Checks that a callback URL is an http(s) URL whose host only resolves to
+/// public addresses, unless the host is in `[webhooks] allowed_hosts`, and
+/// returns the addresses to connect to. Run when a job is queued and again
+/// before each delivery, as the host may resolve differently by then.
+pub async fn validate_url(config: &WebhooksConfig, url: &str) -> Result<Vec<SocketAddr>, String> {
+    check_url(config, url, resolve).await
+}
+
+/// [`validate_url`] with host names looked up by `lookup`.
+pub async fn check_url<F, R>(
+    config: &WebhooksConfig,
+    url: &str,
+    lookup: F,
+) -> Result<Vec<SocketAddr>, String>
+where
+    F: FnOnce(String, u16) -> R,
+    R: Future<Output = Result<Vec<SocketAddr>, String>>,
+{
+    let parsed = Url::parse(url).map_err(|e| format!("Invalid callback URL: {}", e))?;
+    let host = match parsed.host() {
+        Some(host) if matches!(parsed.scheme(), "http" | "https") => host,
+        _ => return Err(format!("Callback URL must be an http(s) URL: {}", url)),
+    };
+    let port = parsed
+        .port_or_known_default()
+        .expect("http(s) URLs have a default port");
+    let name = host.to_string();
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+
+    let addresses = match host {
+        Host::Ipv4(ip) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Host::Ipv6(ip) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Host::Domain(domain) => lookup(domain.to_string(), port).await?,
+    };
+    if addresses.is_empty() {
+        return Err(format!("Callback host has no addresses: {}", name));
+    }
+    if config
+        .allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+    {
+        return Ok(addresses);
+    }
+    match addresses.iter().find(|address| !is_public(address.ip())) {
+        Some(address) => Err(format!(
+            "Callback URL must not point to a private address: {} ({})",
+            name,
+            address.ip()
+        )),
+        None => Ok(addresses),
+    }
+}
+
+/// Looks up the addresses of `domain` with the system resolver.
+pub async fn resolve(domain: String, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let name = domain.clone();
+    let addresses = trace::block(move || (name.as_str(), port).to_socket_addrs()).await;
+    match addresses {
+        Ok(addresses) => Ok(addresses.collect()),
+        Err(BlockingError::Error(e)) => {
+            Err(format!("Failed to resolve callback host {}: {}", domain, e))
+        }
+        Err(BlockingError::Canceled) => Err("Failed to resolve callback host.".to_string()),
+    }
+}
+
+/// Whether `ip` is publicly routable. Loopback, private, link-local
+/// (including cloud metadata services), shared, unspecified, broadcast,
+/// documentation, multicast and reserved addresses are not.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, _, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b == 18 || b == 19))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            match segments {
+                // IPv4-mapped and NAT64 addresses reach the embedded address.
+                [0, 0, 0, 0, 0, 0xffff, high, low] | [0x64, 0xff9b, 0, 0, 0, 0, high, low] => {
+                    let embedded = Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+                    is_public(IpAddr::V4(embedded))
+                }
+                _ => {
+                    !(ip.is_unspecified()
+                        || ip.is_loopback()
+                        || ip.is_multicast()
+                        || segments[0] & 0xfe00 == 0xfc00
+                        || segments[0] & 0xffc0 == 0xfe80
+                        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+                }
+            }
+        }
+    }
+}
+
+/// Signs a payload as `sha256=<hex>`, the HMAC-SHA256 of
+/// `<timestamp>.<body>` keyed with the user's secret. Receivers recompute it
+/// from the timestamp header and the raw body.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let hex = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("sha256={}", hex)
+}
+
+/// Returns the delay before retrying a delivery that failed `attempts` times.
+pub fn backoff(config: &WebhooksConfig, attempts: i32) -> Duration {
+    let exponent = (attempts - 1).max(0).min(30) as u32;
+    let seconds = config
+        .base_delay_secs
+        .saturating_mul(1 << exponent)
+        .min(config.max_delay_secs);
+    Duration::seconds(seconds)
+}
+
+/// Queues a notification of a finished job, if it has a callback URL.
+pub async fn notify(
+    pool: &PgPool,
+    config: &Config,
+    job: &Job,
+) -> Result<Option<WebhookDelivery>, AppError> {
+    let url = match &job.callback_url {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let status = job.status();
+    let event = JobEvent {
+        event: match status {
+            JobStatus::Done => "job.done",
+            _ => "job.failed",
+        },
+        job_id: job.id,
+        status,
+        result_url: match status {
+            JobStatus::Done => Some(config.server.url(&format!("/jobs/{}/audio", job.id))),
+            _ => None,
+        },
+        error: job.error.as_deref(),
+        finished_at: job.finished_at,
+    };
+    let payload = serde_json::to_string(&event)?;
+    let delivery = WebhookDelivery::create(pool, job.id, job.users_id, url, &payload).await?;
+    Ok(Some(delivery))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        assert_eq!(
+            sign("whsec_test", 1_600_000_000, r#"{"job_id":1}"#),
+            "sha256=d5366068f7f42f917d66e26a394de3d353e33c0fa292a4d3ec10114c0d262066"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let config = WebhooksConfig {
+            base_delay_secs: 10,
+            max_delay_secs: 3600,
+            ..WebhooksConfig::default()
+        };
+        let delays: Vec<i64> = (0..6)
+            .map(|attempts| backoff(&config, attempts).num_seconds())
+            .collect();
+        assert_eq!(delays, vec![10, 10, 20, 40, 80, 160]);
+        assert_eq!(backoff(&config, 10).num_seconds(), 3600);
+        assert_eq!(backoff(&config, i32::MAX).num_seconds(), 3600);
+    }
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for ip in &[
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &[
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn rejects_urls_to_private_hosts() {
+        let config = WebhooksConfig::default();
+        for url in &[
+            "http://127.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]:8080/hook",
+            "http://10.0.0.1/hook",
+            "http://localhost/hook",
+        ] {
+            assert!(validate_url(&config, url).await.is_err(), "{}", url);
+        }
+        assert!(validate_url(&config, "ftp://example.com/").await.is_err());
+        assert!(validate_url(&config, "not a url").await.is_err());
+        assert!(validate_url(&config, "https://93.184.216.34/hook")
+            .await
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn returns_the_checked_addresses() {
+        let config = WebhooksConfig::default();
+        let public: SocketAddr = "93.184.216.34:8443".parse().unwrap();
+        let addresses = check_url(&config, "https://hooks.example:8443/hook", |host, port| {
+            assert_eq!((host.as_str(), port), ("hooks.example", 8443));
+            future::ok(vec![public])
+        })
+        .await;
+        assert_eq!(addresses, Ok(vec![public]));
+        assert_eq!(
+            validate_url(&config, "http://93.184.216.34/hook").await,
+            Ok(vec!["93.184.216.34:80".parse().unwrap()])
+        );
+
+        // One private address among public ones is enough to refuse.
+        let private = "10.0.0.1:443".parse().unwrap();
+        let result = check_url(&config, "https://hooks.example/hook", |_, _| {
+            future::ok(vec![public, private])
+        })
+        .await;
+        assert!(result.unwrap_err().contains("10.0.0.1"));
+        let result = check_url(&config, "https://hooks.example/hook", |_, _| {
+            future::ok(Vec::new())
+        })
+        .await;
+        assert!(result.unwrap_err().contains("no addresses"));
+    }
+
+    #[actix_rt::test]
+    async fn allowed_hosts_skip_the_address_check() {
+        let config = WebhooksConfig {
+            allowed_hosts: vec!["LOCALHOST".to_string(), "::1".to_string()],
+            ..WebhooksConfig::default()
+        };
+        assert!(validate_url(&config, "http://localhost:3000/hook")
+            .await
+            .is_ok());
+        assert!(validate_url(&config, "http://[::1]/hook").await.is_ok());
+        assert!(validate_url(&config, "http://127.0.0.1/hook")
+            .await
+            .is_err());
+    }
+}
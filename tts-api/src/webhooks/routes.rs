@@ -0,0 +1,151 @@
+use crate::{
+    auth::{authenticate, Credentials},
+    config::Config,
+    error::AppError,
+    models::{
+        job::{Job, JobStatus},
+        webhook::{self, DeliveryStatus, WebhookAttempt, WebhookDelivery},
+    },
+    webhooks::notify,
+};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+#[derive(Serialize, Debug)]
+struct SecretResponse {
+    secret: String,
+}
+
+#[derive(Serialize, Debug)]
+struct DeliveryResponse {
+    id: i64,
+    url: String,
+    status: DeliveryStatus,
+    attempts: i32,
+    response_status: Option<i32>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    next_attempt_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    log: Vec<WebhookAttempt>,
+}
+
+impl DeliveryResponse {
+    fn new(delivery: WebhookDelivery, log: Vec<WebhookAttempt>) -> DeliveryResponse {
+        let status = delivery.status();
+        DeliveryResponse {
+            id: delivery.id,
+            url: delivery.url,
+            status,
+            attempts: delivery.attempts,
+            response_status: delivery.response_status,
+            error: delivery.error,
+            created_at: delivery.created_at,
+            next_attempt_at: match status {
+                DeliveryStatus::Pending => Some(delivery.next_attempt_at),
+                _ => None,
+            },
+            finished_at: delivery.finished_at,
+            log,
+        }
+    }
+}
+
+fn job_not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("Job not found.")
+}
+
+/// Returns the secret webhook payloads are signed with.
+#[get("/webhooks/secret")]
+async fn get_secret(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let secret = webhook::secret(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(SecretResponse { secret }))
+}
+
+#[post("/webhooks/secret")]
+async fn rotate_secret(
+    pool: web::Data<PgPool>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+
+    let secret = webhook::rotate_secret(pool, user.id).await?;
+    Ok(HttpResponse::Ok().json(SecretResponse { secret }))
+}
+
+/// Lists the webhook deliveries of a job with the log of their attempts.
+#[get("/jobs/{id}/deliveries")]
+async fn list_deliveries(
+    pool: web::Data<PgPool>,
+    id: web::Path<i64>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+    let job = match Job::get(pool, *id, user.id).await {
+        Ok(job) => job,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => return Ok(job_not_found()),
+        Err(e) => return Err(e),
+    };
+
+    let mut deliveries = Vec::new();
+    for delivery in WebhookDelivery::list(pool, job.id).await? {
+        let log = WebhookAttempt::list(pool, delivery.id).await?;
+        deliveries.push(DeliveryResponse::new(delivery, log));
+    }
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// Queues a new delivery of a finished job's notification.
+#[post("/jobs/{id}/redeliver")]
+async fn redeliver(
+    pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+    id: web::Path<i64>,
+    query: web::Query<Credentials>,
+) -> Result<HttpResponse, AppError> {
+    let pool = pool.get_ref();
+    let user = match authenticate(pool, query.id, &query.token).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e),
+    };
+    let job = match Job::get(pool, *id, user.id).await {
+        Ok(job) => job,
+        Err(AppError::DatabaseError(sqlx::Error::RowNotFound)) => return Ok(job_not_found()),
+        Err(e) => return Err(e),
+    };
+    if !matches!(job.status(), JobStatus::Done | JobStatus::Failed) {
+        return Ok(HttpResponse::Conflict().body("Job has not finished."));
+    }
+
+    match notify(pool, &config, &job).await? {
+        Some(delivery) => {
+            Ok(HttpResponse::Accepted().json(DeliveryResponse::new(delivery, vec![])))
+        }
+        None => Ok(HttpResponse::BadRequest().body("Job has no callback URL.")),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_secret);
+    cfg.service(rotate_secret);
+    cfg.service(list_deliveries);
+    cfg.service(redeliver);
+}